@@ -1,12 +1,113 @@
-use clap::Parser;
+use std::ops::RangeInclusive;
+
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    pub rom: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[arg(required = true)]
+    pub rom: Option<String>,
+
+    /// Print the cartridge header and exit
+    #[arg(long)]
+    pub info: bool,
 
     #[arg(short, long)]
     pub boot_rom: Option<String>,
 
+    /// The model to emulate, picked from the cartridge header by default
+    #[arg(short, long, value_enum)]
+    pub model: Option<Model>,
+
+    /// Load roms with a bad header checksum, like real hardware does
+    #[arg(long)]
+    pub ignore_checksum: bool,
+
+    /// Wait for another emulator to plug in a link cable on this port
+    #[arg(long, conflicts_with_all = ["link_connect"])]
+    pub link_listen: Option<u16>,
+
+    /// Listen for the link cable on this address instead of only this machine, for LAN play
+    #[arg(long, requires = "link_listen")]
+    pub link_bind: Option<String>,
+
+    /// Plug a link cable into another emulator listening at this address
+    #[arg(long)]
+    pub link_connect: Option<String>,
+
     #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
     pub zoom: u32,
+
+    /// Log every instruction to this file, in the format of gameboy-doctor
+    #[arg(long)]
+    pub trace: Option<String>,
+
+    /// Only log instructions at these addresses, like 4000-7fff
+    #[arg(long, requires = "trace", value_parser = hex_range)]
+    pub trace_pc: Option<RangeInclusive<u16>>,
+
+    /// Only log instructions in this rom bank, in hex
+    #[arg(long, requires = "trace", value_parser = hex)]
+    pub trace_bank: Option<u16>,
+
+    /// Only log the last instructions before the emulator crashes, this many of them
+    #[arg(long, requires = "trace")]
+    pub trace_last: Option<usize>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Disassemble a rom instead of running it
+    Disasm(Disasm),
+}
+
+#[derive(clap::Args)]
+pub struct Disasm {
+    pub rom: String,
+
+    /// A RGBDS or no$gmb symbol file, the rom with a .sym extension is used if there is one
+    #[arg(long)]
+    pub sym: Option<String>,
+
+    /// Only disassemble this bank, in hex
+    #[arg(short, long, value_parser = hex)]
+    pub bank: Option<u16>,
+
+    /// Where to start in each bank, in hex
+    #[arg(long, value_parser = hex)]
+    pub start: Option<u16>,
+
+    /// Where to stop in each bank, in hex
+    #[arg(long, value_parser = hex)]
+    pub end: Option<u16>,
+}
+
+fn hex(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s), 16)
+        .map_err(|_| format!("{s} is not a hex number"))
+}
+
+fn hex_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = s.split_once('-').ok_or_else(|| format!("expected start-end, not {s}"))?;
+    Ok(hex(start)?..=hex(end)?)
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Model {
+    Dmg,
+    Cgb,
+    Sgb,
+}
+
+impl From<Model> for gb::Model {
+    fn from(m: Model) -> Self {
+        match m {
+            Model::Dmg => Self::Dmg,
+            Model::Cgb => Self::Cgb,
+            Model::Sgb => Self::Sgb,
+        }
+    }
 }
@@ -0,0 +1,298 @@
+//! The debugger prompt, opened with the backtick key. The emulator thread pauses while it is
+//! open and runs what it asks for, sending back what to print.
+
+use std::{io::Write, sync::{mpsc::Sender, Arc}};
+
+use gb::{debug::*, disasm::{self, Symbols}};
+use termion::event::Key;
+
+pub type Command = Box<dyn FnOnce(&mut gb::Gameboy) -> String + Send>;
+
+pub enum Msg {
+    /// Stops running, the emulator answers with where it stopped.
+    Pause,
+    Resume(Step),
+    /// Runs something on the paused emulator, which answers with what it returns.
+    Run(Command),
+}
+
+const HELP: &str = "\
+c                       continue
+s                       step one instruction
+n                       step over calls
+finish                  run until the function returns
+b [bank:]addr [if r=v]  break at an address
+watch addr [r|w|rw]     break after an access, writes by default
+cond r=v                break when a register starts having a value
+info                    list breakpoints, watchpoints and conditions
+d [b|w|c n]             delete one of them, or all
+regs                    show the registers
+x addr [len]            show memory
+dis [addr] [count]      disassemble, from pc by default";
+
+pub struct Prompt {
+    pub open: bool,
+    line: String,
+    emu: Sender<Msg>,
+    symbols: Arc<Symbols>,
+}
+
+impl Prompt {
+    pub fn new(emu: Sender<Msg>, symbols: Arc<Symbols>) -> Self {
+        Self { open: false, line: String::new(), emu, symbols }
+    }
+
+    pub fn pause(&self) {
+        self.emu.send(Msg::Pause).unwrap();
+    }
+
+    /// Prints what the emulator sent back, opening the prompt if it stopped on its own.
+    pub fn show(&mut self, text: &str) {
+        if !self.open {
+            self.open = true;
+            print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        }
+
+        for l in text.lines() { print!("{l}\r\n"); }
+        self.print_line();
+    }
+
+    pub fn key(&mut self, k: Key) {
+        match k {
+            Key::Char('\n') => {
+                print!("\r\n");
+                let line = core::mem::take(&mut self.line);
+
+                match self.exec(line.trim()) {
+                    Ok(true) => {},
+                    Ok(false) => self.print_line(),
+                    Err(e) => self.show(&e),
+                }
+            },
+            Key::Char('`') => self.resume(Step::Continue),
+            Key::Char(c) => {
+                self.line.push(c);
+                print!("{c}");
+            },
+            Key::Backspace if self.line.pop().is_some() => print!("\x08 \x08"),
+            _ => {},
+        }
+
+        std::io::stdout().flush().unwrap();
+    }
+
+    fn print_line(&self) {
+        print!("(gwdb) {}", self.line);
+        std::io::stdout().flush().unwrap();
+    }
+
+    fn resume(&mut self, step: Step) {
+        self.emu.send(Msg::Resume(step)).unwrap();
+
+        if step == Step::Continue {
+            self.open = false;
+            print!("\x1b[?25l\x1b[?1049h\x1b[2J");
+        }
+    }
+
+    fn run(&self, f: impl FnOnce(&mut gb::Gameboy) -> String + Send + 'static) {
+        self.emu.send(Msg::Run(Box::new(f))).unwrap();
+    }
+
+    /// Runs a command, returns whether the emulator will answer it.
+    fn exec(&mut self, line: &str) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        let Some(cmd) = words.next() else { return Ok(false) };
+        let args = words.collect::<Vec<_>>();
+
+        match (cmd, args.as_slice()) {
+            ("c" | "continue", []) => self.resume(Step::Continue),
+            ("s" | "step", []) => self.resume(Step::Into),
+            ("n" | "next", []) => self.resume(Step::Over),
+            ("finish", []) => self.resume(Step::Out),
+            ("b" | "break", [at, rest @ ..]) => {
+                let (bank, pc) = match at.split_once(':') {
+                    Some((bank, pc)) => (Some(number(bank)? as usize), number(pc)?),
+                    None => (None, number(at)?),
+                };
+
+                let cond = match rest {
+                    [] => None,
+                    ["if", cond] => Some(condition(cond)?),
+                    _ => return Err(format!("expected `if r=v` after the address, not {}", rest.join(" "))),
+                };
+
+                self.run(move |gb| {
+                    let bps = &mut debugger(gb).breakpoints;
+                    bps.push(Breakpoint { pc, bank, cond });
+                    format!("b{} at {}", bps.len() - 1, describe_bp(bps.last().unwrap()))
+                });
+            },
+            ("watch", [addr, rest @ ..]) => {
+                let addr = number(addr)?;
+                let (read, write) = match rest {
+                    [] | ["w"] => (false, true),
+                    ["r"] => (true, false),
+                    ["rw"] => (true, true),
+                    _ => return Err(format!("expected r, w or rw, not {}", rest.join(" "))),
+                };
+
+                self.run(move |gb| {
+                    let wps = &mut debugger(gb).watchpoints;
+                    wps.push(Watchpoint { addr, read, write });
+                    format!("w{} at {}", wps.len() - 1, describe_wp(wps.last().unwrap()))
+                });
+            },
+            ("cond", [cond]) => {
+                let cond = condition(cond)?;
+
+                self.run(move |gb| {
+                    let conds = &mut debugger(gb).conditions;
+                    conds.push(cond);
+                    format!("c{} when {}={:x}", conds.len() - 1, cond.reg, cond.value)
+                });
+            },
+            ("info", []) => self.run(|gb| {
+                let d = debugger(gb);
+                let mut out = String::new();
+
+                for (i, b) in d.breakpoints.iter().enumerate() { out += &format!("b{i} at {}\n", describe_bp(b)); }
+                for (i, w) in d.watchpoints.iter().enumerate() { out += &format!("w{i} at {}\n", describe_wp(w)); }
+                for (i, c) in d.conditions.iter().enumerate() { out += &format!("c{i} when {}={:x}\n", c.reg, c.value); }
+
+                if out.is_empty() { "nothing set".into() } else { out }
+            }),
+            ("d" | "delete", []) => self.run(|gb| {
+                let d = debugger(gb);
+                d.breakpoints.clear();
+                d.watchpoints.clear();
+                d.conditions.clear();
+                "deleted everything".into()
+            }),
+            ("d" | "delete", [kind, n]) => {
+                let kind = kind.chars().next().unwrap();
+                let n = n.parse::<usize>().map_err(|_| format!("{n} is not a number"))?;
+
+                self.run(move |gb| {
+                    let d = debugger(gb);
+                    let len = match kind {
+                        'b' => d.breakpoints.len(),
+                        'w' => d.watchpoints.len(),
+                        'c' => d.conditions.len(),
+                        _ => return format!("{kind} is not b, w or c"),
+                    };
+
+                    if n >= len { return format!("there is no {kind}{n}"); }
+
+                    match kind {
+                        'b' => { d.breakpoints.remove(n); },
+                        'w' => { d.watchpoints.remove(n); },
+                        _ => { d.conditions.remove(n); },
+                    }
+
+                    format!("deleted {kind}{n}")
+                });
+            },
+            ("regs", []) => {
+                let symbols = self.symbols.clone();
+                self.run(move |gb| describe(gb, &symbols));
+            },
+            ("dis", rest) if rest.len() <= 2 => {
+                let at = rest.first().map(|a| number(a)).transpose()?;
+                let count = rest.get(1).map(|n| number(n)).transpose()?.unwrap_or(8);
+                let symbols = self.symbols.clone();
+
+                self.run(move |gb| {
+                    let mut pc = at.unwrap_or_else(|| Reg::Pc.get(&gb.cpu_state()));
+                    let mut out = String::new();
+
+                    for _ in 0..count {
+                        let (line, len) = disassemble(gb, pc, &symbols);
+                        out += &line;
+                        out += "\n";
+                        pc = pc.wrapping_add(len);
+                    }
+
+                    out
+                });
+            },
+            ("x", [addr, rest @ ..]) => {
+                let addr = number(addr)?;
+                let len = match rest {
+                    [] => 16,
+                    [len] => number(len)?,
+                    _ => return Err("expected an address and a length".into()),
+                };
+
+                self.run(move |gb| {
+                    (0..len).step_by(16).map(|row| {
+                        let at = addr.wrapping_add(row);
+                        let bytes = (0..16.min(len - row))
+                            .map(|i| format!("{:02x}", gb.peek(at.wrapping_add(i))))
+                            .collect::<Vec<_>>();
+
+                        format!("{at:04x}: {}\n", bytes.join(" "))
+                    }).collect()
+                });
+            },
+            ("help" | "h", []) => self.show(HELP),
+            _ => return Err(format!("unknown command `{line}`, try help")),
+        }
+
+        Ok(true)
+    }
+}
+
+/// The registers and the instruction that runs next.
+pub fn describe(gb: &mut gb::Gameboy, symbols: &Symbols) -> String {
+    let mut s = gb.cpu_state();
+    s.pc = Reg::Pc.get(&s);
+    format!("{s}\n{}", disassemble(gb, s.pc, symbols).0)
+}
+
+/// One line of disassembly, with the label above it if there is one, and its length.
+fn disassemble(gb: &mut gb::Gameboy, pc: u16, symbols: &Symbols) -> (String, u16) {
+    let bank = gb.bank(pc).unwrap_or(0);
+    let inst = disasm::decode(pc, |a| gb.peek(a));
+
+    let label = symbols.get(bank, pc).map_or_else(String::new, |l| format!("{l}:\n"));
+    (format!("{label}{pc:04x}  {}", inst.format(Some(symbols), bank)), inst.len as u16)
+}
+
+fn describe_bp(b: &Breakpoint) -> String {
+    let mut out = match b.bank {
+        Some(bank) => format!("{bank:02x}:{:04x}", b.pc),
+        None => format!("{:04x}", b.pc),
+    };
+
+    if let Some(c) = b.cond { out += &format!(" if {}={:x}", c.reg, c.value); }
+    out
+}
+
+fn describe_wp(w: &Watchpoint) -> String {
+    let kind = match (w.read, w.write) {
+        (true, true) => "rw",
+        (true, false) => "r",
+        _ => "w",
+    };
+
+    format!("{:04x} {kind}", w.addr)
+}
+
+fn debugger<'a>(gb: &'a mut gb::Gameboy) -> &'a mut Debugger {
+    if gb.debugger().is_none() { gb.set_debugger(Some(Debugger::new())); }
+    gb.debugger().unwrap()
+}
+
+/// Numbers are in hex, with or without a `$` or `0x` in front.
+fn number(s: &str) -> Result<u16, String> {
+    let hex = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("{s} is not a hex number"))
+}
+
+fn condition(s: &str) -> Result<Condition, String> {
+    let (reg, value) = s.split_once('=').ok_or_else(|| format!("expected r=v, not {s}"))?;
+    let reg = reg.parse().map_err(|_| format!("{reg} is not a register"))?;
+
+    Ok(Condition { reg, value: number(value)? })
+}
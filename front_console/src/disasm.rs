@@ -0,0 +1,56 @@
+//! The `disasm` subcommand, which goes through each bank of a rom one instruction after
+//! another, so data comes out as nonsense instructions.
+
+use gb::disasm::{self, Symbols};
+
+use crate::args;
+
+pub fn run(args: &args::Disasm) {
+    let rom = std::fs::read(&args.rom).unwrap_or_else(|e| {
+        eprintln!("Failed to read {}: {e}", args.rom);
+        std::process::exit(1);
+    });
+
+    let symbols = load_symbols(args.sym.as_deref(), &args.rom);
+    let banks = match args.bank {
+        Some(bank) => bank as usize..bank as usize + 1,
+        None => 0..rom.len().div_ceil(0x4000),
+    };
+
+    for bank in banks {
+        let base = if bank == 0 { 0x0000 } else { 0x4000 };
+        let read = |a: u16| {
+            (a as usize).checked_sub(base).filter(|&o| o < 0x4000).and_then(|o| rom.get(bank * 0x4000 + o)).copied().unwrap_or(0xff)
+        };
+
+        let start = args.start.map_or(base, |s| s as usize).max(base);
+        let end = args.end.map_or(base + 0x3fff, |e| e as usize).min(base + 0x3fff);
+
+        let mut pc = start;
+        while pc <= end {
+            if let Some(label) = symbols.get(bank, pc as u16) { println!("{label}:"); }
+
+            let inst = disasm::decode(pc as u16, read);
+            let bytes = (0..inst.len as u16)
+                .map(|i| format!("{:02x}", read((pc as u16).wrapping_add(i))))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            println!("{bank:02x}:{pc:04x}  {bytes:<8}  {}", inst.format(Some(&symbols), bank));
+            pc += inst.len as usize;
+        }
+    }
+}
+
+/// Loads `path`, or the rom with a .sym extension if it exists.
+pub fn load_symbols(path: Option<&str>, rom: &str) -> Symbols {
+    let fallback = std::path::Path::new(rom).with_extension("sym");
+
+    match path {
+        Some(path) => Symbols::parse(&std::fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read {path}: {e}");
+            std::process::exit(1);
+        })),
+        None => std::fs::read_to_string(fallback).map(|s| Symbols::parse(&s)).unwrap_or_default(),
+    }
+}
@@ -1,15 +1,34 @@
-use std::{sync::{atomic::*, *}, thread};
+use std::{sync::{atomic::*, mpsc, *}, thread, time::Duration};
 
 use clap::Parser;
 use termion::{input::TermRead, raw::IntoRawMode};
 
 mod args;
+mod debug;
+mod disasm;
 
 const BURST_CYCLES: usize = gb::CLOCK_HZ / 60;
 
 fn main() {
     let args = args::Args::parse();
-    let (gb_fb, keys) = init(&args);
+
+    if let Some(args::Command::Disasm(d)) = &args.command {
+        disasm::run(d);
+        return;
+    }
+
+    let rom = args.rom.clone().unwrap();
+    if args.info {
+        println!("{}", read_header(&rom));
+        return;
+    }
+
+    let cable = connect_link(&args);
+    let (debug_tx, debug_rx) = mpsc::channel();
+    let (out_tx, out_rx) = mpsc::channel();
+    let symbols = Arc::new(disasm::load_symbols(None, &rom));
+    let (gb_fb, keys, model) = init(&args, &rom, cable, debug_rx, out_tx, symbols.clone());
+    let mut prompt = debug::Prompt::new(debug_tx, symbols);
 
     let raw = termion::get_tty().unwrap().into_raw_mode().unwrap();
     raw.activate_raw_mode().unwrap();
@@ -26,6 +45,21 @@ fn main() {
     let tmyh = tmy / 2;
 
     for frame in 0.. {
+        for out in out_rx.try_iter() { prompt.show(&out); }
+
+        if prompt.open {
+            for k in in_keys.by_ref() {
+                match k {
+                    Ok(termion::event::Key::Esc) => quit(&raw),
+                    Ok(k) => prompt.key(k),
+                    Err(_) => {},
+                }
+            }
+
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+
         print!("\x1b[2K\x1b[H");
 
         for my in 0..144 / tmy {
@@ -36,14 +70,14 @@ fn main() {
                 let mut l = 0;
                 for sy in 0..tmyh {
                     for sx in 0..tmx { // NOTE: we do not lock for entire frame here bc console slow ass
-                        l += gb_fb.lock().unwrap()[(y + sy) * 160 + x + sx] as usize & 3;
+                        l += shade(gb_fb.lock().unwrap()[(y + sy) * 160 + x + sx], model);
                     }
                 }
 
                 let mut u = 0;
                 for sy in 0..tmyh {
                     for sx in 0..tmx {
-                        u += gb_fb.lock().unwrap()[(y + sy + tmyh) * 160 + x + sx] as usize & 3;
+                        u += shade(gb_fb.lock().unwrap()[(y + sy + tmyh) * 160 + x + sx], model);
                     }
                 }
 
@@ -98,16 +132,9 @@ fn main() {
                 Ok(Key::Char('i')) => sb = 1,
                 Ok(Key::Char('v')) => sl = 1,
                 Ok(Key::Char('b')) => st = 1,
-                Ok(Key::Esc) => {
-                    STOP.store(true, Ordering::Relaxed);
-                    while STOP.load(Ordering::Relaxed) { std::hint::spin_loop() }
-
-                    println!("\x1b[0m\x1b[?25h\x1b[?1049l");
-                    raw.suspend_raw_mode().unwrap();
-
-                    std::process::exit(0);
-                },
+                Ok(Key::Esc) => quit(&raw),
                 Ok(Key::Char('\n')) => { BURST.fetch_xor(true, Ordering::Relaxed); },
+                Ok(Key::Char('`')) => prompt.pause(),
                 _ => {},
             }
         }
@@ -144,17 +171,65 @@ fn main() {
     ];
 }
 
+fn quit(raw: &termion::raw::RawTerminal<std::fs::File>) -> ! {
+    STOP.store(true, Ordering::Relaxed);
+    while STOP.load(Ordering::Relaxed) { std::hint::spin_loop() }
+
+    println!("\x1b[0m\x1b[?25h\x1b[?1049l");
+    raw.suspend_raw_mode().unwrap();
+
+    std::process::exit(0);
+}
+
+/// The terminal only has four grays, so colors go by how bright they are.
+fn shade(p: u16, model: gb::Model) -> usize {
+    match model {
+        gb::Model::Dmg | gb::Model::Sgb => p as usize & 3,
+        gb::Model::Cgb => {
+            let sum = (p & 0x1f) + ((p >> 5) & 0x1f) + ((p >> 10) & 0x1f);
+            3 - (sum as usize * 4 / 94)
+        },
+    }
+}
+
 static BURST: AtomicBool = AtomicBool::new(false);
 static STOP: AtomicBool = AtomicBool::new(false);
 
-fn run_emu(mut gb: gb::Gameboy) {
+fn run_emu(mut gb: gb::Gameboy, debug: mpsc::Receiver<debug::Msg>, out: mpsc::Sender<String>, symbols: &gb::disasm::Symbols) {
     use std::time::*;
 
     let mut dur = Duration::new(0, 0);
+    let mut paused = false;
+    gb.set_debugger(Some(gb::debug::Debugger::new()));
 
     while !STOP.load(Ordering::Relaxed) {
+        let msg = if paused {
+            debug.recv_timeout(Duration::from_millis(10)).ok()
+        } else {
+            debug.try_recv().ok()
+        };
+
+        match msg {
+            Some(debug::Msg::Pause) => {
+                paused = true;
+                out.send(format!("paused\n{}", debug::describe(&mut gb, symbols))).unwrap();
+            },
+            Some(debug::Msg::Resume(step)) => {
+                paused = false;
+                dur = Duration::new(0, 0);
+                gb.resume(step);
+            },
+            Some(debug::Msg::Run(f)) => out.send(f(&mut gb)).unwrap(),
+            None => {},
+        }
+
+        if paused { continue; }
+
         let start = Instant::now();
-        for _ in 0..BURST_CYCLES { gb.step(); }
+        if let Some(stop) = gb.run_until_break(BURST_CYCLES) {
+            paused = true;
+            out.send(format!("{stop}\n{}", debug::describe(&mut gb, symbols))).unwrap();
+        }
 
         if !BURST.load(Ordering::Relaxed) {
             dur += Duration::from_secs_f64(BURST_CYCLES as f64 / gb::CLOCK_HZ as f64);
@@ -168,14 +243,76 @@ fn run_emu(mut gb: gb::Gameboy) {
     }
 }
 
-fn init(args: &args::Args) -> (Arc<Mutex<[u8]>>, Arc<AtomicU8>) {
-    let rom = std::fs::read(&args.rom).unwrap();
+fn read_header(path: &str) -> gb::cartridge::Header {
+    let rom = std::fs::read(path).unwrap();
+    gb::cartridge::Header::parse(&rom).unwrap_or_else(|e| {
+        eprintln!("Failed to load {path}: {e}");
+        std::process::exit(1);
+    })
+}
+
+fn connect_link(args: &args::Args) -> Option<gb::link::TcpCable> {
+    let cable = match (args.link_listen, &args.link_connect) {
+        (Some(port), _) => {
+            println!("Waiting for a link cable on port {port}");
+            match &args.link_bind {
+                Some(addr) => gb::link::TcpCable::listen_on((addr.as_str(), port)),
+                None => gb::link::TcpCable::listen(port),
+            }
+        },
+        (None, Some(addr)) => gb::link::TcpCable::connect(addr.as_str()),
+        (None, None) => return None,
+    };
+
+    Some(cable.unwrap_or_else(|e| {
+        eprintln!("Failed to connect the link cable: {e}");
+        std::process::exit(1);
+    }))
+}
+
+fn open_trace(args: &args::Args, path: &str) -> gb::trace::Tracer {
+    let file = std::fs::File::create(path).unwrap_or_else(|e| {
+        eprintln!("Failed to create {path}: {e}");
+        std::process::exit(1);
+    });
+
+    let out = std::io::BufWriter::new(file);
+    let mut tracer = match args.trace_last {
+        Some(len) => gb::trace::Tracer::ring(out, len),
+        None => gb::trace::Tracer::new(out),
+    };
+
+    tracer.pc = args.trace_pc.clone();
+    tracer.bank = args.trace_bank.map(|b| b as usize);
+    tracer
+}
+
+fn init(
+    args: &args::Args,
+    path: &str,
+    cable: Option<gb::link::TcpCable>,
+    debug: mpsc::Receiver<debug::Msg>,
+    out: mpsc::Sender<String>,
+    symbols: Arc<gb::disasm::Symbols>,
+) -> (Arc<Mutex<[u16]>>, Arc<AtomicU8>, gb::Model) {
+    let rom = std::fs::read(path).unwrap();
     let br = args.boot_rom.as_ref().map(|b| std::fs::read(b).unwrap().into());
 
     let gb_fb = Mutex::new([0; 160 * 144]).into();
     let keys = Arc::new(AtomicU8::new(0x00));
 
-    let mapper = gb::mapper::Mapper::from_bin(&rom);
+    let mapper = if args.ignore_checksum {
+        gb::mapper::Mapper::from_bin_permissive(&rom)
+    } else {
+        gb::mapper::Mapper::from_bin(&rom)
+    };
+    let mapper = mapper.unwrap_or_else(|e| {
+        eprintln!("Failed to load {path}: {e}");
+        std::process::exit(1);
+    });
+
+    let model = args.model.map_or_else(|| gb::Model::from_header(&read_header(path)), Into::into);
+    let tracer = args.trace.as_ref().map(|path| open_trace(args, path));
 
     {
         let gb_fb = Arc::clone(&gb_fb);
@@ -209,7 +346,7 @@ fn init(args: &args::Args) -> (Arc<Mutex<[u8]>>, Arc<AtomicU8>) {
                 (wav, file_size_idx, data_size_idx)
             };
 
-            let gb = gb::Gameboy::new(mapper, br, gb_fb, Box::new(|buf| {
+            let mut gb = gb::Gameboy::new(model, mapper, br, gb_fb, Box::new(|buf| {
                 #[cfg(feature = "audio")] {
                     if sink.len() > 3 {
                         for _ in 0..sink.len() { sink.skip_one(); }
@@ -222,7 +359,13 @@ fn init(args: &args::Args) -> (Arc<Mutex<[u8]>>, Arc<AtomicU8>) {
                 wav.extend(buf.iter().flat_map(|v| v.to_le_bytes()));
             }), keys);
 
-            run_emu(gb);
+            if let Some(cable) = cable {
+                gb.set_link_cable(Some(Box::new(cable)));
+            }
+
+            gb.set_tracer(tracer);
+
+            run_emu(gb, debug, out, &symbols);
 
             #[cfg(feature = "wav")] {
                 let wav_len = wav.len();
@@ -235,6 +378,6 @@ fn init(args: &args::Args) -> (Arc<Mutex<[u8]>>, Arc<AtomicU8>) {
         });
     }
 
-    (gb_fb, keys)
+    (gb_fb, keys, model)
 }
 
@@ -1,18 +1,112 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 #[derive(Parser)]
 pub struct Args {
     pub rom: String,
 
+    /// Print the cartridge header and exit
+    #[arg(long)]
+    pub info: bool,
+
     #[arg(short, long)]
     pub boot_rom: Option<String>,
 
+    /// The model to emulate, picked from the cartridge header by default
+    #[arg(short, long, value_enum)]
+    pub model: Option<Model>,
+
+    /// Color monochrome games like a CGB does, auto picks the colors from the title like its boot
+    /// rom
+    #[arg(long, value_enum, conflicts_with = "model")]
+    pub colorize: Option<Colorize>,
+
+    /// Load roms with a bad header checksum, like real hardware does
+    #[arg(long)]
+    pub ignore_checksum: bool,
+
+    /// Wait for another emulator to plug in a link cable on this port
+    #[arg(long, conflicts_with_all = ["link_connect", "link"])]
+    pub link_listen: Option<u16>,
+
+    /// Listen for the link cable on this address instead of only this machine, for LAN play
+    #[arg(long, requires = "link_listen")]
+    pub link_bind: Option<String>,
+
+    /// Plug a link cable into another emulator listening at this address
+    #[arg(long, conflicts_with = "link")]
+    pub link_connect: Option<String>,
+
     #[arg(short, long)]
     pub save_file: Option<String>,
 
+    /// Run a second rom next to the first with a link cable between them, controlled with the
+    /// arrow keys, period, comma, right shift and slash
+    #[arg(long)]
+    pub link: Option<String>,
+
+    /// Let a debugger attach over the GDB remote protocol on this port of localhost
+    #[arg(long, conflicts_with = "link")]
+    pub gdb: Option<u16>,
+
     #[arg(long, hide = true)]
     pub waifu: bool,
 
     // #[arg(long)]
     // pub run_for: Option<usize>,
 }
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Model {
+    Dmg,
+    Cgb,
+    Sgb,
+}
+
+impl From<Model> for gb::Model {
+    fn from(m: Model) -> Self {
+        match m {
+            Model::Dmg => Self::Dmg,
+            Model::Cgb => Self::Cgb,
+            Model::Sgb => Self::Sgb,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Colorize {
+    Auto,
+    Brown,
+    Red,
+    DarkBrown,
+    Blue,
+    DarkBlue,
+    Gray,
+    Pastel,
+    Orange,
+    Yellow,
+    Green,
+    DarkGreen,
+    Inverted,
+}
+
+impl Colorize {
+    pub fn preset(self) -> Option<gb::compat::Preset> {
+        use gb::compat::Preset;
+
+        Some(match self {
+            Self::Auto => return None,
+            Self::Brown => Preset::Brown,
+            Self::Red => Preset::Red,
+            Self::DarkBrown => Preset::DarkBrown,
+            Self::Blue => Preset::Blue,
+            Self::DarkBlue => Preset::DarkBlue,
+            Self::Gray => Preset::Gray,
+            Self::Pastel => Preset::Pastel,
+            Self::Orange => Preset::Orange,
+            Self::Yellow => Preset::Yellow,
+            Self::Green => Preset::Green,
+            Self::DarkGreen => Preset::DarkGreen,
+            Self::Inverted => Preset::Inverted,
+        })
+    }
+}
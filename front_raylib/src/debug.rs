@@ -0,0 +1,433 @@
+//! Debug windows next to the screen, F1 to F5 open the tile data, the tile maps, the objects,
+//! the palettes and a memory editor. Tab switches between what a window can show.
+//!
+//! The emulator thread copies what they show out of the first Game Boy after every burst, but
+//! only while one is open.
+
+use std::sync::{atomic::*, Arc, Mutex};
+
+use raylib::prelude::*;
+
+/// What the windows show, taken between bursts.
+struct Snapshot {
+    /// Both banks of tile data, 384 tiles each.
+    tiles: Vec<[[u8; 8]; 8]>,
+    /// The two tile maps, and their attributes in cgb mode.
+    maps: [u8; 0x800],
+    attrs: [u8; 0x800],
+    oam: [u8; 0xa0],
+    regs: gb::ppu::Registers,
+    /// Each palette as what the framebuffer gets for its colors.
+    bg: [[u16; 4]; 8],
+    obj: [[u16; 4]; 8],
+    cgb: bool,
+    cgb_mode: bool,
+    /// The page the memory editor is on.
+    memory: [u8; 0x100],
+    memory_at: u16,
+}
+
+impl Snapshot {
+    fn take(gb: &mut gb::Gameboy, memory_at: u16) -> Self {
+        let memory = core::array::from_fn(|i| gb.peek(memory_at.wrapping_add(i as u16)));
+        let ppu = gb.ppu();
+        let cgb_mode = ppu.cgb_mode();
+
+        Self {
+            tiles: (0..2).flat_map(|bank| (0..384).map(move |i| ppu.tile(bank, i))).collect(),
+            maps: ppu.vram()[0x1800..0x2000].try_into().unwrap(),
+            attrs: if cgb_mode { ppu.vram()[0x3800..0x4000].try_into().unwrap() } else { [0; 0x800] },
+            oam: *ppu.oam(),
+            regs: ppu.registers(),
+            bg: core::array::from_fn(|p| core::array::from_fn(|c| ppu.color(false, p as u8, c as u8))),
+            obj: core::array::from_fn(|p| core::array::from_fn(|c| ppu.color(true, p as u8, c as u8))),
+            cgb: ppu.cgb(),
+            cgb_mode,
+            memory,
+            memory_at,
+        }
+    }
+
+    fn rgba(&self, c: u16) -> [u8; 4] {
+        crate::rgba(c, self.cgb)
+    }
+}
+
+/// Shared between the windows and the emulator thread.
+#[derive(Default)]
+pub struct Inspector {
+    /// Set while a window is open, nothing is copied otherwise.
+    open: AtomicBool,
+    /// The first of the bytes the memory editor shows.
+    memory_at: AtomicU16,
+    snapshot: Mutex<Option<Snapshot>>,
+    /// Writes from the memory editor, waiting for the emulator thread.
+    pokes: Mutex<Vec<(u16, u8)>>,
+}
+
+impl Inspector {
+    /// Called by the emulator thread between bursts.
+    pub fn update(&self, gb: &mut gb::Gameboy) {
+        for (a, d) in self.pokes.lock().unwrap().drain(..) { gb.poke(a, d); }
+
+        if !self.open.load(Ordering::Relaxed) { return; }
+
+        let snapshot = Snapshot::take(gb, self.memory_at.load(Ordering::Relaxed));
+        *self.snapshot.lock().unwrap() = Some(snapshot);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum View {
+    Tiles,
+    Maps,
+    Objects,
+    Palettes,
+    Memory,
+}
+
+const VIEWS: [(KeyboardKey, View); 5] = [
+    (KeyboardKey::KEY_F1, View::Tiles),
+    (KeyboardKey::KEY_F2, View::Maps),
+    (KeyboardKey::KEY_F3, View::Objects),
+    (KeyboardKey::KEY_F4, View::Palettes),
+    (KeyboardKey::KEY_F5, View::Memory),
+];
+
+const BACKGROUND: [u8; 4] = [0x16, 0x2a, 0x33, 0xff];
+const OVERLAY: [u8; 4] = [0xf5, 0x00, 0x00, 0xff];
+const CURSOR: Color = Color::new(0x2f, 0x69, 0x57, 0xff);
+const TEXT: f32 = 16.0;
+
+pub struct Windows {
+    inspector: Arc<Inspector>,
+    view: Option<View>,
+
+    /// Tiles through 0x8800 addressing instead of 0x8000.
+    signed: bool,
+    bank: usize,
+    /// Which tile map is shown, the one at 0x9c00 if set.
+    high_map: bool,
+    /// The byte the memory editor is on, and the first digit typed into it.
+    cursor: u16,
+    digit: Option<u8>,
+
+    tiles: RenderTexture2D,
+    map: RenderTexture2D,
+    objects: RenderTexture2D,
+    pixels: Vec<u8>,
+}
+
+impl Windows {
+    pub fn new(rl: &mut RaylibHandle, thread: &RaylibThread, inspector: Arc<Inspector>) -> Self {
+        Self {
+            inspector,
+            view: None,
+
+            signed: false,
+            bank: 0,
+            high_map: false,
+            cursor: 0xc000,
+            digit: None,
+
+            tiles: rl.load_render_texture(thread, 128, 128).unwrap(),
+            map: rl.load_render_texture(thread, 256, 256).unwrap(),
+            objects: rl.load_render_texture(thread, 64, 80).unwrap(),
+            pixels: Vec::new(),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.view.is_some()
+    }
+
+    /// Handles the keys for the windows, returns whether the keyboard is taken for typing into
+    /// the memory editor.
+    pub fn input(&mut self, rl: &mut RaylibHandle) -> bool {
+        for (key, view) in VIEWS {
+            if rl.is_key_pressed(key) {
+                self.view = if self.view == Some(view) { None } else { Some(view) };
+            }
+        }
+
+        self.inspector.open.store(self.view.is_some(), Ordering::Relaxed);
+        let cgb = self.inspector.snapshot.lock().unwrap().as_ref().is_some_and(|s| s.cgb);
+
+        match self.view {
+            Some(View::Tiles) if rl.is_key_pressed(KeyboardKey::KEY_TAB) => {
+                // 0x8000 and 0x8800 addressing of each bank in turn
+                self.signed = !self.signed;
+                if !self.signed && cgb { self.bank ^= 1; }
+            },
+            Some(View::Maps) if rl.is_key_pressed(KeyboardKey::KEY_TAB) => self.high_map = !self.high_map,
+            Some(View::Memory) => {
+                self.edit(rl);
+                return true;
+            },
+            _ => {},
+        }
+
+        if !cgb { self.bank = 0; }
+        false
+    }
+
+    fn edit(&mut self, rl: &mut RaylibHandle) {
+        let moves = [
+            (KeyboardKey::KEY_LEFT, 0xffff),
+            (KeyboardKey::KEY_RIGHT, 0x0001),
+            (KeyboardKey::KEY_UP, 0xfff0),
+            (KeyboardKey::KEY_DOWN, 0x0010),
+            (KeyboardKey::KEY_PAGE_UP, 0xff00),
+            (KeyboardKey::KEY_PAGE_DOWN, 0x0100),
+        ];
+
+        for (key, by) in moves {
+            if rl.is_key_pressed(key) {
+                self.cursor = self.cursor.wrapping_add(by);
+                self.digit = None;
+            }
+        }
+
+        let wheel = rl.get_mouse_wheel_move();
+        if wheel != 0.0 { self.cursor = self.cursor.wrapping_add_signed(-(wheel as i16) * 0x10); }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE) { self.digit = None; }
+
+        while let Some(c) = rl.get_char_pressed() {
+            let Some(v) = c.to_digit(16) else { continue };
+
+            match self.digit.take() {
+                None => self.digit = Some(v as u8),
+                Some(hi) => {
+                    self.inspector.pokes.lock().unwrap().push((self.cursor, (hi << 4) | v as u8));
+                    self.cursor = self.cursor.wrapping_add(1);
+                },
+            }
+        }
+
+        self.inspector.memory_at.store(self.cursor & 0xff00, Ordering::Relaxed);
+    }
+
+    /// Draws the open window inside `area`.
+    pub fn draw(&mut self, d: &mut RaylibDrawHandle, font: &Font, area: Rectangle) {
+        let Some(view) = self.view else { return };
+
+        let inspector = Arc::clone(&self.inspector);
+        let snapshot = inspector.snapshot.lock().unwrap();
+        let Some(s) = snapshot.as_ref() else { return };
+
+        let mut text = Text { font, x: area.x + 8.0, y: area.y + 8.0 };
+
+        match view {
+            View::Tiles => {
+                let at = if self.signed { 0x8800 } else { 0x8000 };
+                text.line(d, &format!("F1 tiles at {at:04x}, bank {} (tab)", self.bank));
+
+                self.pixels.resize(128 * 128 * 4, 0);
+                for n in 0..=255u8 {
+                    let tile = &s.tiles[self.bank * 384 + gb::ppu::tile_index(n, self.signed)];
+                    let (tx, ty) = (n as usize % 16 * 8, n as usize / 16 * 8);
+
+                    for (y, row) in tile.iter().enumerate() {
+                        for (x, c) in row.iter().enumerate() {
+                            put(&mut self.pixels, 128, tx + x, ty + y, crate::rgba(*c as u16, false));
+                        }
+                    }
+                }
+
+                self.tiles.update_texture(&self.pixels);
+                text.image(d, &self.tiles, 128.0, 128.0, &area);
+            },
+            View::Maps => {
+                let r = s.regs;
+                let bg = if r.lcdc & 0x08 != 0 { "9c00" } else { "9800" };
+                let win = if r.lcdc & 0x40 != 0 { "9c00" } else { "9800" };
+                let at = if self.high_map { "9c00" } else { "9800" };
+                text.line(d, &format!("F2 tile map at {at} (tab), bg {bg}, window {win}"));
+                text.line(d, &format!("scx {:02x} scy {:02x} wx {:02x} wy {:02x}", r.scx, r.scy, r.wx, r.wy));
+
+                self.draw_map(s);
+                self.map.update_texture(&self.pixels);
+                text.image(d, &self.map, 256.0, 256.0, &area);
+            },
+            View::Objects => {
+                text.line(d, "F3 objects");
+                self.draw_objects(s);
+                self.objects.update_texture(&self.pixels);
+
+                let size = if s.regs.lcdc & 0x04 != 0 { "8x16" } else { "8x8" };
+                text.line(d, &format!("{size}, y x tile, then palette, bank, flips and priority"));
+
+                let top = text.y;
+                for (i, o) in s.oam.chunks(4).enumerate() {
+                    let (x, y) = (area.x + 8.0 + (i / 20) as f32 * area.width / 2.0, top + (i % 20) as f32 * 20.0);
+                    let src = Rectangle { x: (i % 8 * 8) as f32, y: (i / 8 * 16) as f32, width: 8.0, height: 16.0 };
+                    d.draw_texture_pro(&self.objects, src, Rectangle { x, y: y + 2.0, width: 8.0, height: 16.0 }, Vector2 { x: 0.0, y: 0.0 }, 0.0, Color::WHITE);
+
+                    let attr = o[3];
+                    let pal = if s.cgb_mode { attr & 7 } else { (attr >> 4) & 1 };
+                    let bank = if s.cgb_mode { (attr >> 3) & 1 } else { 0 };
+                    let flips = match attr & 0x60 {
+                        0x20 => "x",
+                        0x40 => "y",
+                        0x60 => "xy",
+                        _ => "",
+                    };
+                    let prio = if attr & 0x80 != 0 { "bg" } else { "" };
+
+                    let line = format!("{i:02}  {:02x} {:02x} {:02x}  p{pal} b{bank} {flips} {prio}", o[0], o[1], o[2]);
+                    d.draw_text_ex(font, &line, Vector2 { x: x + 14.0, y }, TEXT, 0.0, Color::WHITE);
+                }
+            },
+            View::Palettes => {
+                text.line(d, "F4 palettes");
+
+                if s.cgb_mode {
+                    for (name, pals) in [("bg", &s.bg), ("obj", &s.obj)] {
+                        for (i, p) in pals.iter().enumerate() {
+                            let hex = p.iter().map(|c| format!("{c:04x}")).collect::<Vec<_>>().join(" ");
+                            text.swatches(d, s, &format!("{name}{i}  {hex}"), p);
+                        }
+                    }
+                } else {
+                    text.swatches(d, s, &format!("bgp  {:02x}", s.regs.bgp), &s.bg[0]);
+                    text.swatches(d, s, &format!("obp0 {:02x}", s.regs.obp[0]), &s.obj[0]);
+                    text.swatches(d, s, &format!("obp1 {:02x}", s.regs.obp[1]), &s.obj[1]);
+                }
+
+                let r = s.regs;
+                text.line(d, "");
+                text.line(d, &format!("lcdc {:02x}  stat {:02x}  ly {:02x}  lyc {:02x}", r.lcdc, r.stat, r.ly, r.lyc));
+                text.line(d, &format!("scx {:02x}  scy {:02x}  wx {:02x}  wy {:02x}", r.scx, r.scy, r.wx, r.wy));
+                if s.cgb {
+                    text.line(d, &format!("vbk {:02x}  bcps {:02x}  ocps {:02x}  opri {:02x}", r.vbk, r.bcps, r.ocps, r.opri));
+                }
+            },
+            View::Memory => {
+                text.line(d, "F5 memory, arrows and page up/down move, hex digits write");
+
+                // the font isn't monospaced, so every byte gets its own place
+                let cell = TEXT * 1.6;
+                for row in 0..16 {
+                    let at = s.memory_at.wrapping_add(row * 16);
+                    d.draw_text_ex(font, &format!("{at:04x}"), Vector2 { x: text.x, y: text.y }, TEXT, 0.0, Color::GRAY);
+
+                    for col in 0..16 {
+                        let a = at.wrapping_add(col);
+                        let x = text.x + TEXT * 3.5 + col as f32 * cell;
+                        let value = match self.digit {
+                            Some(hi) if a == self.cursor => format!("{hi:x}_"),
+                            _ => format!("{:02x}", s.memory[(row * 16 + col) as usize]),
+                        };
+
+                        if a == self.cursor {
+                            d.draw_rectangle(x as i32 - 2, text.y as i32, cell as i32 - 2, TEXT as i32 + 2, CURSOR);
+                        }
+                        d.draw_text_ex(font, &value, Vector2 { x, y: text.y }, TEXT, 0.0, Color::WHITE);
+                    }
+
+                    text.y += TEXT + 4.0;
+                }
+            },
+        }
+    }
+
+    /// Draws the selected tile map as the background would look, with the screen drawn on top.
+    fn draw_map(&mut self, s: &Snapshot) {
+        self.pixels.resize(256 * 256 * 4, 0);
+        let signed = s.regs.lcdc & 0x10 == 0;
+        let base = if self.high_map { 0x400 } else { 0 };
+
+        for i in 0..0x400 {
+            let (tile, attr) = (s.maps[base + i], s.attrs[base + i]);
+            let bank = (attr as usize >> 3) & 1;
+            let pal = if s.cgb_mode { attr as usize & 7 } else { 0 };
+            let tile = &s.tiles[bank * 384 + gb::ppu::tile_index(tile, signed)];
+
+            for y in 0..8 {
+                for x in 0..8 {
+                    let (ix, iy) = (if attr & 0x20 != 0 { 7 - x } else { x }, if attr & 0x40 != 0 { 7 - y } else { y });
+                    let c = s.bg[pal][tile[iy][ix] as usize];
+                    put(&mut self.pixels, 256, i % 32 * 8 + x, i / 32 * 8 + y, s.rgba(c));
+                }
+            }
+        }
+
+        // the edges of the screen, which wrap around the map
+        let (scx, scy) = (s.regs.scx as usize, s.regs.scy as usize);
+        for i in 0..160 {
+            put(&mut self.pixels, 256, (scx + i) % 256, scy, OVERLAY);
+            put(&mut self.pixels, 256, (scx + i) % 256, (scy + 143) % 256, OVERLAY);
+        }
+        for i in 0..144 {
+            put(&mut self.pixels, 256, scx, (scy + i) % 256, OVERLAY);
+            put(&mut self.pixels, 256, (scx + 159) % 256, (scy + i) % 256, OVERLAY);
+        }
+    }
+
+    /// Draws every object into its own 8x16 cell, 8 to a row.
+    fn draw_objects(&mut self, s: &Snapshot) {
+        self.pixels.clear();
+        self.pixels.resize(64 * 80 * 4, 0);
+        for p in self.pixels.chunks_mut(4) { p.copy_from_slice(&BACKGROUND); }
+
+        let tall = s.regs.lcdc & 0x04 != 0;
+
+        for (i, o) in s.oam.chunks(4).enumerate() {
+            let (tile, attr) = (o[2] as usize, o[3]);
+            let (pal, bank) = if s.cgb_mode { (attr as usize & 7, (attr as usize >> 3) & 1) } else { ((attr as usize >> 4) & 1, 0) };
+            let (tile, height) = if tall { (tile & 0xfe, 16) } else { (tile, 8) };
+
+            for y in 0..height {
+                let iy = if attr & 0x40 != 0 { height - 1 - y } else { y };
+                let row = s.tiles[bank * 384 + tile + iy / 8][iy % 8];
+
+                for x in 0..8 {
+                    let c = row[if attr & 0x20 != 0 { 7 - x } else { x }];
+                    if c != 0 { put(&mut self.pixels, 64, i % 8 * 8 + x, i / 8 * 16 + y, s.rgba(s.obj[pal][c as usize])); }
+                }
+            }
+        }
+    }
+}
+
+fn put(pixels: &mut [u8], width: usize, x: usize, y: usize, c: [u8; 4]) {
+    let at = (y * width + x) * 4;
+    pixels[at..at + 4].copy_from_slice(&c);
+}
+
+/// Lays out a window from the top down.
+struct Text<'a> {
+    font: &'a Font,
+    x: f32,
+    y: f32,
+}
+
+impl Text<'_> {
+    fn line(&mut self, d: &mut RaylibDrawHandle, s: &str) {
+        d.draw_text_ex(self.font, s, Vector2 { x: self.x, y: self.y }, TEXT, 0.0, Color::WHITE);
+        self.y += TEXT + 4.0;
+    }
+
+    /// A palette, with its colors after `label`.
+    fn swatches(&mut self, d: &mut RaylibDrawHandle, s: &Snapshot, label: &str, colors: &[u16; 4]) {
+        for (i, c) in colors.iter().enumerate() {
+            let [r, g, b, a] = s.rgba(*c);
+            d.draw_rectangle(self.x as i32 + i as i32 * 20, self.y as i32, 18, TEXT as i32, Color::new(r, g, b, a));
+        }
+
+        d.draw_text_ex(self.font, label, Vector2 { x: self.x + 88.0, y: self.y }, TEXT, 0.0, Color::WHITE);
+        self.y += TEXT + 4.0;
+    }
+
+    /// An image scaled up as far as it fits in what is left of `area`.
+    fn image(&mut self, d: &mut RaylibDrawHandle, texture: &RenderTexture2D, w: f32, h: f32, area: &Rectangle) {
+        let room = (area.width - 16.0, area.y + area.height - self.y - 8.0);
+        let scale = (room.0 / w).min(room.1 / h).floor().max(1.0);
+
+        let src = Rectangle { x: 0.0, y: 0.0, width: w, height: h };
+        let dst = Rectangle { x: self.x, y: self.y, width: w * scale, height: h * scale };
+        d.draw_texture_pro(texture, src, dst, Vector2 { x: 0.0, y: 0.0 }, 0.0, Color::WHITE);
+        self.y += h * scale + 8.0;
+    }
+}
@@ -4,24 +4,58 @@ use clap::Parser;
 use raylib::{ffi::Vector2, prelude::*};
 
 mod args;
+mod debug;
 
 const BURST_CYCLES: usize = gb::CLOCK_HZ / 120;
 
+/// Right, left, up, down, A, B, select and start for each player.
+const KEYMAPS: [[KeyboardKey; 8]; 2] = [
+    [
+        KeyboardKey::KEY_D, KeyboardKey::KEY_A, KeyboardKey::KEY_W, KeyboardKey::KEY_S,
+        KeyboardKey::KEY_O, KeyboardKey::KEY_I, KeyboardKey::KEY_V, KeyboardKey::KEY_B,
+    ],
+    [
+        KeyboardKey::KEY_RIGHT, KeyboardKey::KEY_LEFT, KeyboardKey::KEY_UP, KeyboardKey::KEY_DOWN,
+        KeyboardKey::KEY_PERIOD, KeyboardKey::KEY_COMMA, KeyboardKey::KEY_RIGHT_SHIFT, KeyboardKey::KEY_SLASH,
+    ],
+];
+
 fn main() {
+    let args = args::Args::parse();
+    let header = read_header(&args.rom);
+
+    if args.info {
+        println!("{header}");
+        return;
+    }
+
+    let title = match &args.link {
+        Some(link) => format!("Gamewaifu - {} | {}", header.title, read_header(link).title),
+        None => format!("Gamewaifu - {}", header.title),
+    };
+    let screens = if args.link.is_some() { 2 } else { 1 };
+    let cable = connect_link(&args);
+
     let (mut rl, thread) = raylib::init()
-        .size(640, 570)
-        .title("Gamewaifu")
+        .size(640 * screens, 570)
+        .title(&title)
         .resizable()
         .vsync()
         .build();
 
     rl.set_exit_key(None);
 
-    let args = args::Args::parse();
-    let (gb_fb, keys) = crate::init(&args);
+    let inspector = Arc::new(debug::Inspector::default());
+    let (gb_screens, keys) = crate::init(&args, cable, inspector.clone());
+    let mut windows = debug::Windows::new(&mut rl, &thread, inspector);
 
-    let mut fb = vec![0; 160 * 144 * 4];
-    let mut rl_fb = rl.load_render_texture(&thread, 160, 144).unwrap();
+    let mut fb = Vec::new();
+    let mut rl_fbs = gb_screens.iter()
+        .map(|s| {
+            let (w, h) = s.size();
+            rl.load_render_texture(&thread, w as u32, h as u32).unwrap()
+        })
+        .collect::<Vec<_>>();
 
     let font = rl.load_font_ex(&thread, "Roboto-Regular.ttf", 18, None).unwrap();
 
@@ -29,16 +63,25 @@ fn main() {
         {
             let mut d = rl.begin_drawing(&thread);
 
-            convert(&gb_fb.lock().unwrap(), &mut fb);
-            rl_fb.update_texture(&fb);
-
             d.clear_background(Color::from_hex("0b1920").unwrap());
 
-            let scale = (d.get_screen_width() as f32 / 160.0).min(d.get_screen_height() as f32 / 144.0).floor();
-            let x = (d.get_screen_width() as f32 - scale * 160.0) / 2.0;
-            let y = (d.get_screen_height() as f32 - scale * 144.0) / 2.0;
+            // an open debug window takes the right half
+            let lcd_width = if windows.is_open() { d.get_screen_width() / 2 } else { d.get_screen_width() } as f32;
 
-            d.draw_texture_ex(&rl_fb, Vector2 { x, y }, 0.0, scale, Color::WHITE);
+            let width = gb_screens.iter().map(|s| s.size().0).sum::<usize>() as f32;
+            let height = gb_screens.iter().map(|s| s.size().1).max().unwrap() as f32;
+            let scale = (lcd_width / width).min(d.get_screen_height() as f32 / height).floor();
+            let mut x = (lcd_width - scale * width) / 2.0;
+
+            for (gb_screen, rl_fb) in gb_screens.iter().zip(rl_fbs.iter_mut()) {
+                gb_screen.convert(&mut fb);
+                rl_fb.update_texture(&fb);
+
+                let (w, h) = gb_screen.size();
+                let y = (d.get_screen_height() as f32 - scale * h as f32) / 2.0;
+                d.draw_texture_ex(&*rl_fb, Vector2 { x, y }, 0.0, scale, Color::WHITE);
+                x += scale * w as f32;
+            }
 
             let fps = d.get_fps();
             d.draw_text_ex(&font, &format!("Display FPS {fps}\nScale {scale}"), Vector2 { x: 0.0, y: 0.0 }, 18.0, 0.0, Color::WHITE);
@@ -46,76 +89,151 @@ fn main() {
             if args.waifu {
                 d.draw_text(&format!("bruh you expected waifu??"), 0, 100, 18, Color::RED);
             }
+
+            let area = Rectangle { x: lcd_width, y: 0.0, width: d.get_screen_width() as f32 - lcd_width, height: d.get_screen_height() as f32 };
+            windows.draw(&mut d, &font, area);
+        }
+
+        // the memory editor takes the keyboard while it is open
+        let typing = windows.input(&mut rl);
+
+        for (keys, map) in keys.iter().zip(KEYMAPS) {
+            let k = map.iter().enumerate().fold(0, |k, (i, key)| k | (((rl.is_key_down(*key) && !typing) as u8) << i));
+            keys.store(k, Ordering::Relaxed);
         }
 
-        let du = rl.is_key_down(KeyboardKey::KEY_W) as u8;
-        let dd = rl.is_key_down(KeyboardKey::KEY_S) as u8;
-        let dl = rl.is_key_down(KeyboardKey::KEY_A) as u8;
-        let dr = rl.is_key_down(KeyboardKey::KEY_D) as u8;
-        let sa = rl.is_key_down(KeyboardKey::KEY_O) as u8;
-        let sb = rl.is_key_down(KeyboardKey::KEY_I) as u8;
-        let sl = rl.is_key_down(KeyboardKey::KEY_V) as u8;
-        let st = rl.is_key_down(KeyboardKey::KEY_B) as u8;
-
-        keys.store(
-            dr | (dl << 1) | (du << 2) | (dd << 3)
-               | (sa << 4) | (sb << 5) | (sl << 6) | (st << 7),
-            Ordering::Relaxed
-        );
-
-        if rl.is_key_pressed(KeyboardKey::KEY_T) {
-            let color_map = PALETTE.iter().flat_map(|v| TryInto::<[u8; 3]>::try_into(&v.to_be_bytes()[..3]).unwrap()).collect::<Vec<u8>>();
-
-            let mut image = std::fs::File::create(&format!("screenshot_{}.gif", std::time::UNIX_EPOCH.elapsed().unwrap().as_millis())).unwrap();
-            let mut encoder = gif::Encoder::new(&mut image, 160, 144, &color_map).unwrap();
-            let mut frame = gif::Frame::default();
-            frame.width = 160;
-            frame.height = 144;
-            frame.buffer = std::borrow::Cow::Owned((*gb_fb.lock().unwrap()).to_vec());
-            encoder.write_frame(&frame).unwrap();
-        } else if rl.is_key_pressed(KeyboardKey::KEY_Y) {
+        let hotkey = |k| !typing && rl.is_key_pressed(k);
+
+        if hotkey(KeyboardKey::KEY_T) {
+            let time = std::time::UNIX_EPOCH.elapsed().unwrap().as_millis();
+
+            for (i, gb_screen) in gb_screens.iter().enumerate() {
+                let name = if i == 0 { format!("screenshot_{time}.gif") } else { format!("screenshot_{time}_{}.gif", i + 1) };
+
+                let mut rgba = Vec::new();
+                gb_screen.convert(&mut rgba);
+                let (w, h) = gb_screen.size();
+
+                let mut image = std::fs::File::create(&name).unwrap();
+                let mut encoder = gif::Encoder::new(&mut image, w as u16, h as u16, &[]).unwrap();
+                encoder.write_frame(&gif::Frame::from_rgba(w as u16, h as u16, &mut rgba)).unwrap();
+            }
+        } else if hotkey(KeyboardKey::KEY_Y) {
             SAVE.store(true, Ordering::Relaxed);
+        } else if hotkey(KeyboardKey::KEY_K) {
+            SAVE_STATE.store(true, Ordering::Relaxed);
+        } else if hotkey(KeyboardKey::KEY_L) {
+            LOAD_STATE.store(true, Ordering::Relaxed);
         }
 
         BURST.store(rl.is_key_down(KeyboardKey::KEY_ENTER), Ordering::Relaxed);
     }
 
-    const PALETTE: [u32; 8] = [
-        0xf5faefff,
-        0x86c270ff,
-        0x2f6957ff,
-        0x0b1920ff,
-        0xf50000ff,
-        0x860000ff,
-        0x2f0000ff,
-        0x0b0000ff,
-    ];
-
-    fn convert(gb_fb: &[u8], fb: &mut [u8]) {
-        for (i, c) in gb_fb.iter().enumerate() {
-            let c = PALETTE[*c as usize];
-            let c = c.to_be_bytes();
-            let (_, r) = fb.split_at_mut(i * 4);
-            let (l, _) = r.split_at_mut(4);
-            l.copy_from_slice(&c);
+    SAVE.store(!rl.is_key_down(KeyboardKey::KEY_BACKSPACE), Ordering::Relaxed);
+    while SAVE.load(Ordering::Relaxed) {}
+}
+
+const PALETTE: [u32; 8] = [
+    0xf5faefff,
+    0x86c270ff,
+    0x2f6957ff,
+    0x0b1920ff,
+    0xf50000ff,
+    0x860000ff,
+    0x2f0000ff,
+    0x0b0000ff,
+];
+
+/// What a Game Boy draws into, a Super Game Boy draws the game inside its border.
+enum Screen {
+    Lcd(gb::ppu::Framebuffer, gb::Model),
+    Sgb(gb::sgb::Framebuffer),
+}
+
+impl Screen {
+    fn size(&self) -> (usize, usize) {
+        match self {
+            Self::Lcd(..) => (160, 144),
+            Self::Sgb(_) => (256, 224),
         }
     }
 
-    SAVE.store(!rl.is_key_down(KeyboardKey::KEY_BACKSPACE), Ordering::Relaxed);
-    while SAVE.load(Ordering::Relaxed) {}
+    /// Converts the current picture to rgba.
+    fn convert(&self, fb: &mut Vec<u8>) {
+        let (w, h) = self.size();
+        fb.resize(w * h * 4, 0);
+
+        match self {
+            Self::Lcd(gb_fb, gb::Model::Cgb) => convert(&*gb_fb.lock().unwrap(), fb, true),
+            Self::Lcd(gb_fb, _) => convert(&*gb_fb.lock().unwrap(), fb, false),
+            Self::Sgb(sgb_fb) => convert(&*sgb_fb.lock().unwrap(), fb, true),
+        }
+    }
+}
+
+/// `colors` tells 15 bit colors apart from the shades of a DMG.
+fn convert(gb_fb: &[u16], fb: &mut [u8], colors: bool) {
+    for (i, c) in gb_fb.iter().enumerate() {
+        let (_, r) = fb.split_at_mut(i * 4);
+        let (l, _) = r.split_at_mut(4);
+        l.copy_from_slice(&rgba(*c, colors));
+    }
+}
+
+fn rgba(c: u16, colors: bool) -> [u8; 4] {
+    if colors {
+        let ch = |s: u16| { let v = (c >> s) as u8 & 0x1f; (v << 3) | (v >> 2) };
+        [ch(0), ch(5), ch(10), 0xff]
+    } else {
+        PALETTE[c as usize].to_be_bytes()
+    }
 }
 
 static BURST: AtomicBool = AtomicBool::new(false);
 static SAVE: AtomicBool = AtomicBool::new(false);
+static SAVE_STATE: AtomicBool = AtomicBool::new(false);
+static LOAD_STATE: AtomicBool = AtomicBool::new(false);
 
-fn run_emu(mut gb: gb::Gameboy, save_file: String) {
+enum Emu<'a> {
+    Single(Box<gb::Gameboy<'a>>),
+    Linked(Box<gb::link::LinkedPair<'a>>),
+}
+
+impl<'a> Emu<'a> {
+    fn step(&mut self) {
+        match self {
+            Self::Single(gb) => gb.step(),
+            Self::Linked(pair) => pair.step(),
+        }
+    }
+
+    fn gameboys(&mut self) -> Vec<&mut gb::Gameboy<'a>> {
+        match self {
+            Self::Single(gb) => vec![&mut **gb],
+            Self::Linked(pair) => vec![&mut pair.a, &mut pair.b],
+        }
+    }
+}
+
+/// The save file and state file of each game.
+struct Files {
+    save: String,
+    state: String,
+}
+
+fn run_emu(mut emu: Emu, files: Vec<Files>, mut gdb: Option<gb::gdb::GdbStub>, inspector: Arc<debug::Inspector>) {
     use std::time::*;
 
     let mut dur = Duration::new(0, 0);
 
     loop {
         let start = Instant::now();
-        for _ in 0..BURST_CYCLES { gb.step(); }
+        match (&mut gdb, &mut emu) {
+            (Some(gdb), Emu::Single(gb)) => gdb.run(gb, BURST_CYCLES),
+            _ => for _ in 0..BURST_CYCLES { emu.step(); },
+        }
+
+        if let Some(gb) = emu.gameboys().into_iter().next() { inspector.update(gb); }
 
         if !BURST.load(Ordering::Relaxed) {
             dur += Duration::from_secs_f64(BURST_CYCLES as f64 / gb::CLOCK_HZ as f64);
@@ -132,33 +250,131 @@ fn run_emu(mut gb: gb::Gameboy, save_file: String) {
         }
 
         if SAVE.load(Ordering::Acquire) {
-            if let Some(sram) = gb.get_sram() {
-                std::fs::write(&save_file, sram).unwrap();
+            for (gb, Files { save: save_file, .. }) in emu.gameboys().into_iter().zip(&files) {
+                if let Some(sram) = gb.get_sram() {
+                    let mut sav = sram.to_vec();
+                    sav.extend(gb.get_rtc().into_iter().flatten());
+                    std::fs::write(save_file, sav).unwrap();
+                    println!("Saved to {save_file}");
+                }
             }
 
             SAVE.store(false, Ordering::Release);
-            println!("Saved to {save_file}");
         }
+
+        if SAVE_STATE.swap(false, Ordering::Relaxed) {
+            for (gb, Files { state: state_file, .. }) in emu.gameboys().into_iter().zip(&files) {
+                std::fs::write(state_file, gb.save_state()).unwrap();
+                println!("Saved state to {state_file}");
+            }
+        }
+
+        if LOAD_STATE.swap(false, Ordering::Relaxed) {
+            for (gb, Files { state: state_file, .. }) in emu.gameboys().into_iter().zip(&files) {
+                match std::fs::read(state_file).map(|s| gb.load_state(&s)) {
+                    Ok(Ok(())) => println!("Loaded state from {state_file}"),
+                    Ok(Err(e)) => println!("Failed to load state from {state_file}: {e}"),
+                    Err(e) => println!("Failed to read {state_file}: {e}"),
+                }
+            }
+        }
+    }
+}
+
+fn read_header(path: &str) -> gb::cartridge::Header {
+    let rom = std::fs::read(path).unwrap();
+    gb::cartridge::Header::parse(&rom).unwrap_or_else(|e| {
+        eprintln!("Failed to load {path}: {e}");
+        std::process::exit(1);
+    })
+}
+
+fn connect_link(args: &args::Args) -> Option<gb::link::TcpCable> {
+    let cable = match (args.link_listen, &args.link_connect) {
+        (Some(port), _) => {
+            println!("Waiting for a link cable on port {port}");
+            match &args.link_bind {
+                Some(addr) => gb::link::TcpCable::listen_on((addr.as_str(), port)),
+                None => gb::link::TcpCable::listen(port),
+            }
+        },
+        (None, Some(addr)) => gb::link::TcpCable::connect(addr.as_str()),
+        (None, None) => return None,
+    };
+
+    Some(cable.unwrap_or_else(|e| {
+        eprintln!("Failed to connect the link cable: {e}");
+        std::process::exit(1);
+    }))
+}
+
+fn load_mapper(path: &str, args: &args::Args) -> gb::mapper::Mapper {
+    let rom = std::fs::read(path).unwrap();
+
+    let mapper = if args.ignore_checksum {
+        gb::mapper::Mapper::from_bin_permissive(&rom)
+    } else {
+        gb::mapper::Mapper::from_bin(&rom)
+    };
+    let mut mapper = mapper.unwrap_or_else(|e| {
+        eprintln!("Failed to load {path}: {e}");
+        std::process::exit(1);
+    });
+    mapper.set_rtc_clock(gb::rtc::Clock::system());
+
+    mapper
+}
+
+fn listen_gdb(args: &args::Args) -> Option<gb::gdb::GdbStub> {
+    let port = args.gdb?;
+
+    match gb::gdb::GdbStub::listen(port) {
+        Ok(stub) => {
+            println!("Listening for gdb on port {port}");
+            Some(stub)
+        },
+        Err(e) => {
+            eprintln!("Failed to listen for gdb on port {port}: {e}");
+            std::process::exit(1);
+        },
     }
 }
 
-fn init(args: &args::Args) -> (Arc<Mutex<[u8]>>, Arc<AtomicU8>) {
-    let rom = std::fs::read(&args.rom).unwrap();
-    let br = args.boot_rom.as_ref().map(|b| std::fs::read(b).unwrap().into());
+fn init(args: &args::Args, cable: Option<gb::link::TcpCable>, inspector: Arc<debug::Inspector>) -> (Vec<Screen>, Vec<Arc<AtomicU8>>) {
+    let br = args.boot_rom.as_ref().map(|b| std::fs::read(b).unwrap().into_boxed_slice());
+    let gdb = listen_gdb(args);
 
-    let gb_fb = Mutex::new([0; 160 * 144]).into();
-    let keys = Arc::new(AtomicU8::new(0x00));
+    let mut roms = vec![(args.rom.clone(), args.save_file.clone().unwrap_or(args.rom.to_string() + ".sav"))];
+    if let Some(link) = &args.link {
+        roms.push((link.clone(), link.to_string() + ".sav"));
+    }
 
-    let mapper = gb::mapper::Mapper::from_bin(&rom);
+    let mappers = roms.iter().map(|(rom, _)| load_mapper(rom, args)).collect::<Vec<_>>();
+    let models = roms.iter()
+        .map(|(rom, _)| match (args.model, args.colorize) {
+            (Some(model), _) => model.into(),
+            (None, Some(_)) => gb::Model::Cgb,
+            (None, None) => gb::Model::from_header(&read_header(rom)),
+        })
+        .collect::<Vec<_>>();
+    let preset = args.colorize.and_then(args::Colorize::preset);
+    let files = roms.into_iter().map(|(rom, save)| Files { save, state: rom + ".state" }).collect::<Vec<_>>();
+
+    let gb_fbs = mappers.iter().map(|_| Arc::new(Mutex::new([0; 160 * 144]))).collect::<Vec<gb::ppu::Framebuffer>>();
+    let sgb_fbs = models.iter()
+        .map(|m| (*m == gb::Model::Sgb).then(|| Arc::new(Mutex::new([0; 256 * 224]))))
+        .collect::<Vec<Option<gb::sgb::Framebuffer>>>();
+    let keys = mappers.iter().map(|_| Arc::new(AtomicU8::new(0x00))).collect::<Vec<_>>();
 
     {
-        let gb_fb = Arc::clone(&gb_fb);
-        let keys = Arc::clone(&keys);
-        let save_file = args.save_file.clone().unwrap_or(args.rom.to_string() + ".sav");
+        let gb_fbs = gb_fbs.clone();
+        let sgb_fbs = sgb_fbs.clone();
+        let keys = keys.clone();
+        let models = models.clone();
 
         thread::spawn(move || {
             let (_stream, st_handle) = rodio::OutputStream::try_default().unwrap();
-            let sink = rodio::Sink::try_new(&st_handle).unwrap();
+            let sinks = mappers.iter().map(|_| rodio::Sink::try_new(&st_handle).unwrap()).collect::<Vec<_>>();
 
             // let mut wav = Vec::<u8>::new();
             // wav.extend(b"RIFF");
@@ -177,22 +393,46 @@ fn init(args: &args::Args) -> (Arc<Mutex<[u8]>>, Arc<AtomicU8>) {
             // let data_size_idx = wav.len();
             // wav.extend(0_u32.to_le_bytes());
 
-            let mut gb = gb::Gameboy::new(mapper, br, gb_fb, Box::new(|buf| {
-                // if sink.len() > 3 {
-                //     for _ in 0..sink.len() { sink.skip_one(); }
-                // }
-
-                sink.append(rodio::buffer::SamplesBuffer::new(2, gb::apu::SAMPLE_RATE as u32, buf));
-
-                // wav.extend(buf.iter().flat_map(|v| v.to_le_bytes()));
-            }), keys);
+            let mut gbs = mappers.into_iter()
+                .zip(models)
+                .zip(gb_fbs)
+                .zip(keys)
+                .zip(&sinks)
+                .map(|((((mapper, model), gb_fb), keys), sink)| gb::Gameboy::new(model, mapper, br.clone(), gb_fb, Box::new(move |buf| {
+                    // if sink.len() > 3 {
+                    //     for _ in 0..sink.len() { sink.skip_one(); }
+                    // }
+
+                    sink.append(rodio::buffer::SamplesBuffer::new(2, gb::apu::SAMPLE_RATE as u32, buf));
+
+                    // wav.extend(buf.iter().flat_map(|v| v.to_le_bytes()));
+                }), keys))
+                .collect::<Vec<_>>();
+
+            for ((gb, Files { save: save_file, .. }), sgb_fb) in gbs.iter_mut().zip(&files).zip(sgb_fbs) {
+                gb.set_sgb_framebuffer(sgb_fb);
+
+                if let Some(preset) = preset {
+                    gb.set_compat_palette(&preset.palette());
+                }
+
+                if let Ok(sav) = std::fs::read(save_file) {
+                    gb.set_sram(&sav);
+                    println!("Restored save file from {save_file}");
+                }
+            }
 
-            if let Ok(sav) = std::fs::read(&save_file) {
-                gb.set_sram(&sav);
-                println!("Restored save file from {save_file}");
+            if let (Some(cable), Some(gb)) = (cable, gbs.first_mut()) {
+                gb.set_link_cable(Some(Box::new(cable)));
             }
 
-            run_emu(gb, save_file);
+            let emu = match (gbs.pop(), gbs.pop()) {
+                (Some(b), Some(a)) => Emu::Linked(Box::new(gb::link::LinkedPair::new(a, b))),
+                (Some(a), None) => Emu::Single(Box::new(a)),
+                _ => unreachable!(),
+            };
+
+            run_emu(emu, files, gdb, inspector);
 
             // let wav_len = wav.len();
             // wav[file_size_idx..file_size_idx + 4].copy_from_slice(&(wav_len as u32).to_le_bytes());
@@ -201,6 +441,14 @@ fn init(args: &args::Args) -> (Arc<Mutex<[u8]>>, Arc<AtomicU8>) {
         });
     }
 
-    (gb_fb, keys)
-}
+    let screens = gb_fbs.into_iter()
+        .zip(sgb_fbs)
+        .zip(models)
+        .map(|((gb_fb, sgb_fb), model)| match sgb_fb {
+            Some(sgb_fb) => Screen::Sgb(sgb_fb),
+            None => Screen::Lcd(gb_fb, model),
+        })
+        .collect();
 
+    (screens, keys)
+}
@@ -1,3 +1,5 @@
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
 pub const SAMPLE_RATE: usize = 44100;
 pub const FRAME_COUNT: usize = 1024;
 pub const BUFFER_SIZE: usize = FRAME_COUNT * 2;
@@ -619,3 +621,231 @@ impl Channel4 {
 
     fn dac_enabled(&self) -> bool { self.envelope.init_vol != 0 || self.envelope.env_dir }
 }
+
+impl Snapshot for Apu<'_> {
+    fn save_state(&self, w: &mut Writer) {
+        w.usize(self.output_timer);
+        w.usize(self.seq_timer);
+        w.bool(self.last_div_edge);
+
+        w.bool(self.enable);
+
+        self.ch1.save_state(w);
+        self.ch2.save_state(w);
+        self.ch3.save_state(w);
+        self.ch4.save_state(w);
+
+        w.u8(self.volume.0);
+        w.u8(self.volume.1);
+        w.bool(self.vin_enabled.0);
+        w.bool(self.vin_enabled.1);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.output_timer = r.usize()?;
+        self.seq_timer = r.usize()? % 8;
+        self.last_div_edge = r.bool()?;
+
+        self.enable = r.bool()?;
+
+        self.ch1.load_state(r)?;
+        self.ch2.load_state(r)?;
+        self.ch3.load_state(r)?;
+        self.ch4.load_state(r)?;
+
+        self.volume = (r.u8()?, r.u8()?);
+        self.vin_enabled = (r.bool()?, r.bool()?);
+
+        Ok(())
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.to_bits());
+        w.u8(self.volume);
+        w.u8(self.pace_timer);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.update_from_bits(r.u8()?);
+        self.volume = r.u8()?;
+        self.pace_timer = r.u8()?;
+
+        Ok(())
+    }
+}
+
+impl Snapshot for Channel1 {
+    fn save_state(&self, w: &mut Writer) {
+        w.bool(self.active);
+        w.bool(self.triggered);
+        w.bool(self.hard_pan.0);
+        w.bool(self.hard_pan.1);
+
+        w.u8(self.sweep_pace);
+        w.bool(self.sweep_dir);
+        w.u8(self.sweep_step);
+        w.bool(self.sweep_enabled);
+        w.u8(self.sweep_timer);
+
+        w.u8(self.duty);
+        w.u16(self.period);
+        w.u16(self.internal_period);
+
+        self.envelope.save_state(w);
+
+        w.u8(self.length_timer);
+        w.bool(self.length_en);
+
+        w.u16(self.freq_timer);
+        w.u8(self.duty_pos);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.active = r.bool()?;
+        self.triggered = r.bool()?;
+        self.hard_pan = (r.bool()?, r.bool()?);
+
+        self.sweep_pace = r.u8()?;
+        self.sweep_dir = r.bool()?;
+        self.sweep_step = r.u8()?;
+        self.sweep_enabled = r.bool()?;
+        self.sweep_timer = r.u8()?;
+
+        self.duty = r.u8()? & 3;
+        self.period = r.u16()?;
+        self.internal_period = r.u16()?;
+
+        self.envelope.load_state(r)?;
+
+        self.length_timer = r.u8()?;
+        self.length_en = r.bool()?;
+
+        self.freq_timer = r.u16()?;
+        self.duty_pos = r.u8()? % 8;
+
+        Ok(())
+    }
+}
+
+impl Snapshot for Channel2 {
+    fn save_state(&self, w: &mut Writer) {
+        w.bool(self.active);
+        w.bool(self.triggered);
+        w.bool(self.hard_pan.0);
+        w.bool(self.hard_pan.1);
+
+        w.u8(self.duty);
+        w.u16(self.period);
+
+        self.envelope.save_state(w);
+
+        w.u8(self.length_timer);
+        w.bool(self.length_en);
+
+        w.u16(self.freq_timer);
+        w.u8(self.duty_pos);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.active = r.bool()?;
+        self.triggered = r.bool()?;
+        self.hard_pan = (r.bool()?, r.bool()?);
+
+        self.duty = r.u8()? & 3;
+        self.period = r.u16()?;
+
+        self.envelope.load_state(r)?;
+
+        self.length_timer = r.u8()?;
+        self.length_en = r.bool()?;
+
+        self.freq_timer = r.u16()?;
+        self.duty_pos = r.u8()? % 8;
+
+        Ok(())
+    }
+}
+
+impl Snapshot for Channel3 {
+    fn save_state(&self, w: &mut Writer) {
+        w.bool(self.active);
+        w.bool(self.dac_enabled);
+        w.bool(self.triggered);
+        w.bool(self.hard_pan.0);
+        w.bool(self.hard_pan.1);
+
+        w.u16(self.period);
+
+        w.u8(self.out_level);
+        w.bytes(&self.wave);
+
+        w.u16(self.length_timer);
+        w.bool(self.length_en);
+
+        w.u16(self.freq_timer);
+        w.usize(self.wave_pos);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.active = r.bool()?;
+        self.dac_enabled = r.bool()?;
+        self.triggered = r.bool()?;
+        self.hard_pan = (r.bool()?, r.bool()?);
+
+        self.period = r.u16()?;
+
+        self.out_level = r.u8()? & 3;
+        r.bytes(&mut self.wave)?;
+
+        self.length_timer = r.u16()?;
+        self.length_en = r.bool()?;
+
+        self.freq_timer = r.u16()?;
+        self.wave_pos = r.usize()? % 32;
+
+        Ok(())
+    }
+}
+
+impl Snapshot for Channel4 {
+    fn save_state(&self, w: &mut Writer) {
+        w.bool(self.active);
+        w.bool(self.triggered);
+        w.bool(self.hard_pan.0);
+        w.bool(self.hard_pan.1);
+
+        w.u8(self.clock_shift);
+        w.u8(self.clock_div);
+        w.bool(self.width);
+
+        self.envelope.save_state(w);
+
+        w.u8(self.length_timer);
+        w.bool(self.length_en);
+
+        w.u16(self.freq_timer);
+        w.u16(self.lfsr);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.active = r.bool()?;
+        self.triggered = r.bool()?;
+        self.hard_pan = (r.bool()?, r.bool()?);
+
+        self.clock_shift = r.u8()? & 0xf;
+        self.clock_div = r.u8()? & 7;
+        self.width = r.bool()?;
+
+        self.envelope.load_state(r)?;
+
+        self.length_timer = r.u8()?;
+        self.length_en = r.bool()?;
+
+        self.freq_timer = r.u16()?;
+        self.lfsr = r.u16()?;
+
+        Ok(())
+    }
+}
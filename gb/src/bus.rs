@@ -1,17 +1,48 @@
 use std::sync::{atomic::*, *};
 
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
 #[derive(derivative::Derivative)]
 #[derivative(Debug)]
 pub(crate) struct Bus<'a> {
     pub(crate) ppu: crate::ppu::Ppu,
     pub(crate) apu: crate::apu::Apu<'a>,
+    pub(crate) serial: crate::serial::Serial<'a>,
+    pub(crate) sgb: Option<Box<crate::sgb::Sgb>>,
 
     #[derivative(Debug = "ignore")]
     pub(crate) mapper: crate::mapper::Mapper,
-    pub(crate) wram: [u8; 0x2000],
+    #[derivative(Debug = "ignore")]
+    pub(crate) wram: [u8; 0x8000],
     pub(crate) hram: [u8; 0x7f],
+    svbk: u8,
+
+    /// KEY1, bit 0 asks the next `stop` to switch speeds.
+    key1: u8,
+    pub(crate) double_speed: bool,
+    /// Which half of a dot the cpu is in, in double speed.
+    half: bool,
 
     pub(crate) oam_dma_at: (u8, u8),
+    /// The m-cycle after writing DMA, before the first byte moves.
+    oam_dma_delay: bool,
+    /// The byte the dma last moved, which is what the cpu reads when it shares the dma's bus.
+    oam_dma_byte: u8,
+
+    /// An oam row the cpu's increment or decrement unit glitched, along with the divider at
+    /// that point. A read or write on the same m-cycle turns it into a different corruption.
+    oam_glitch: Option<(usize, usize)>,
+    /// The cpu's divider as of the last step.
+    div: usize,
+
+    hdma_src: u16,
+    hdma_dst: u16,
+    /// Blocks left minus one, wraps to 0xff when done.
+    hdma_len: u8,
+    /// A dma copying a block every hblank.
+    hdma_active: bool,
+    /// M-cycles the cpu still owes a dma.
+    stall: usize,
 
     pub(crate) tima: u8,
     pub(crate) tma: u8,
@@ -34,16 +65,36 @@ impl<'a> Bus<'a> {
         boot_rom: Option<Box<[u8]>>,
         keys: Arc<AtomicU8>,
     ) -> Self {
+        let cgb = ppu.cgb;
+
         Self {
             ppu,
             apu,
+            serial: crate::serial::Serial::new(cgb),
+            sgb: None,
 
             mapper,
 
-            wram: [0; 0x2000],
+            wram: [0; 0x8000],
             hram: [0; 0x7f],
+            svbk: 1,
+
+            key1: 0,
+            double_speed: false,
+            half: false,
 
             oam_dma_at: (0xff, 0xff),
+            oam_dma_delay: false,
+            oam_dma_byte: 0xff,
+
+            oam_glitch: None,
+            div: 0,
+
+            hdma_src: 0,
+            hdma_dst: 0,
+            hdma_len: 0xff,
+            hdma_active: false,
+            stall: 0,
 
             tima: 0,
             tma: 0,
@@ -58,33 +109,112 @@ impl<'a> Bus<'a> {
             boot_rom,
         }
     }
-}
 
-impl sm83::bus::Bus for Bus<'_> {
-    fn load(&mut self, a: u16) -> u8 {
-        if let (true, Some(br)) = (a <= 0x00ff, &self.boot_rom) {
-            return br[a as usize];
+    /// Where a wram address ends up, 0xd000..=0xdfff is banked on a cgb.
+    fn wram_at(&self, a: u16) -> usize {
+        let a = a as usize & 0x1fff;
+        if a < 0x1000 { a } else { self.svbk as usize * 0x1000 + a - 0x1000 }
+    }
+
+    /// Copies 16 bytes into vram, the cpu waits the same time in either speed.
+    fn hdma_block(&mut self) {
+        for _ in 0..16 {
+            // vram can't see itself, and nothing past wram can be a source
+            let d = match self.hdma_src {
+                0x8000..=0x9fff | 0xe000..=0xffff => 0xff,
+                a => self.read(a),
+            };
+            let at = self.ppu.vram_at(self.hdma_dst);
+            self.ppu.vram[at] = d;
+
+            self.hdma_src = self.hdma_src.wrapping_add(1);
+            self.hdma_dst = self.hdma_dst.wrapping_add(1);
+        }
+
+        self.hdma_len = self.hdma_len.wrapping_sub(1);
+        if self.hdma_len == 0xff { self.hdma_active = false; }
+
+        self.stall += if self.double_speed { 16 } else { 8 };
+    }
+
+    fn boot_rom_mapped(&self, a: u16) -> Option<u8> {
+        let br = self.boot_rom.as_ref()?;
+
+        // a cgb boot rom leaves a hole for the cartridge header
+        match a {
+            0x0000..=0x00ff => Some(br[a as usize]),
+            0x0200..=0x08ff if br.len() > 0x200 => Some(br[a as usize]),
+            _ => None,
+        }
+    }
+
+    fn joypad(&self) -> u8 {
+        // the other players on a super game boy never press anything
+        let (player, multi) = self.sgb.as_ref().map_or((0, false), |s| (s.player, s.multiplayer()));
+        if multi && self.key_sel == 0x30 { return 0xff - player; }
+
+        let keys = if player == 0 { self.keys.load(Ordering::Relaxed) } else { 0 };
+        let dp = if self.key_sel & 0x10 == 0 { keys & 0xf } else { 0 };
+        let sl = if self.key_sel & 0x20 == 0 { keys >> 4 } else { 0 };
+        (0xf | self.key_sel) & !dp & !sl
+    }
+
+    /// What the cpu gets instead when an oam dma is using the same bus as it, only the io
+    /// registers and hram are never shared.
+    fn dma_conflict(&self, a: u16) -> Option<u8> {
+        if self.oam_dma_at.1 > 0x9f || self.oam_dma_delay { return None; }
+
+        let bus = |a: u16| match a {
+            0x8000..=0x9fff => 1,
+            // a cgb gives wram its own bus
+            0xc000..=0xfdff if self.ppu.cgb => 2,
+            0xfe00..=0xffff => 3,
+            _ => 0,
+        };
+
+        match a {
+            0xfe00..=0xfeff => Some(0xff),
+            0xff00..=0xffff => None,
+            _ if bus(a) == bus((self.oam_dma_at.0 as u16) << 8) => Some(self.oam_dma_byte),
+            _ => None,
+        }
+    }
+
+    /// The dmg's oam corruption for a cpu access, along with any increment or decrement on the
+    /// same m-cycle.
+    fn oam_bug(&mut self, a: u16, read: bool) {
+        let div = self.div;
+        let idu = self.oam_glitch.take().filter(|(_, at)| div.wrapping_sub(*at) <= 4);
+        let row = self.ppu.oam_bug_row().filter(|_| (0xfe00..=0xfeff).contains(&a));
+
+        match (row, idu) {
+            (Some(r), Some(_)) if read => self.ppu.oam_bug_read_idu(r),
+            (Some(r), _) if read => self.ppu.oam_bug_read(r),
+            (Some(r), _) | (None, Some((r, _))) => self.ppu.oam_bug_write(r),
+            (None, None) => {},
         }
+    }
+
+    pub(crate) fn read(&mut self, a: u16) -> u8 {
+        if let Some(d) = self.boot_rom_mapped(a) { return d; }
+
+        let cgb = self.ppu.cgb;
 
         match a {
             0x0000..=0x7fff => self.mapper.load(a),
             0xa000..=0xbfff => self.mapper.load(a),
-            0xc000..=0xdfff => self.wram[a as usize - 0xc000],
-            0xe000..=0xfdff => self.wram[a as usize - 0xe000],
+            0xc000..=0xfdff => self.wram[self.wram_at(a)],
             0xfea0..=0xfeff => 0xff,
-            0xff00 => {
-                let keys = self.keys.load(Ordering::Relaxed);
-                let dp = if self.key_sel & 0x10 == 0 { keys & 0xf } else { 0 };
-                let sl = if self.key_sel & 0x20 == 0 { keys >> 4 } else { 0 };
-                (0xf | self.key_sel) & !dp & !sl
-            },
-            0xff01 => 0x00,
-            0xff02 => 0x7e,
+            0xff00 => self.joypad(),
+            0xff01 | 0xff02 => self.serial.load(a),
             0xff05 => self.tima,
             0xff06 => self.tma,
             0xff07 => self.tac,
             0xff46 => self.oam_dma_at.0,
-            0x8000..=0x9fff | 0xfe00..=0xfe9f | 0xff40..=0xff45 | 0xff47..=0xff4b => self.ppu.load(a),
+            0xff4d if cgb => 0x7e | ((self.double_speed as u8) << 7) | self.key1,
+            0xff55 if cgb => ((!self.hdma_active as u8) << 7) | (self.hdma_len & 0x7f),
+            0xff70 if cgb => 0xf8 | self.svbk,
+            0x8000..=0x9fff | 0xfe00..=0xfe9f | 0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f | 0xff68..=0xff6c => self.ppu.load(a),
             0xff10..=0xff3f => self.apu.load(a),
             0xff00..=0xff7f => 0xff,
             0xff80..=0xfffe => self.hram[a as usize - 0xff80],
@@ -92,41 +222,157 @@ impl sm83::bus::Bus for Bus<'_> {
         }
     }
 
-    fn store(&mut self, a: u16, d: u8) {
+    pub(crate) fn write(&mut self, a: u16, d: u8) {
         if self.boot_rom.is_some() && a == 0xff50 {
             self.boot_rom = None;
             return;
         }
 
+        // KEY0, the cgb boot rom drops into dmg compatibility through it
+        if self.boot_rom.is_some() && self.ppu.cgb && a == 0xff4c {
+            self.ppu.dmg_compat = d & 0x04 != 0;
+            return;
+        }
+
+        let cgb = self.ppu.cgb;
+
         match a {
             0x0000..=0x7fff => self.mapper.store(a, d),
             0xa000..=0xbfff => self.mapper.store(a, d),
-            0xc000..=0xdfff => self.wram[a as usize - 0xc000] = d,
-            0xe000..=0xfdff => self.wram[a as usize - 0xe000] = d,
+            0xc000..=0xfdff => self.wram[self.wram_at(a)] = d,
             0xfea0..=0xfeff => {},
-            0xff00 => self.key_sel = d & 0x30,
+            0xff00 => {
+                self.key_sel = d & 0x30;
+                if let Some(sgb) = &mut self.sgb { sgb.write_joypad(self.key_sel); }
+            },
+            0xff01 | 0xff02 => self.serial.store(a, d),
             0xff05 => self.tima = d,
             0xff06 => self.tma = d,
             0xff07 => self.tac = d & 7,
-            0xff46 => self.oam_dma_at = (d, 0),
-            0x8000..=0x9fff | 0xfe00..=0xfe9f | 0xff40..=0xff45 | 0xff47..=0xff4b => self.ppu.store(a, d),
+            0xff46 => {
+                self.oam_dma_at = (d, 0);
+                self.oam_dma_delay = true;
+            },
+            0xff4d if cgb => self.key1 = d & 1,
+            0xff51 if cgb => self.hdma_src = (self.hdma_src & 0x00ff) | ((d as u16) << 8),
+            0xff52 if cgb => self.hdma_src = (self.hdma_src & 0xff00) | (d & 0xf0) as u16,
+            0xff53 if cgb => self.hdma_dst = (self.hdma_dst & 0x00ff) | (((d & 0x1f) as u16) << 8),
+            0xff54 if cgb => self.hdma_dst = (self.hdma_dst & 0xff00) | (d & 0xf0) as u16,
+            0xff55 if cgb => match (self.hdma_active, d & 0x80 != 0) {
+                // stopping an hblank dma keeps what's left of it readable
+                (true, false) => self.hdma_active = false,
+                (_, true) => {
+                    self.hdma_len = d & 0x7f;
+                    self.hdma_active = true;
+
+                    // with the lcd off there is no hblank to wait for
+                    if self.ppu.lcdc & 0x80 == 0 { self.hdma_block(); }
+                },
+                (false, false) => {
+                    self.hdma_len = d & 0x7f;
+                    while self.hdma_len != 0xff { self.hdma_block(); }
+                },
+            },
+            0xff70 if cgb => self.svbk = (d & 7).max(1),
+            0x8000..=0x9fff | 0xfe00..=0xfe9f | 0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f | 0xff68..=0xff6c => self.ppu.store(a, d),
             0xff10..=0xff3f => self.apu.store(a, d),
             0xff00..=0xff7f => {},
             0xff80..=0xfffe => self.hram[a as usize - 0xff80] = d,
             0xffff => unreachable!(),
         }
     }
+}
+
+impl sm83::bus::Bus for Bus<'_> {
+    fn load(&mut self, a: u16) -> u8 {
+        self.oam_bug(a, true);
+        self.dma_conflict(a).unwrap_or_else(|| self.read(a))
+    }
+
+    fn peek(&mut self, a: u16) -> u8 {
+        match a {
+            0x0000..=0x7fff | 0xa000..=0xbfff if self.boot_rom_mapped(a).is_none() => self.mapper.peek(a),
+            _ => self.read(a),
+        }
+    }
+
+    fn poke(&mut self, a: u16, d: u8) { self.write(a, d) }
+
+    fn store(&mut self, a: u16, d: u8) {
+        self.oam_bug(a, false);
+        if self.dma_conflict(a).is_none() { self.write(a, d); }
+    }
+
+    fn glitch_store(&mut self, a: u16) {
+        if !(0xfe00..=0xfeff).contains(&a) { return; }
+        self.oam_glitch = self.ppu.oam_bug_row().map(|r| (r, self.div));
+    }
+
+    fn speed_switch(&mut self) -> bool {
+        if !self.ppu.cgb || self.key1 & 1 == 0 {
+            // the clock stops, and the lcd goes blank with it
+            self.ppu.blank();
+            return false;
+        }
+
+        self.key1 = 0;
+        self.double_speed = !self.double_speed;
+        true
+    }
+
+    fn stop_wake(&mut self) -> bool {
+        self.key_sel != 0x30 && self.joypad() & 0xf != 0xf
+    }
+
+    fn bank(&self, a: u16) -> Option<usize> {
+        match a {
+            0x0000..=0x7fff => Some(self.mapper.rom_bank(a)),
+            0xd000..=0xdfff => Some(self.svbk as usize),
+            _ => None,
+        }
+    }
+
+    fn stall(&mut self) -> usize { core::mem::take(&mut self.stall) }
 
     fn external_step(&mut self, div: usize, int_mgr: &mut sm83::cpu::InterruptManager) {
         let tima = self.tima;
 
-        self.ppu.step(int_mgr);
-        self.apu.step(div & 0x1000 != 0);
+        // a glitch no access picked up on its m-cycle corrupts on its own
+        self.div = div;
+        if let Some((row, at)) = self.oam_glitch {
+            if div.wrapping_sub(at) > 4 {
+                self.oam_glitch = None;
+                self.ppu.oam_bug_write(row);
+            }
+        }
+
+        // in double speed only the cpu, timer, serial port and oam dma go twice as fast
+        self.half = !self.half;
+        if !self.double_speed || self.half {
+            let mode = self.ppu.get_mode();
+            self.ppu.step(int_mgr);
+
+            if self.hdma_active && mode != 0 && self.ppu.get_mode() == 0 && self.ppu.ly < 144 {
+                self.hdma_block();
+            }
+
+            if core::mem::take(&mut self.ppu.frame_done) {
+                if let Some(sgb) = &mut self.sgb { sgb.frame(&self.ppu.back_buffer); }
+            }
+
+            self.apu.step(div & if self.double_speed { 0x2000 } else { 0x1000 } != 0);
+            self.mapper.step();
+        }
+
+        self.serial.step(div, int_mgr);
 
-        // oam dma
+        // oam dma, a byte every m-cycle
         let dma = self.oam_dma_at;
-        if dma.1 <= 0x9f {
-            let v = self.load(((dma.0 as u16) << 8) | dma.1 as u16);
+        if div & 3 == 3 && !core::mem::take(&mut self.oam_dma_delay) && dma.1 <= 0x9f {
+            // sources past wram read from its echo
+            let src = if dma.0 >= 0xe0 { dma.0 - 0x20 } else { dma.0 };
+            let v = self.read(((src as u16) << 8) | dma.1 as u16);
+            self.oam_dma_byte = v;
             self.ppu.oam[dma.1 as usize] = v;
             self.oam_dma_at.1 += 1;
         }
@@ -148,9 +394,95 @@ impl sm83::bus::Bus for Bus<'_> {
             let prev = core::mem::replace(&mut self.timer_prev, bit);
 
             if prev && !bit {
-                self.tima = tima + 1;
+                self.tima = tima.wrapping_add(1);
                 self.timer_reload = self.tima == 0;
             }
         }
     }
 }
+
+impl Snapshot for Bus<'_> {
+    fn save_state(&self, w: &mut Writer) {
+        w.bool(self.boot_rom.is_some());
+
+        w.bytes(&self.wram);
+        w.bytes(&self.hram);
+        w.u8(self.svbk);
+
+        w.u8(self.key1);
+        w.bool(self.double_speed);
+        w.bool(self.half);
+
+        w.u8(self.oam_dma_at.0);
+        w.u8(self.oam_dma_at.1);
+        w.bool(self.oam_dma_delay);
+        w.u8(self.oam_dma_byte);
+
+        w.u8(self.tima);
+        w.u8(self.tma);
+        w.u8(self.tac);
+
+        w.bool(self.timer_prev);
+        w.bool(self.timer_reload);
+
+        w.u8(self.key_sel);
+
+        self.ppu.save_state(w);
+        self.apu.save_state(w);
+        self.mapper.save_state(w);
+        self.serial.save_state(w);
+
+        w.u16(self.hdma_src);
+        w.u16(self.hdma_dst);
+        w.u8(self.hdma_len);
+        w.bool(self.hdma_active);
+        w.usize(self.stall);
+
+        if let Some(sgb) = &self.sgb { sgb.save_state(w); }
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        match (r.bool()?, self.boot_rom.is_some()) {
+            (true, false) => return Err(StateError::BootRomMissing),
+            (false, true) => self.boot_rom = None,
+            _ => {},
+        }
+
+        r.bytes(&mut self.wram)?;
+        r.bytes(&mut self.hram)?;
+        self.svbk = (r.u8()? & 7).max(1);
+
+        self.key1 = r.u8()? & 1;
+        self.double_speed = r.bool()?;
+        self.half = r.bool()?;
+
+        self.oam_dma_at = (r.u8()?, r.u8()?);
+
+        self.oam_dma_delay = r.bool()?;
+        self.oam_dma_byte = r.u8()?;
+
+        self.tima = r.u8()?;
+        self.tma = r.u8()?;
+        self.tac = r.u8()?;
+
+        self.timer_prev = r.bool()?;
+        self.timer_reload = r.bool()?;
+
+        self.key_sel = r.u8()? & 0xf0;
+
+        self.ppu.load_state(r)?;
+        self.apu.load_state(r)?;
+        self.mapper.load_state(r)?;
+        self.serial.load_state(r)?;
+
+        self.hdma_src = r.u16()? & 0xfff0;
+        self.hdma_dst = r.u16()? & 0x1ff0;
+        self.hdma_len = r.u8()?;
+        self.hdma_active = r.bool()?;
+        self.stall = r.usize()?;
+
+        if let Some(sgb) = &mut self.sgb { sgb.load_state(r)?; }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use core::fmt;
+
+use crate::mapper::RomError;
+
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbFlag {
+    DmgOnly,
+    Enhanced,
+    CgbOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Licensee {
+    Old(u8),
+    New([u8; 2]),
+}
+
+/// The cartridge header at 0x100..=0x14f.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub entry_point: [u8; 4],
+    pub logo_valid: bool,
+    pub title: String,
+    pub manufacturer: Option<[u8; 4]>,
+    pub cgb: CgbFlag,
+    pub licensee: Licensee,
+    pub sgb: bool,
+    pub cart_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub japanese: bool,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub computed_header_checksum: u8,
+    pub global_checksum: u16,
+    pub computed_global_checksum: u16,
+}
+
+impl Header {
+    pub fn parse(bin: &[u8]) -> Result<Self, RomError> {
+        if bin.len() < 0x150 {
+            return Err(RomError::TooSmall(bin.len()));
+        }
+
+        let cgb = match bin[0x143] {
+            0xc0 => CgbFlag::CgbOnly,
+            v if v & 0x80 != 0 => CgbFlag::Enhanced,
+            _ => CgbFlag::DmgOnly,
+        };
+
+        // newer carts steal the end of the title for a manufacturer code, there is no flag for
+        // it so guess from the contents like everyone else does
+        let manufacturer = <[u8; 4]>::try_from(&bin[0x13f..0x143]).unwrap();
+        let manufacturer = (cgb != CgbFlag::DmgOnly && manufacturer.iter().all(u8::is_ascii_uppercase))
+            .then_some(manufacturer);
+
+        let title_end = match (manufacturer, cgb) {
+            (Some(_), _) => 0x13f,
+            (None, CgbFlag::DmgOnly) => 0x144,
+            (None, _) => 0x143,
+        };
+        let title = &bin[0x134..title_end];
+        let title = &title[..title.iter().position(|c| *c == 0).unwrap_or(title.len())];
+        let title = title.iter().map(|c| if c.is_ascii_graphic() || *c == b' ' { *c as char } else { '?' }).collect::<String>();
+
+        let licensee = match bin[0x14b] {
+            0x33 => Licensee::New([bin[0x144], bin[0x145]]),
+            v => Licensee::Old(v),
+        };
+
+        let computed_header_checksum = bin[0x134..=0x14c].iter().fold(0_u8, |c, b| c.wrapping_sub(*b).wrapping_sub(1));
+        let computed_global_checksum = bin.iter()
+            .enumerate()
+            .filter(|(a, _)| !matches!(a, 0x14e | 0x14f))
+            .fold(0_u16, |c, (_, b)| c.wrapping_add(*b as u16));
+
+        Ok(Self {
+            entry_point: bin[0x100..0x104].try_into().unwrap(),
+            logo_valid: bin[0x104..0x134] == NINTENDO_LOGO,
+            title,
+            manufacturer,
+            cgb,
+            licensee,
+            sgb: bin[0x146] == 0x03,
+            cart_type: bin[0x147],
+            rom_size: bin[0x148],
+            ram_size: bin[0x149],
+            japanese: bin[0x14a] == 0x00,
+            version: bin[0x14c],
+            header_checksum: bin[0x14d],
+            computed_header_checksum,
+            global_checksum: u16::from_be_bytes([bin[0x14e], bin[0x14f]]),
+            computed_global_checksum,
+        })
+    }
+
+    pub fn rom_banks(&self) -> Option<usize> {
+        (self.rom_size <= 8).then(|| 2 << self.rom_size)
+    }
+
+    pub fn ram_banks(&self) -> Option<usize> {
+        match self.ram_size {
+            0x00 => Some(0),
+            0x02 => Some(1),
+            0x03 => Some(4),
+            0x05 => Some(8),
+            0x04 => Some(16),
+            _ => None,
+        }
+    }
+
+    pub fn header_checksum_valid(&self) -> bool { self.header_checksum == self.computed_header_checksum }
+
+    /// Real hardware never checks this one.
+    pub fn global_checksum_valid(&self) -> bool { self.global_checksum == self.computed_global_checksum }
+
+    pub fn cart_type_name(&self) -> &'static str {
+        match self.cart_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0b => "MMM01",
+            0x0c => "MMM01+RAM",
+            0x0d => "MMM01+RAM+BATTERY",
+            0x0f => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1a => "MBC5+RAM",
+            0x1b => "MBC5+RAM+BATTERY",
+            0x1c => "MBC5+RUMBLE",
+            0x1d => "MBC5+RUMBLE+RAM",
+            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xfc => "POCKET CAMERA",
+            0xfd => "BANDAI TAMA5",
+            0xfe => "HuC3",
+            0xff => "HuC1+RAM+BATTERY",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Title:           {}", self.title)?;
+
+        if let Some(m) = self.manufacturer {
+            writeln!(f, "Manufacturer:    {}", String::from_utf8_lossy(&m))?;
+        }
+
+        match self.licensee {
+            Licensee::Old(l) => writeln!(f, "Licensee:        {l:02x}")?,
+            Licensee::New(l) => writeln!(f, "Licensee:        {} (new)", String::from_utf8_lossy(&l))?,
+        }
+
+        writeln!(f, "CGB:             {}", match self.cgb {
+            CgbFlag::DmgOnly => "no",
+            CgbFlag::Enhanced => "enhanced",
+            CgbFlag::CgbOnly => "only",
+        })?;
+        writeln!(f, "SGB:             {}", if self.sgb { "yes" } else { "no" })?;
+        writeln!(f, "Cartridge type:  {:02x} ({})", self.cart_type, self.cart_type_name())?;
+
+        match self.rom_banks() {
+            Some(b) => writeln!(f, "ROM size:        {} KiB ({b} banks)", b * 16)?,
+            None => writeln!(f, "ROM size:        illegal ({:02x})", self.rom_size)?,
+        }
+
+        match self.ram_banks() {
+            Some(b) => writeln!(f, "RAM size:        {} KiB ({b} banks)", b * 8)?,
+            None => writeln!(f, "RAM size:        illegal ({:02x})", self.ram_size)?,
+        }
+
+        writeln!(f, "Region:          {}", if self.japanese { "Japan" } else { "overseas" })?;
+        writeln!(f, "Version:         {}", self.version)?;
+        writeln!(f, "Nintendo logo:   {}", if self.logo_valid { "ok" } else { "bad" })?;
+        writeln!(
+            f, "Header checksum: {:02x} ({})",
+            self.header_checksum,
+            if self.header_checksum_valid() { "ok" } else { "bad" },
+        )?;
+        write!(
+            f, "Global checksum: {:04x} ({})",
+            self.global_checksum,
+            if self.global_checksum_valid() { "ok" } else { "bad" },
+        )
+    }
+}
@@ -0,0 +1,229 @@
+/// Colors a CGB gives a game made for the DMG, in the ppu's 15 bit format. Each list is indexed
+/// by the shade the game's own palette registers pick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatPalette {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+/// The palettes that can be picked by holding buttons while the boot logo shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    Brown,
+    Red,
+    DarkBrown,
+    Blue,
+    DarkBlue,
+    Gray,
+    Pastel,
+    Orange,
+    Yellow,
+    Green,
+    DarkGreen,
+    Inverted,
+}
+
+/// The colors of the boot rom, four to a palette. Combinations pick three runs of four from it,
+/// which are usually whole palettes.
+const COLORS: [u16; 120] = [
+    0x7fff, 0x32bf, 0x00d0, 0x0000,  0x639f, 0x4279, 0x15b0, 0x04cb,
+    0x7fff, 0x6e31, 0x454a, 0x0000,  0x7fff, 0x1bef, 0x0200, 0x0000,
+    0x7fff, 0x421f, 0x1cf2, 0x0000,  0x7fff, 0x5294, 0x294a, 0x0000,
+    0x7fff, 0x03ff, 0x012f, 0x0000,  0x7fff, 0x03ef, 0x01d6, 0x0000,
+    0x7fff, 0x42b5, 0x3dc8, 0x0000,  0x7e74, 0x03ff, 0x0180, 0x0000,
+    0x67ff, 0x77ac, 0x1a13, 0x2d6b,  0x7ed6, 0x4bff, 0x2175, 0x0000,
+    0x53ff, 0x4a5f, 0x7e52, 0x0000,  0x4fff, 0x7ed2, 0x3a4c, 0x1ce0,
+    0x03ed, 0x7fff, 0x255f, 0x0000,  0x036a, 0x021f, 0x03ff, 0x7fff,
+    0x7fff, 0x01df, 0x0112, 0x0000,  0x231f, 0x035f, 0x00f2, 0x0009,
+    0x7fff, 0x03ea, 0x011f, 0x0000,  0x299f, 0x001a, 0x000c, 0x0000,
+    0x7fff, 0x027f, 0x001f, 0x0000,  0x7fff, 0x03e0, 0x0206, 0x0120,
+    0x7fff, 0x7eeb, 0x001f, 0x7c00,  0x7fff, 0x3fff, 0x7e00, 0x001f,
+    0x7fff, 0x03ff, 0x001f, 0x0000,  0x03ff, 0x001f, 0x000c, 0x0000,
+    0x7fff, 0x033f, 0x0193, 0x0000,  0x0000, 0x4200, 0x037f, 0x7fff,
+    0x7fff, 0x7e8c, 0x7c00, 0x0000,  0x7fff, 0x1bef, 0x6180, 0x0000,
+];
+
+/// Whole palettes for obj0, obj1 and bg, as offsets into `COLORS`.
+const fn pals(obj0: usize, obj1: usize, bg: usize) -> [usize; 3] {
+    [obj0 * 4, obj1 * 4, bg * 4]
+}
+
+/// What each palette index of the boot rom stands for. Three of them start the objects one color
+/// early, which the boot rom does too.
+const COMBINATIONS: [[usize; 3]; 51] = [
+    pals(4, 4, 29), pals(18, 18, 18), pals(20, 20, 20), pals(24, 24, 24), pals(9, 9, 9),
+    pals(0, 0, 0), pals(27, 27, 27), pals(5, 5, 5), pals(12, 12, 12), pals(26, 26, 26),
+    pals(16, 8, 8), pals(4, 28, 28), pals(4, 2, 2), pals(3, 4, 4), pals(4, 29, 29),
+    pals(28, 4, 28), pals(2, 17, 2), pals(16, 16, 8), pals(4, 4, 7), pals(4, 4, 18),
+    pals(4, 4, 20), pals(19, 19, 9), [15, 15, 44], pals(17, 17, 2), pals(4, 4, 2),
+    pals(4, 4, 3), pals(28, 28, 0), pals(3, 3, 0), pals(0, 0, 1), pals(18, 22, 18),
+    pals(20, 22, 20), pals(24, 22, 24), pals(16, 22, 8), pals(17, 4, 13), [111, 0, 56],
+    [111, 16, 60], pals(19, 22, 9), pals(16, 28, 10), pals(4, 23, 28), pals(17, 22, 2),
+    pals(4, 0, 2), pals(4, 28, 3), pals(28, 3, 0), pals(3, 28, 4), pals(21, 28, 4),
+    pals(3, 28, 0), pals(25, 3, 28), pals(0, 28, 8), pals(4, 3, 28), pals(28, 3, 6),
+    pals(4, 28, 29),
+];
+
+fn combination(index: usize) -> CompatPalette {
+    let [obj0, obj1, bg] = COMBINATIONS[index].map(|at| COLORS[at..at + 4].try_into().unwrap());
+    CompatPalette { bg, obj0, obj1 }
+}
+
+impl Preset {
+    pub const ALL: [Self; 12] = [
+        Self::Brown, Self::Red, Self::DarkBrown, Self::Blue, Self::DarkBlue, Self::Gray,
+        Self::Pastel, Self::Orange, Self::Yellow, Self::Green, Self::DarkGreen, Self::Inverted,
+    ];
+
+    pub fn palette(self) -> CompatPalette {
+        combination(match self {
+            Self::Brown => 5,
+            Self::Red => 43,
+            Self::DarkBrown => 28,
+            Self::Blue => 48,
+            Self::DarkBlue => 40,
+            Self::Gray => 7,
+            Self::Pastel => 8,
+            Self::Orange => 3,
+            Self::Yellow => 49,
+            Self::Green => 1,
+            Self::DarkGreen => 0,
+            Self::Inverted => 6,
+        })
+    }
+
+    /// The preset picked by the buttons held at boot, in the same bit order as the keys given to
+    /// [`crate::Gameboy::new`].
+    pub fn from_keys(keys: u8) -> Option<Self> {
+        // a direction, optionally with a or b
+        Some(match keys & 0x3f {
+            0x04 => Self::Brown,
+            0x14 => Self::Red,
+            0x24 => Self::DarkBrown,
+            0x02 => Self::Blue,
+            0x12 => Self::DarkBlue,
+            0x22 => Self::Gray,
+            0x08 => Self::Pastel,
+            0x18 => Self::Orange,
+            0x28 => Self::Yellow,
+            0x01 => Self::Green,
+            0x11 => Self::DarkGreen,
+            0x21 => Self::Inverted,
+            _ => return None,
+        })
+    }
+}
+
+/// Title checksums of the games the boot rom knows and the palette index each one gets, in the
+/// boot rom's order. Checksums shared by several games also need the fourth title letter.
+const TITLES: [(u8, Option<u8>, usize); 93] = [
+    (0x88, None, 4),            // ALLEY WAY
+    (0x16, None, 5),            // YAKUMAN
+    (0x36, None, 35),           // BASEBALL
+    (0xd1, None, 34),           // TENNIS
+    (0xdb, None, 3),            // TETRIS
+    (0xf2, None, 31),           // QIX
+    (0x3c, None, 15),           // DR.MARIO
+    (0x8c, None, 10),           // RADARMISSION
+    (0x92, None, 5),            // F1RACE
+    (0x3d, None, 19),           // YOSSY NO TAMAGO
+    (0x5c, None, 36),           // HOSHINOKA-BI
+    (0x58, None, 7),            // X
+    (0xc9, None, 37),           // MARIOLAND2
+    (0x3e, None, 30),           // YOSSY NO COOKIE
+    (0x70, None, 44),           // ZELDA
+    (0x1d, None, 21),           // KIRBY'S PINBALL
+    (0x59, None, 32),           // SUPERMARIOLAND3
+    (0x69, None, 31),           // TETRIS FLASH
+    (0x19, None, 20),           // DONKEY KONG
+    (0x35, None, 5),            // MARIO'S PICROSS
+    (0xa8, None, 33),
+    (0x14, None, 13),           // POKEMON RED
+    (0xaa, None, 14),           // POKEMON GREEN
+    (0x75, None, 5),            // PICROSS 2
+    (0x95, None, 29),           // YOSSY NO PANEPON
+    (0x99, None, 5),            // KIRAKIRA KIDS
+    (0x34, None, 18),           // GAMEBOY GALLERY
+    (0x6f, None, 9),            // POCKETCAMERA
+    (0x15, None, 3),
+    (0xff, None, 2),            // BALLOON KID
+    (0x97, None, 26),           // KINGOFTHEZOO
+    (0x4b, None, 25),           // DMG FOOTBALL
+    (0x90, None, 25),           // WORLD CUP
+    (0x17, None, 41),           // OTHELLO
+    (0x10, None, 42),           // SUPER RC PRO-AM
+    (0x39, None, 26),           // DYNABLASTER
+    (0xf7, None, 45),           // BOY AND BLOB GB2
+    (0xf6, None, 42),           // MEGAMAN
+    (0xa2, None, 45),           // STAR WARS-NOA
+    (0x49, None, 36),           // KIRBY DREAM LAND
+    (0x4e, None, 38),           // WAVERACE
+    (0x43, None, 26),
+    (0x68, None, 42),           // LOLO2
+    (0xe0, None, 30),           // YOSHI'S COOKIE
+    (0x8b, None, 41),           // MYSTIC QUEST
+    (0xf0, None, 34),
+    (0xce, None, 34),           // TOPRANKINGTENNIS
+    (0x0c, None, 5),            // MANSELL
+    (0x29, None, 42),           // MEGAMAN3
+    (0xe8, None, 6),            // SPACE INVADERS
+    (0xb7, None, 5),            // GAME&WATCH
+    (0x86, None, 33),           // DONKEYKONGLAND95
+    (0x9a, None, 25),           // ASTEROIDS/MISCMD
+    (0x52, None, 42),           // STREET FIGHTER 2
+    (0x01, None, 42),           // DEFENDER/JOUST
+    (0x9d, None, 40),           // KILLERINSTINCT95
+    (0x71, None, 2),            // TETRIS BLAST
+    (0x9c, None, 16),           // PINOCCHIO
+    (0xbd, None, 25),
+    (0x5d, None, 42),           // BA.TOSHINDEN
+    (0x6d, None, 42),           // NETTOU KOF 95
+    (0x67, None, 5),            // STAR STACKER
+    (0x3f, None, 0),            // TETRIS PLUS
+    (0x6b, None, 39),           // DONKEYKONGLAND 3
+    (0xb3, Some(b'B'), 36),     // KIRBY2
+    (0x46, Some(b'E'), 22),     // SUPER MARIOLAND
+    (0x28, Some(b'F'), 25),     // GOLF
+    (0xa5, Some(b'A'), 6),      // SOLARSTRIKER
+    (0xc6, Some(b'A'), 32),     // GBWARS
+    (0xd3, Some(b'R'), 12),     // KAERUNOTAMENI
+    (0x27, Some(b'B'), 36),     // KIRBY BLOCKBALL
+    (0x61, Some(b'E'), 11),     // POKEMON BLUE
+    (0x18, Some(b'K'), 39),     // DONKEYKONGLAND
+    (0x66, Some(b'E'), 18),     // GAMEBOY GALLERY2
+    (0x6a, Some(b'K'), 39),     // DONKEYKONGLAND 2
+    (0xbf, Some(b' '), 24),     // KID ICARUS
+    (0x0d, Some(b'R'), 31),     // TETRIS2
+    (0xf4, Some(b'-'), 50),     // PAC-IN-TIME
+    (0xb3, Some(b'U'), 17),     // MOGURANYA
+    (0x46, Some(b'R'), 46),     // METROID2
+    (0x28, Some(b'A'), 6),
+    (0xa5, Some(b'R'), 27),
+    (0xc6, Some(b' '), 0),
+    (0xd3, Some(b'I'), 47),     // WARIOLAND2
+    (0x27, Some(b'N'), 41),
+    (0x61, Some(b'A'), 41),
+    (0x18, Some(b'I'), 0),      // WARIO BLAST
+    (0x66, Some(b'L'), 0),
+    (0x6a, Some(b'I'), 19),     // MARIO & YOSHI
+    (0xbf, Some(b'C'), 34),     // SOCCER
+    (0x0d, Some(b'E'), 23),
+    (0xf4, Some(b' '), 18),     // KID DRACULA
+    (0xb3, Some(b'R'), 29),     // TETRIS ATTACK
+];
+
+/// The palette the boot rom picks for a game from its header, only titles from Nintendo are
+/// looked up and everything else gets the default.
+pub fn for_rom(rom: &[u8]) -> CompatPalette {
+    let default = combination(0);
+
+    let nintendo = rom[0x14b] == 0x01 || (rom[0x14b] == 0x33 && &rom[0x144..0x146] == b"01");
+    if !nintendo { return default; }
+
+    let sum = rom[0x134..0x144].iter().fold(0_u8, |s, b| s.wrapping_add(*b));
+
+    TITLES.iter()
+        .find(|(c, letter, _)| *c == sum && letter.is_none_or(|l| l == rom[0x137]))
+        .map_or(default, |&(.., p)| combination(p))
+}
@@ -0,0 +1,441 @@
+//! A stub for the GDB remote serial protocol, so a debugger front end can attach over TCP.
+//!
+//! The stub is driven from the loop that runs the emulator, by calling [`GdbStub::run`] in place
+//! of stepping. That works the same for a frontend and a headless instance, and the Game Boy
+//! runs freely while nothing is attached.
+
+use std::{io::{self, Read, Write}, net::*, sync::mpsc, thread};
+
+use crate::{debug::*, Gameboy};
+
+/// Names the registers in the order of the `g` packet, gdb has no idea what an SM83 is.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gamewaifu.sm83.core">
+    <flags id="sm83_flags" size="1">
+      <field name="c" start="4" end="4"/>
+      <field name="h" start="5" end="5"/>
+      <field name="n" start="6" end="6"/>
+      <field name="z" start="7" end="7"/>
+    </flags>
+    <reg name="a" bitsize="8" regnum="0"/>
+    <reg name="f" bitsize="8" type="sm83_flags"/>
+    <reg name="b" bitsize="8"/>
+    <reg name="c" bitsize="8"/>
+    <reg name="d" bitsize="8"/>
+    <reg name="e" bitsize="8"/>
+    <reg name="h" bitsize="8"/>
+    <reg name="l" bitsize="8"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="ime" bitsize="8"/>
+    <reg name="ie" bitsize="8"/>
+    <reg name="if" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/// The size of each register in the `g` packet, in bytes.
+const REGS: [usize; 13] = [1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 1, 1, 1];
+
+enum Event {
+    Packet(String),
+    /// The client hit ctrl-c.
+    Interrupt,
+}
+
+struct Client {
+    stream: TcpStream,
+    rx: mpsc::Receiver<Event>,
+    ack: bool,
+}
+
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<Client>,
+    /// Whether the client stopped the Game Boy, until it continues.
+    stopped: bool,
+    /// Whether the cpu was stopped mid instruction since it locked up on an illegal opcode,
+    /// which is reported as SIGILL.
+    locked: bool,
+}
+
+impl GdbStub {
+    /// Listens for a client on the loopback interface, without waiting for one.
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self { listener, client: None, stopped: false, locked: false })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Answers the client, then runs for up to `dots` dots like [`Gameboy::step`] unless the
+    /// client has it stopped.
+    pub fn run(&mut self, gb: &mut Gameboy, dots: usize) {
+        self.accept(gb);
+        self.serve(gb);
+
+        if self.client.is_none() {
+            for _ in 0..dots { gb.step(); }
+            return;
+        }
+
+        if self.stopped { return; }
+
+        if let Some(stop) = gb.run_until_break(dots) {
+            self.stopped = true;
+            self.send(&stop_reply(stop));
+        }
+    }
+
+    fn accept(&mut self, gb: &mut Gameboy) {
+        if self.client.is_some() { return; }
+
+        let Ok((stream, _)) = self.listener.accept() else { return };
+        let Ok(client) = Client::new(stream) else { return };
+
+        // a client expects to find the target stopped
+        self.client = Some(client);
+        self.stopped = true;
+        gb.set_debugger(Some(Debugger::new()));
+        self.locked = !to_boundary(gb);
+    }
+
+    fn detach(&mut self, gb: &mut Gameboy) {
+        self.client = None;
+        self.stopped = false;
+        gb.set_debugger(None);
+    }
+
+    fn serve(&mut self, gb: &mut Gameboy) {
+        loop {
+            let Some(client) = &mut self.client else { return };
+
+            match client.rx.try_recv() {
+                Ok(Event::Packet(p)) => {
+                    if client.ack { client.write(b"+"); }
+                    if let Some(reply) = self.packet(gb, &p) { self.send(&reply); }
+                },
+                Ok(Event::Interrupt) if !self.stopped => {
+                    self.stopped = true;
+                    self.locked = !to_boundary(gb);
+                    self.send(if self.locked { "S04" } else { "S02" });
+                },
+                Ok(Event::Interrupt) => {},
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => self.detach(gb),
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) {
+        let Some(client) = &mut self.client else { return };
+
+        let mut body = Vec::with_capacity(data.len());
+        for b in data.bytes() {
+            match b {
+                b'#' | b'$' | b'}' | b'*' => body.extend([b'}', b ^ 0x20]),
+                _ => body.push(b),
+            }
+        }
+
+        let sum = body.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+        let mut packet = vec![b'$'];
+        packet.extend(body);
+        packet.extend(format!("#{sum:02x}").bytes());
+        client.write(&packet);
+    }
+
+    /// Handles a packet, returns the reply if there is one. Anything not known gets an empty
+    /// reply, which tells the client it isn't supported.
+    fn packet(&mut self, gb: &mut Gameboy, p: &str) -> Option<String> {
+        let (cmd, args) = p.split_at(p.chars().next().map_or(0, char::len_utf8));
+
+        let reply = match cmd {
+            "?" => if self.locked { "S04" } else { "S05" }.into(),
+            "g" => (0..REGS.len()).map(|n| reg_hex(gb, n)).collect(),
+            "G" => {
+                let mut at = 0;
+                for (n, len) in REGS.iter().enumerate() {
+                    let Some(v) = args.get(at..at + len * 2).and_then(le_hex) else { return Some("E01".into()) };
+                    set_reg(gb, n, v);
+                    at += len * 2;
+                }
+
+                "OK".into()
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGS.len() => reg_hex(gb, n),
+                _ => "E01".into(),
+            },
+            "P" => {
+                let Some((n, v)) = args.split_once('=') else { return Some("E01".into()) };
+                match (usize::from_str_radix(n, 16), le_hex(v)) {
+                    (Ok(n), Some(v)) if n < REGS.len() => {
+                        set_reg(gb, n, v);
+                        "OK".into()
+                    },
+                    _ => "E01".into(),
+                }
+            },
+            "m" => {
+                let Some((addr, len)) = addr_len(args) else { return Some("E01".into()) };
+                (0..len).map(|i| format!("{:02x}", gb.peek(addr.wrapping_add(i)))).collect()
+            },
+            "M" => {
+                let Some((at, data)) = args.split_once(':') else { return Some("E01".into()) };
+                let Some((addr, len)) = addr_len(at) else { return Some("E01".into()) };
+                let Some(bytes) = hex_bytes(data).filter(|b| b.len() == len as usize) else { return Some("E01".into()) };
+
+                for (i, b) in bytes.into_iter().enumerate() { gb.poke(addr.wrapping_add(i as u16), b); }
+                "OK".into()
+            },
+            "Z" | "z" => self.breakpoint(gb, cmd == "Z", args),
+            "c" | "s" => {
+                // continuing somewhere else isn't supported, gdb only asks when told to jump
+                gb.resume(if cmd == "c" { Step::Continue } else { Step::Into });
+                self.stopped = false;
+                self.locked = false;
+                return None;
+            },
+            "D" => {
+                self.send("OK");
+                self.detach(gb);
+                return None;
+            },
+            "k" => {
+                self.detach(gb);
+                return None;
+            },
+            "H" | "T" => "OK".into(),
+            "q" | "Q" => query(p),
+            _ => String::new(),
+        };
+
+        if p == "QStartNoAckMode" {
+            self.send(&reply);
+            if let Some(c) = &mut self.client { c.ack = false; }
+            return None;
+        }
+
+        Some(reply)
+    }
+
+    /// Adds or removes a breakpoint or watchpoint from `Z` and `z` packets.
+    fn breakpoint(&mut self, gb: &mut Gameboy, add: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+        let (Some(kind), Some(addr)) = (parts.next(), parts.next().and_then(|a| u16::from_str_radix(a, 16).ok())) else {
+            return "E01".into();
+        };
+
+        let Some(d) = gb.debugger() else { return "E01".into() };
+
+        match kind {
+            // there are no hardware breakpoints, but they work the same
+            "0" | "1" => {
+                let bp = Breakpoint { pc: addr, bank: None, cond: None };
+                if add {
+                    d.breakpoints.push(bp);
+                } else if let Some(i) = d.breakpoints.iter().position(|b| *b == bp) {
+                    d.breakpoints.remove(i);
+                }
+            },
+            "2" | "3" | "4" => {
+                let wp = Watchpoint { addr, read: kind != "2", write: kind != "3" };
+                if add {
+                    d.watchpoints.push(wp);
+                } else if let Some(i) = d.watchpoints.iter().position(|w| *w == wp) {
+                    d.watchpoints.remove(i);
+                }
+            },
+            _ => return String::new(),
+        }
+
+        "OK".into()
+    }
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+
+        let (tx, rx) = mpsc::channel();
+        let reader = stream.try_clone()?;
+        thread::spawn(move || read_packets(reader, tx));
+
+        Ok(Self { stream, rx, ack: true })
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        // a client that went away shows up as a disconnect on the reading side
+        let _ = self.stream.write_all(data);
+    }
+}
+
+/// Splits what the client sends into packets until it disconnects. Checksums aren't checked,
+/// TCP already does that.
+fn read_packets(stream: TcpStream, tx: mpsc::Sender<Event>) {
+    let mut bytes = io::BufReader::new(stream).bytes().map_while(Result::ok);
+
+    while let Some(b) = bytes.next() {
+        let event = match b {
+            0x03 => Event::Interrupt,
+            b'$' => {
+                let mut packet = Vec::new();
+                let mut escaped = false;
+
+                for b in bytes.by_ref() {
+                    match (escaped, b) {
+                        (true, _) => {
+                            packet.push(b ^ 0x20);
+                            escaped = false;
+                        },
+                        (false, b'}') => escaped = true,
+                        (false, b'#') => break,
+                        (false, _) => packet.push(b),
+                    }
+                }
+
+                let _checksum = (bytes.next(), bytes.next());
+                Event::Packet(String::from_utf8_lossy(&packet).into_owned())
+            },
+            // acks, and anything between packets
+            _ => continue,
+        };
+
+        if tx.send(event).is_err() { return; }
+    }
+}
+
+fn query(p: &str) -> String {
+    if p.starts_with("qSupported") {
+        return "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".into();
+    }
+
+    if let Some(args) = p.strip_prefix("qXfer:features:read:target.xml:") {
+        let Some((offset, len)) = args.split_once(',') else { return "E01".into() };
+        let (Ok(offset), Ok(len)) = (usize::from_str_radix(offset, 16), usize::from_str_radix(len, 16)) else {
+            return "E01".into();
+        };
+
+        let rest = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+        return match rest.get(..len) {
+            Some(part) if len < rest.len() => format!("m{part}"),
+            _ => format!("l{rest}"),
+        };
+    }
+
+    match p {
+        "QStartNoAckMode" => "OK",
+        "qAttached" => "1",
+        "qC" => "QC1",
+        "qfThreadInfo" => "m1",
+        "qsThreadInfo" => "l",
+        _ => "",
+    }.into()
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Watchpoint { addr, write: true, .. } => format!("T05watch:{addr:x};"),
+        Stop::Watchpoint { addr, write: false, .. } => format!("T05rwatch:{addr:x};"),
+        Stop::Breakpoint(_) | Stop::Condition(_) | Stop::Step => "S05".into(),
+    }
+}
+
+/// The most cycles an instruction can take to finish, the longest one waiting out a general
+/// purpose dma of 128 blocks in double speed.
+const MAX_CYCLES: usize = 4 * (6 + 128 * 16);
+
+/// Runs to the end of the instruction underway, where the registers are all up to date. Returns
+/// false if it never ends, since the cpu locked up.
+fn to_boundary(gb: &mut Gameboy) -> bool {
+    for _ in 0..MAX_CYCLES {
+        if gb.cpu.get_internals().cycles == 1 { return true; }
+        gb.cpu.step();
+    }
+
+    gb.cpu.get_internals().cycles == 1
+}
+
+fn reg(gb: &mut Gameboy, n: usize) -> u16 {
+    let s = gb.cpu.get_state();
+
+    match n {
+        0 => s.a as u16,
+        1 => s.f as u16,
+        2 => s.b as u16,
+        3 => s.c as u16,
+        4 => s.d as u16,
+        5 => s.e as u16,
+        6 => s.h as u16,
+        7 => s.l as u16,
+        8 => s.sp,
+        9 => Reg::Pc.get(&s),
+        10 => gb.cpu.get_internals().ime as u16,
+        11 => gb.cpu.ints.enabled as u16,
+        _ => gb.cpu.ints.pending as u16,
+    }
+}
+
+fn set_reg(gb: &mut Gameboy, n: usize, v: u16) {
+    let mut s = gb.cpu.get_state();
+
+    match n {
+        0 => s.a = v as u8,
+        // the low bits of f don't exist
+        1 => s.f = v as u8 & 0xf0,
+        2 => s.b = v as u8,
+        3 => s.c = v as u8,
+        4 => s.d = v as u8,
+        5 => s.e = v as u8,
+        6 => s.h = v as u8,
+        7 => s.l = v as u8,
+        8 => s.sp = v,
+        // the cpu fetches the opcode ahead of running it
+        9 => {
+            s.ir = gb.peek(v);
+            s.pc = v.wrapping_add(1);
+        },
+        10 => {
+            let mut i = gb.cpu.get_internals();
+            i.ime = v & 1 != 0;
+            gb.cpu.set_internals(&i);
+        },
+        11 => gb.cpu.ints.enabled = v as u8,
+        _ => gb.cpu.ints.pending = v as u8,
+    }
+
+    gb.cpu.set_state(&s);
+}
+
+/// A register in target byte order, which is little endian.
+fn reg_hex(gb: &mut Gameboy, n: usize) -> String {
+    let v = reg(gb, n);
+    if REGS[n] == 2 { format!("{:02x}{:02x}", v as u8, v >> 8) } else { format!("{:02x}", v as u8) }
+}
+
+fn le_hex(s: &str) -> Option<u16> {
+    let bytes = hex_bytes(s)?;
+    (1..=2).contains(&bytes.len()).then(|| bytes.iter().rev().fold(0, |v, b| (v << 8) | *b as u16))
+}
+
+fn hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) { return None; }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn addr_len(s: &str) -> Option<(u16, u16)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((u16::from_str_radix(addr, 16).ok()?, u16::from_str_radix(len, 16).ok()?))
+}
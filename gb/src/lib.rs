@@ -2,46 +2,122 @@ use std::sync::{atomic::*, *};
 
 pub mod apu;
 pub mod bus;
+pub mod cartridge;
+pub mod compat;
+pub mod gdb;
+pub mod link;
 pub mod mapper;
 pub mod ppu;
+pub mod rtc;
+pub mod serial;
+pub mod sgb;
+pub mod state;
+
+pub use sm83::{debug, disasm, trace};
+
+use state::Snapshot;
 
 pub const CLOCK_HZ: usize = 4194304;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Cgb,
+    Sgb,
+}
+
+impl Model {
+    /// The model a cartridge asks for, a CGB for anything that knows about one and then a SGB.
+    pub fn from_header(header: &cartridge::Header) -> Self {
+        // sgb functions are only unlocked for carts that use the new licensee code
+        match (header.cgb, header.sgb, header.licensee) {
+            (cartridge::CgbFlag::DmgOnly, true, cartridge::Licensee::New(_)) => Self::Sgb,
+            (cartridge::CgbFlag::DmgOnly, ..) => Self::Dmg,
+            _ => Self::Cgb,
+        }
+    }
+}
+
 pub struct Gameboy<'a> {
     cpu: sm83::Sm83<bus::Bus<'a>>,
 }
 
 impl<'a> Gameboy<'a> {
     pub fn new(
+        model: Model,
         mapper: mapper::Mapper,
         boot_rom: Option<Box<[u8]>>,
-        framebuffer: Arc<Mutex<[u8; 160 * 144]>>,
+        framebuffer: ppu::Framebuffer,
         aud_callback: apu::Callback<'a>,
         keys: Arc<AtomicU8>,
     ) -> Self {
         let have_br = boot_rom.is_some();
-        let ppu = ppu::Ppu::new(framebuffer);
+        let held = keys.load(Ordering::Relaxed);
+        let cgb = model == Model::Cgb;
+        let ppu = ppu::Ppu::new(framebuffer, cgb);
         let apu = apu::Apu::new(aud_callback);
-        let bus = bus::Bus::new(ppu, apu, mapper, boot_rom, keys);
+        let mut bus = bus::Bus::new(ppu, apu, mapper, boot_rom, keys);
+        if model == Model::Sgb { bus.sgb = Some(Box::new(sgb::Sgb::new())); }
+
         let mut cpu = sm83::Sm83::new(bus);
 
         if !have_br {
             let checksum = cpu.bus.mapper.load(0x014d);
 
-            cpu.set_state(&sm83::cpu::State {
-                a: 0x01,
-                b: 0x00,
-                c: 0x13,
-                d: 0x00,
-                e: 0xd8,
-                f: 0x80 | if checksum != 0 { 0x30 } else { 0x00 },
-                h: 0x01,
-                l: 0x4d,
-
-                pc: 0x0100,
-                sp: 0xfffe,
-                ir: 0,
-            });
+            if cgb {
+                cpu.set_state(&sm83::cpu::State {
+                    a: 0x11,
+                    b: 0x00,
+                    c: 0x00,
+                    d: 0xff,
+                    e: 0x56,
+                    f: 0x80,
+                    h: 0x00,
+                    l: 0x0d,
+
+                    pc: 0x0100,
+                    sp: 0xfffe,
+                    ir: 0,
+                });
+
+                // what the boot rom would have picked for a dmg game
+                cpu.bus.ppu.dmg_compat = cpu.bus.mapper.load(0x0143) & 0x80 == 0;
+                if cpu.bus.ppu.dmg_compat {
+                    let pal = compat::Preset::from_keys(held)
+                        .map_or_else(|| compat::for_rom(cpu.bus.mapper.rom()), compat::Preset::palette);
+                    cpu.bus.ppu.set_compat_palette(&pal);
+                }
+            } else if model == Model::Sgb {
+                cpu.set_state(&sm83::cpu::State {
+                    a: 0x01,
+                    b: 0x00,
+                    c: 0x14,
+                    d: 0x00,
+                    e: 0x00,
+                    f: 0x00,
+                    h: 0xc0,
+                    l: 0x60,
+
+                    pc: 0x0100,
+                    sp: 0xfffe,
+                    ir: 0,
+                });
+            } else {
+                cpu.set_state(&sm83::cpu::State {
+                    a: 0x01,
+                    b: 0x00,
+                    c: 0x13,
+                    d: 0x00,
+                    e: 0xd8,
+                    f: 0x80 | if checksum != 0 { 0x30 } else { 0x00 },
+                    h: 0x01,
+                    l: 0x4d,
+
+                    pc: 0x0100,
+                    sp: 0xfffe,
+                    ir: 0,
+                });
+            }
 
             cpu.div = 0xabff;
             cpu.bus.tac = 0xf8;
@@ -83,6 +159,10 @@ impl<'a> Gameboy<'a> {
             cpu.bus.ppu.ly = 153;
             cpu.bus.ppu.bgp = 0xfc;
             cpu.bus.ppu.hsync = 132;
+
+            // the nop in `ir` stands in for the boot rom's last cycle, which fetches the opcode at
+            // 0x0100. Running it here keeps it out of traces and away from breakpoints.
+            cpu.step();
         }
 
         Self {
@@ -90,8 +170,159 @@ impl<'a> Gameboy<'a> {
         }
     }
 
-    pub fn step(&mut self) { self.cpu.step(); }
+    /// Runs for one dot, which is two cpu cycles in double speed.
+    pub fn step(&mut self) {
+        self.cpu.step();
+        if self.cpu.bus.double_speed { self.cpu.step(); }
+    }
+
+    /// Recolors a DMG game running on a CGB, does nothing otherwise.
+    pub fn set_compat_palette(&mut self, palette: &compat::CompatPalette) {
+        if self.cpu.bus.ppu.dmg_compat { self.cpu.bus.ppu.set_compat_palette(palette); }
+    }
+
+    pub fn model(&self) -> Model {
+        match (self.cpu.bus.ppu.cgb, &self.cpu.bus.sgb) {
+            (true, _) => Model::Cgb,
+            (false, Some(_)) => Model::Sgb,
+            (false, None) => Model::Dmg,
+        }
+    }
+
+    /// Where a super game boy draws the game with its border, nothing is drawn without one.
+    pub fn set_sgb_framebuffer(&mut self, framebuffer: Option<sgb::Framebuffer>) {
+        if let Some(sgb) = &mut self.cpu.bus.sgb { sgb.screen = framebuffer; }
+    }
+
+    pub fn header(&self) -> cartridge::Header {
+        cartridge::Header::parse(self.cpu.bus.mapper.rom()).unwrap()
+    }
 
     pub fn set_sram(&mut self, sram: &[u8]) { self.cpu.bus.mapper.set_sram(sram) }
     pub fn get_sram(&self) -> Option<&[u8]> { self.cpu.bus.mapper.get_sram() }
+    pub fn get_rtc(&self) -> Option<Vec<u8>> { self.cpu.bus.mapper.get_rtc() }
+
+    /// Plugs a cable into the link port, or unplugs it with `None`.
+    pub fn set_link_cable(&mut self, cable: Option<Box<dyn serial::LinkCable + 'a>>) { self.cpu.bus.serial.cable = cable }
+
+    /// The ppu, to look at what it has without changing anything.
+    pub fn ppu(&self) -> &ppu::Ppu { &self.cpu.bus.ppu }
+
+    /// The cpu registers, `ir` is the opcode that runs next.
+    pub fn cpu_state(&self) -> sm83::cpu::State { self.cpu.get_state() }
+
+    /// Attaches a debugger, which only takes effect in `run_until_break`.
+    pub fn set_debugger(&mut self, debugger: Option<debug::Debugger>) { self.cpu.debugger = debugger }
+    pub fn debugger(&mut self) -> Option<&mut debug::Debugger> { self.cpu.debugger.as_mut() }
+
+    /// Sets how far the next `run_until_break` goes, after attaching a debugger or stopping.
+    pub fn resume(&mut self, step: debug::Step) { self.cpu.resume(step) }
+
+    /// Runs for up to `dots` dots like `step`, or until the debugger stops it before an
+    /// instruction.
+    pub fn run_until_break(&mut self, dots: usize) -> Option<debug::Stop> {
+        for _ in 0..dots {
+            for _ in 0..1 + self.cpu.bus.double_speed as usize {
+                if let Some(stop) = self.cpu.check_break() { return Some(stop); }
+                self.cpu.step();
+            }
+        }
+
+        None
+    }
+
+    /// The bank mapped at `a`, `None` where nothing is banked.
+    pub fn bank(&self, a: u16) -> Option<usize> { sm83::bus::Bus::bank(&self.cpu.bus, a) }
+
+    /// Reads memory like the cpu would, but without taking any time or touching anything. An
+    /// infrared port reads as dark.
+    pub fn peek(&mut self, a: u16) -> u8 { self.cpu.peek(a) }
+    pub fn poke(&mut self, a: u16, d: u8) { self.cpu.poke(a, d) }
+
+    /// Logs instructions as they run, or stops logging with `None`.
+    pub fn set_tracer(&mut self, tracer: Option<trace::Tracer>) { self.cpu.tracer = tracer }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = state::Writer::new();
+
+        // identifies the rom, from the title up to the global checksum
+        w.bytes(&self.cpu.bus.mapper.rom()[0x134..0x150]);
+        w.u8(self.model() as u8);
+
+        let s = self.cpu.get_state();
+        for r in [s.a, s.b, s.c, s.d, s.e, s.f, s.h, s.l, s.ir] { w.u8(r); }
+        w.u16(s.pc);
+        w.u16(s.sp);
+
+        let i = self.cpu.get_internals();
+        w.bool(i.ime);
+        w.usize(i.cycles);
+        w.bool(i.after_ei);
+        w.u8(match i.mode {
+            sm83::cpu::Mode::Normal => 0,
+            sm83::cpu::Mode::Halting => 1,
+            sm83::cpu::Mode::Stopped => 2,
+        });
+
+        w.usize(self.cpu.div);
+        w.u8(self.cpu.ints.pending);
+        w.u8(self.cpu.ints.enabled);
+
+        self.cpu.bus.save_state(&mut w);
+
+        w.finish()
+    }
+
+    /// Restores a state made by [`Self::save_state`] with the same rom. The header and rom are
+    /// checked before anything is touched, but a malformed body can leave the machine partially
+    /// restored.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), state::StateError> {
+        let mut r = state::Reader::new(data)?;
+
+        let mut id = [0; 0x1c];
+        r.bytes(&mut id)?;
+        if id != self.cpu.bus.mapper.rom()[0x134..0x150] {
+            return Err(state::StateError::RomMismatch);
+        }
+
+        if r.u8()? != self.model() as u8 {
+            return Err(state::StateError::ModelMismatch);
+        }
+
+        let mut regs = [0; 9];
+        for reg in regs.iter_mut() { *reg = r.u8()?; }
+        let [a, b, c, d, e, f, h, l, ir] = regs;
+
+        self.cpu.set_state(&sm83::cpu::State {
+            a, b, c, d, e,
+            f: f & 0xf0,
+            h, l,
+
+            pc: r.u16()?,
+            sp: r.u16()?,
+            ir,
+        });
+
+        let ime = r.bool()?;
+        let cycles = r.usize()?;
+        let after_ei = r.bool()?;
+        let mode = match r.u8()? {
+            0 => sm83::cpu::Mode::Normal,
+            1 => sm83::cpu::Mode::Halting,
+            2 => sm83::cpu::Mode::Stopped,
+            _ => return Err(state::StateError::Invalid("cpu mode")),
+        };
+
+        if cycles == 0 { return Err(state::StateError::Invalid("cpu cycle counter")); }
+
+        self.cpu.set_internals(&sm83::cpu::Internals { ime, cycles, after_ei, mode });
+
+        self.cpu.div = r.usize()?;
+        self.cpu.ints.pending = r.u8()?;
+        self.cpu.ints.enabled = r.u8()?;
+
+        self.cpu.bus.load_state(&mut r)?;
+
+        r.finish()
+    }
 }
@@ -0,0 +1,195 @@
+use std::{cell::RefCell, io::{self, Read, Write}, net::*, rc::Rc, sync::mpsc, thread};
+
+use crate::{serial::LinkCable, Gameboy};
+
+/// Two Game Boys with a cable between them, stepped in lockstep.
+pub struct LinkedPair<'a> {
+    pub a: Gameboy<'a>,
+    pub b: Gameboy<'a>,
+}
+
+#[derive(Default)]
+struct Wire {
+    /// The byte each side has armed on the external clock.
+    ready: [Option<u8>; 2],
+    /// A byte clocked into each side, waiting for its next poll.
+    incoming: [Option<u8>; 2],
+}
+
+struct End {
+    wire: Rc<RefCell<Wire>>,
+    side: usize,
+}
+
+impl LinkCable for End {
+    fn transfer(&mut self, data: u8) -> u8 {
+        let mut w = self.wire.borrow_mut();
+        let peer = self.side ^ 1;
+
+        match w.ready[peer].take() {
+            Some(d) => {
+                w.incoming[peer] = Some(data);
+                d
+            },
+            // the other side isn't listening, so its input stays pulled up
+            None => 0xff,
+        }
+    }
+
+    fn poll(&mut self, data: Option<u8>) -> Option<u8> {
+        let mut w = self.wire.borrow_mut();
+        let incoming = w.incoming[self.side].take();
+
+        // a byte clocked in while the port wasn't listening is lost
+        match (data, incoming) {
+            (Some(_), Some(d)) => {
+                w.ready[self.side] = None;
+                Some(d)
+            },
+            _ => {
+                w.ready[self.side] = data;
+                None
+            },
+        }
+    }
+}
+
+impl<'a> LinkedPair<'a> {
+    pub fn new(mut a: Gameboy<'a>, mut b: Gameboy<'a>) -> Self {
+        let wire = Rc::new(RefCell::new(Wire::default()));
+        a.set_link_cable(Some(Box::new(End { wire: Rc::clone(&wire), side: 0 })));
+        b.set_link_cable(Some(Box::new(End { wire, side: 1 })));
+
+        Self { a, b }
+    }
+
+    pub fn step(&mut self) {
+        self.a.step();
+        self.b.step();
+    }
+
+    /// Unplugs the cable and gives both back.
+    pub fn split(mut self) -> (Gameboy<'a>, Gameboy<'a>) {
+        self.a.set_link_cable(None);
+        self.b.set_link_cable(None);
+        (self.a, self.b)
+    }
+}
+
+const MAGIC: [u8; 4] = *b"GWLC";
+const PROTOCOL_VERSION: u8 = 1;
+
+/// A transfer clocked by the sender, answered with [`REPLY`].
+const TRANSFER: u8 = b'T';
+const REPLY: u8 = b'R';
+
+/// A link cable to another emulator over TCP.
+///
+/// Every byte clocked by one side is answered by the other before the transfer finishes, so the
+/// clocking side stalls for a round trip instead of losing bytes. When both sides clock a
+/// transfer at once the side that listened is the clock master: its transfer goes through and
+/// the other side takes the master's byte as its own answer.
+pub struct TcpCable {
+    stream: TcpStream,
+    rx: mpsc::Receiver<[u8; 2]>,
+    master: bool,
+    connected: bool,
+}
+
+impl TcpCable {
+    /// Waits for one peer on this machine to connect, which makes this side the clock master.
+    pub fn listen(port: u16) -> io::Result<Self> {
+        Self::listen_on((Ipv4Addr::LOCALHOST, port))
+    }
+
+    /// Like [`listen`](Self::listen), on any address, to let peers in from other machines.
+    pub fn listen_on(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        Self::handshake(stream, true)
+    }
+
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::handshake(TcpStream::connect(addr)?, false)
+    }
+
+    fn handshake(mut stream: TcpStream, master: bool) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+
+        let mut hello = [0; 6];
+        hello[..4].copy_from_slice(&MAGIC);
+        hello[4] = PROTOCOL_VERSION;
+        hello[5] = master as u8;
+        stream.write_all(&hello)?;
+
+        let mut peer = [0; 6];
+        stream.read_exact(&mut peer)?;
+
+        if peer[..4] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "peer is not a link cable"));
+        }
+
+        if peer[4] != PROTOCOL_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("peer speaks link protocol {}, expected {PROTOCOL_VERSION}", peer[4])));
+        }
+
+        if (peer[5] != 0) == master {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "both sides want to be the clock master"));
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || {
+            let mut msg = [0; 2];
+            while reader.read_exact(&mut msg).is_ok() && tx.send(msg).is_ok() {}
+        });
+
+        Ok(Self { stream, rx, master, connected: true })
+    }
+
+    fn send(&mut self, kind: u8, data: u8) {
+        if self.stream.write_all(&[kind, data]).is_err() {
+            self.connected = false;
+        }
+    }
+}
+
+impl LinkCable for TcpCable {
+    fn transfer(&mut self, data: u8) -> u8 {
+        if !self.connected { return 0xff; }
+
+        self.send(TRANSFER, data);
+
+        while self.connected {
+            match self.rx.recv() {
+                Ok([REPLY, d]) => return d,
+                // both sides clocked at once, the master drops the other transfer and still gets
+                // its reply, the other side answers the master's transfer instead of its own
+                Ok([TRANSFER, _]) if self.master => {},
+                Ok([TRANSFER, d]) => {
+                    self.send(REPLY, data);
+                    return d;
+                },
+                Ok(_) => {},
+                Err(_) => self.connected = false,
+            }
+        }
+
+        0xff
+    }
+
+    fn poll(&mut self, data: Option<u8>) -> Option<u8> {
+        while self.connected {
+            match self.rx.try_recv() {
+                Ok([TRANSFER, d]) => {
+                    self.send(REPLY, data.unwrap_or(0xff));
+                    return data.map(|_| d);
+                },
+                Ok(_) => {},
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => self.connected = false,
+            }
+        }
+
+        None
+    }
+}
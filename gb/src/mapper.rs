@@ -1,3 +1,26 @@
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+mod camera;
+mod huc3;
+mod mbc6;
+mod mbc7;
+mod tama5;
+
+pub use camera::{ImageSource, CAMERA_HEIGHT, CAMERA_WIDTH};
+
+/// Called with the new motor state whenever a rumble cart turns its motor on or off.
+pub type RumbleCallback = Box<dyn FnMut(bool) + Send>;
+
+/// Returns the tilt on the x and y axes in g, positive being right and down.
+pub type Accelerometer = Box<dyn Fn() -> (f32, f32) + Send>;
+
+/// The other end of the infrared port on HuC1 and HuC3 carts.
+pub trait Infrared: Send {
+    fn set_led(&mut self, on: bool);
+    /// Whether light is coming in right now.
+    fn receiving(&mut self) -> bool;
+}
+
 pub enum Mapper {
     None {
         rom: Vec<u8>,
@@ -13,7 +36,16 @@ pub enum Mapper {
         rom_bk: u8,
         ram_bk: u8,
         mode: bool,
-        rom_ext: bool,
+        multicart: bool,
+    },
+    Mbc2 {
+        rom: Vec<u8>,
+        ram: Vec<u8>, // 512 nibbles, one per byte
+
+        rom_mask: usize,
+
+        ram_en: bool,
+        rom_bk: u8,
     },
     Mbc3 {
         rom: Vec<u8>,
@@ -24,6 +56,8 @@ pub enum Mapper {
         ram_en: bool,
         rom_bk: u8,
         ram_bk: u8,
+
+        rtc: Option<crate::rtc::Rtc>,
     },
     Mbc5 {
         rom: Vec<u8>,
@@ -34,46 +68,129 @@ pub enum Mapper {
         ram_en: bool,
         rom_bk: u16,
         ram_bk: u8,
+
+        rumble: bool,
+        motor: bool,
+        on_rumble: Option<RumbleCallback>,
+    },
+    Mbc6(mbc6::Mbc6),
+    Mbc7(mbc7::Mbc7),
+    Mmm01 {
+        rom: Vec<u8>,
+        ram: Vec<u8>,
+
+        rom_mask: usize,
+
+        ram_en: bool,
+        /// Bits 0-6 of the rom bank, bits 5 and 6 are only writable before mapping.
+        rom_bk: u8,
+        rom_hi: u8,
+        ram_bk: u8,
+        ram_hi: u8,
+        /// Bits 1-4 of the rom bank that are frozen once mapped.
+        rom_bk_mask: u8,
+        mode: bool,
+        /// Starts out showing the menu in the last 32 KiB, mapping a game locks the outer bank.
+        mapped: bool,
     },
+    Huc1 {
+        rom: Vec<u8>,
+        ram: Vec<u8>,
+
+        rom_mask: usize,
+
+        ir_mode: bool,
+        rom_bk: u8,
+        ram_bk: u8,
+
+        infrared: Option<Box<dyn Infrared>>,
+        led: bool,
+    },
+    Huc3(huc3::Huc3),
+    Camera(camera::Camera),
+    Tama5(tama5::Tama5),
 }
 
-impl Mapper {
-    pub fn from_bin(bin: &[u8]) -> Self {
-        if bin.len() < 0x150 {
-            panic!("bin too smol");
-        }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomError {
+    TooSmall(usize),
+    ChecksumMismatch {
+        expected: u8,
+        computed: u8,
+    },
+    IllegalRomSize(u8),
+    IllegalRamSize(u8),
+    SizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    UnknownMapper(u8),
+}
 
-        let mut checksum = 0;
-        for a in 0x134..=0x14c {
-            checksum -= bin[a] + 1;
+impl core::fmt::Display for RomError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::TooSmall(len) => write!(f, "rom is too small to have a header ({len} bytes)"),
+            Self::ChecksumMismatch { expected, computed } => write!(f, "header checksum mismatch (expected {expected:02x}, computed {computed:02x})"),
+            Self::IllegalRomSize(v) => write!(f, "illegal rom size {v:02x}"),
+            Self::IllegalRamSize(v) => write!(f, "illegal ram size {v:02x}"),
+            Self::SizeMismatch { expected, actual } => write!(f, "rom is {actual} bytes but the header says {expected} bytes"),
+            Self::UnknownMapper(m) => write!(f, "unknown mapper {m:02x}"),
         }
+    }
+}
 
-        if checksum != bin[0x14d] {
-            panic!("checksum error");
+impl std::error::Error for RomError {}
+
+impl Mapper {
+    pub fn from_bin(bin: &[u8]) -> Result<Self, RomError> {
+        Self::from_bin_inner(bin, true)
+    }
+
+    /// Like [`Self::from_bin`], but loads roms with a bad header checksum like real hardware
+    /// does.
+    pub fn from_bin_permissive(bin: &[u8]) -> Result<Self, RomError> {
+        Self::from_bin_inner(bin, false)
+    }
+
+    fn from_bin_inner(bin: &[u8], check_checksum: bool) -> Result<Self, RomError> {
+        let header = crate::cartridge::Header::parse(bin)?;
+
+        if check_checksum && !header.header_checksum_valid() {
+            return Err(RomError::ChecksumMismatch {
+                expected: header.header_checksum,
+                computed: header.computed_header_checksum,
+            });
         }
 
-        let rom_banks = 2_usize << bin[0x148];
-        let ram_banks = match bin[0x149] {
-            0x00 => 0,
-            0x02 => 1,
-            0x03 => 4,
-            0x05 => 8,
-            0x04 => 16,
-            _ => panic!("illegal ram banks"),
+        // mmm01 compilations have the menu and its header in the last 32 KiB
+        let header = match bin.len().checked_sub(0x8000).map(|at| crate::cartridge::Header::parse(&bin[at..])) {
+            Some(Ok(h)) if matches!(h.cart_type, 0x0b..=0x0d) => h,
+            _ => header,
         };
 
-        match bin[0x147] {
+        let rom_banks = header.rom_banks().ok_or(RomError::IllegalRomSize(header.rom_size))?;
+        let ram_banks = header.ram_banks().ok_or(RomError::IllegalRamSize(header.ram_size))?;
+
+        if bin.len() != rom_banks << 14 {
+            return Err(RomError::SizeMismatch { expected: rom_banks << 14, actual: bin.len() });
+        }
+
+        Ok(match header.cart_type {
             0x00 => { // rom only
-                assert_eq!(rom_banks, 2);
-                assert_eq!(ram_banks, 0);
-                assert_eq!(bin.len(), 0x8000);
+                if rom_banks != 2 {
+                    return Err(RomError::IllegalRomSize(header.rom_size));
+                }
+
+                if ram_banks != 0 {
+                    return Err(RomError::IllegalRamSize(header.ram_size));
+                }
 
                 Mapper::None {
                     rom: bin.to_vec(),
                     ram: Vec::new(),
                 }
             },
-            // TODO: more asserts
             0x01..=0x03 => { // mbc1
                 Mapper::Mbc1 {
                     rom: bin.to_vec(),
@@ -85,10 +202,21 @@ impl Mapper {
                     rom_bk: 0,
                     ram_bk: 0,
                     mode: false,
-                    rom_ext: false, // TODO: fat ass rom
+                    multicart: is_mbc1m(bin),
                 }
             },
-            0x11..=0x13 => { // mbc3
+            0x05..=0x06 => { // mbc2
+                Mapper::Mbc2 {
+                    rom: bin.to_vec(),
+                    ram: vec![0x0f; 512],
+
+                    rom_mask: (rom_banks << 14) - 1,
+
+                    ram_en: false,
+                    rom_bk: 1,
+                }
+            },
+            0x0f..=0x13 => { // mbc3
                 Mapper::Mbc3 {
                     rom: bin.to_vec(),
                     ram: vec![0xff; ram_banks * 8192],
@@ -98,6 +226,8 @@ impl Mapper {
                     ram_en: false,
                     rom_bk: 1,
                     ram_bk: 0,
+
+                    rtc: matches!(header.cart_type, 0x0f | 0x10).then(crate::rtc::Rtc::new),
                 }
             },
             0x19..=0x1e => { // mbc5
@@ -110,29 +240,176 @@ impl Mapper {
                     ram_en: false,
                     rom_bk: 1,
                     ram_bk: 0,
+
+                    rumble: matches!(header.cart_type, 0x1c..=0x1e),
+                    motor: false,
+                    on_rumble: None,
                 }
             },
-            m => panic!("unknown mapper {m:02x}"),
-        }
+            0x20 => Mapper::Mbc6(mbc6::Mbc6::new(bin.to_vec(), (rom_banks << 14) - 1)),
+            0x22 => Mapper::Mbc7(mbc7::Mbc7::new(bin.to_vec(), (rom_banks << 14) - 1)),
+            0x0b..=0x0d => {
+                Mapper::Mmm01 {
+                    rom: bin.to_vec(),
+                    ram: vec![0xff; ram_banks * 8192],
+
+                    rom_mask: bin.len().next_power_of_two() - 1,
+
+                    ram_en: false,
+                    rom_bk: 0,
+                    rom_hi: 0,
+                    ram_bk: 0,
+                    ram_hi: 0,
+                    rom_bk_mask: 0,
+                    mode: false,
+                    mapped: false,
+                }
+            },
+            0xfc => Mapper::Camera(camera::Camera::new(bin.to_vec(), (rom_banks << 14) - 1)),
+            0xfd => Mapper::Tama5(tama5::Tama5::new(bin.to_vec(), (rom_banks << 14) - 1)),
+            0xfe => Mapper::Huc3(huc3::Huc3::new(bin.to_vec(), vec![0xff; ram_banks * 8192], (rom_banks << 14) - 1)),
+            0xff => {
+                Mapper::Huc1 {
+                    rom: bin.to_vec(),
+                    ram: vec![0xff; ram_banks * 8192],
+
+                    rom_mask: (rom_banks << 14) - 1,
+
+                    ir_mode: false,
+                    rom_bk: 1,
+                    ram_bk: 0,
+
+                    infrared: None,
+                    led: false,
+                }
+            },
+            m => return Err(RomError::UnknownMapper(m)),
+        })
     }
 
+    /// Takes the sram, optionally followed by an rtc trailer from [`Self::get_rtc`].
     pub fn set_sram(&mut self, sram: &[u8]) {
         match self {
             Self::None { .. } => {},
+            Self::Mbc3 { ram, rtc, .. } => {
+                let (sram, trailer) = sram.split_at(ram.len().min(sram.len()));
+                ram[..sram.len()].copy_from_slice(sram);
+
+                if let (Some(rtc), 44..) = (rtc, trailer.len()) {
+                    rtc.set_trailer(trailer);
+                }
+            },
+            Self::Huc3(m) => {
+                let (sram, trailer) = sram.split_at(m.ram.len().min(sram.len()));
+                m.ram[..sram.len()].copy_from_slice(sram);
+
+                if trailer.len() >= huc3::TRAILER_SIZE {
+                    m.set_trailer(trailer);
+                }
+            },
+            Self::Mbc2 { ram, .. } => {
+                for (r, s) in ram.iter_mut().zip(sram) { *r = s & 0xf; }
+            },
             Self::Mbc1 { ram, .. }
-                | Self::Mbc3 { ram, .. }
                 | Self::Mbc5 { ram, .. }
-            => ram.copy_from_slice(sram),
+                | Self::Mmm01 { ram, .. }
+                | Self::Huc1 { ram, .. }
+                | Self::Mbc6(mbc6::Mbc6 { ram, .. })
+                | Self::Camera(camera::Camera { ram, .. })
+            => {
+                let (sram, _) = sram.split_at(ram.len().min(sram.len()));
+                ram[..sram.len()].copy_from_slice(sram);
+            },
+            Self::Tama5(m) => {
+                let (sram, trailer) = sram.split_at(m.ram.len().min(sram.len()));
+                m.ram[..sram.len()].copy_from_slice(sram);
+
+                if trailer.len() >= tama5::TRAILER_SIZE {
+                    m.set_trailer(trailer);
+                }
+            },
+            Self::Mbc7(m) => {
+                let (sram, _) = sram.split_at(m.eeprom.len().min(sram.len()));
+                m.eeprom[..sram.len()].copy_from_slice(sram);
+            },
         }
     }
 
+    /// Battery backed ram. Carts with a clock also need [`Self::get_rtc`] appended to it.
     pub fn get_sram(&self) -> Option<&[u8]> {
         match self {
             Self::None { .. } => None,
             Self::Mbc1 { ram, .. }
+                | Self::Mbc2 { ram, .. }
                 | Self::Mbc3 { ram, .. }
                 | Self::Mbc5 { ram, .. }
+                | Self::Mmm01 { ram, .. }
+                | Self::Huc1 { ram, .. }
+                | Self::Mbc6(mbc6::Mbc6 { ram, .. })
+                | Self::Huc3(huc3::Huc3 { ram, .. })
+                | Self::Camera(camera::Camera { ram, .. })
+                | Self::Tama5(tama5::Tama5 { ram, .. })
             => Some(ram),
+            Self::Mbc7(m) => Some(&m.eeprom),
+        }
+    }
+
+    pub fn get_rtc(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::Mbc3 { rtc: Some(rtc), .. } => Some(rtc.get_trailer().to_vec()),
+            Self::Huc3(m) => Some(m.get_trailer().to_vec()),
+            Self::Tama5(m) => Some(m.get_trailer().to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Sets where the cartridge clock gets its time from, does nothing for carts without one.
+    pub fn set_rtc_clock(&mut self, clock: crate::rtc::Clock) {
+        match self {
+            Self::Mbc3 { rtc: Some(rtc), .. } => rtc.set_clock(clock),
+            Self::Huc3(m) => m.set_clock(clock),
+            Self::Tama5(m) => m.set_clock(clock),
+            _ => {},
+        }
+    }
+
+    /// Does nothing for carts without a rumble motor.
+    pub fn set_rumble_callback(&mut self, f: RumbleCallback) {
+        if let Self::Mbc5 { rumble: true, on_rumble, .. } = self {
+            *on_rumble = Some(f);
+        }
+    }
+
+    /// Does nothing for carts without an accelerometer.
+    pub fn set_accelerometer(&mut self, f: Accelerometer) {
+        if let Self::Mbc7(m) = self {
+            m.accelerometer = Some(f);
+        }
+    }
+
+    /// Does nothing for carts without a camera. Without a source the camera sees flat gray.
+    pub fn set_image_source(&mut self, f: ImageSource) {
+        if let Self::Camera(m) = self {
+            m.image_source = Some(f);
+        }
+    }
+
+    /// Does nothing for carts without an infrared port.
+    pub fn set_infrared(&mut self, i: Box<dyn Infrared>) {
+        match self {
+            Self::Huc1 { infrared, .. } => *infrared = Some(i),
+            Self::Huc3(m) => m.infrared = Some(i),
+            _ => {},
+        }
+    }
+
+    pub(crate) fn step(&mut self) {
+        match self {
+            Self::Mbc3 { rtc: Some(rtc), .. } => rtc.step(),
+            Self::Huc3(m) => m.step(),
+            Self::Camera(m) => m.step(),
+            Self::Tama5(m) => m.step(),
+            _ => {},
         }
     }
 
@@ -143,27 +420,34 @@ impl Mapper {
                 0xa000..=0xbfff => ram.get(a as usize - 0xa000).copied().unwrap_or(0xff),
                 _ => 0xff,
             },
-            Self::Mbc1 { rom, ram, rom_mask, rom_bk, ram_en, ram_bk, mode, .. } => match a {
+            Self::Mbc1 { rom, ram, rom_mask, rom_bk, ram_en, ram_bk, mode, multicart } => {
+                let (lo, hi, rbk) = mbc1_banks(*rom_bk, *ram_bk, *mode, *multicart);
+
+                match a {
+                    0x0000..=0x3fff => rom.get(((a as usize & 0x3fff) | (lo << 14)) & *rom_mask).copied().unwrap_or(0xff),
+                    0x4000..=0x7fff => rom.get(((a as usize & 0x3fff) | (hi << 14)) & *rom_mask).copied().unwrap_or(0xff),
+                    0xa000..=0xbfff if *ram_en && !ram.is_empty() => ram[((a as usize & 0x1fff) | (rbk << 13)) & (ram.len() - 1)],
+                    _ => 0xff,
+                }
+            },
+            Self::Mbc2 { rom, ram, rom_mask, ram_en, rom_bk } => match a {
                 0x0000..=0x3fff => rom.get(a as usize).copied().unwrap_or(0xff),
-                0x4000..=0x7fff => rom.get(((a as usize & 0x3fff) | ((*rom_bk as usize).max(1) << 14)) & *rom_mask).copied().unwrap_or(0xff),
-                0xa000..=0xbfff => if *ram_en {
-                    ram.get((a as usize & 0x1fff) | (mbc1_get_ram_bank(*ram_bk as usize, *mode) << 13)).copied().unwrap_or(0xff)
-                } else {
-                    0xff
-                },
+                0x4000..=0x7fff => rom.get(((a as usize & 0x3fff) | ((*rom_bk as usize) << 14)) & *rom_mask).copied().unwrap_or(0xff),
+                // only 9 address lines and 4 data lines go to the ram
+                0xa000..=0xbfff if *ram_en => ram[a as usize & 0x1ff] | 0xf0,
                 _ => 0xff,
             },
-            Self::Mbc3 { rom, ram, rom_mask, ram_en, rom_bk, ram_bk } => match a {
+            Self::Mbc3 { rom, ram, rom_mask, ram_en, rom_bk, ram_bk, rtc } => match a {
                 0x0000..=0x3fff => rom.get(a as usize).copied().unwrap_or(0xff),
                 0x4000..=0x7fff => rom.get(((a as usize & 0x3fff) | ((*rom_bk as usize) << 14)) & *rom_mask).copied().unwrap_or(0xff),
-                0xa000..=0xbfff => if *ram_en {
-                    ram.get((a as usize & 0x1fff) | ((*ram_bk as usize) << 13)).copied().unwrap_or(0xff)
-                } else {
-                    0xff
+                0xa000..=0xbfff => match (*ram_en, *ram_bk, rtc) {
+                    (true, 0x00..=0x03, _) => ram.get((a as usize & 0x1fff) | ((*ram_bk as usize) << 13)).copied().unwrap_or(0xff),
+                    (true, 0x08..=0x0c, Some(rtc)) => rtc.load(*ram_bk),
+                    _ => 0xff,
                 },
                 _ => 0xff,
             },
-            Self::Mbc5 { rom, ram, rom_mask, ram_en, rom_bk, ram_bk } => match a {
+            Self::Mbc5 { rom, ram, rom_mask, ram_en, rom_bk, ram_bk, .. } => match a {
                 0x0000..=0x3fff => rom.get(a as usize).copied().unwrap_or(0xff),
                 0x4000..=0x7fff => rom.get(((a as usize & 0x3fff) | ((*rom_bk as usize) << 14)) & *rom_mask).copied().unwrap_or(0xff),
                 0xa000..=0xbfff => if *ram_en {
@@ -173,32 +457,76 @@ impl Mapper {
                 },
                 _ => 0xff,
             },
+            Self::Mmm01 { rom, ram, rom_mask, ram_en, rom_bk, rom_hi, ram_bk, ram_hi, rom_bk_mask, mode, mapped } => {
+                let (lo, hi, rbk) = mmm01_banks(*rom_bk, *rom_hi, *ram_bk, *ram_hi, *rom_bk_mask, *mode, *mapped);
+
+                match a {
+                    0x0000..=0x3fff => rom.get(((a as usize & 0x3fff) | (lo << 14)) & *rom_mask).copied().unwrap_or(0xff),
+                    0x4000..=0x7fff => rom.get(((a as usize & 0x3fff) | (hi << 14)) & *rom_mask).copied().unwrap_or(0xff),
+                    0xa000..=0xbfff if *ram_en && !ram.is_empty() => ram[((a as usize & 0x1fff) | (rbk << 13)) & (ram.len() - 1)],
+                    _ => 0xff,
+                }
+            },
+            Self::Huc1 { rom, ram, rom_mask, ir_mode, rom_bk, ram_bk, infrared, .. } => match a {
+                0x0000..=0x3fff => rom.get(a as usize).copied().unwrap_or(0xff),
+                0x4000..=0x7fff => rom.get(((a as usize & 0x3fff) | ((*rom_bk as usize) << 14)) & *rom_mask).copied().unwrap_or(0xff),
+                0xa000..=0xbfff if *ir_mode => 0xc0 | infrared.as_mut().is_some_and(|i| i.receiving()) as u8,
+                0xa000..=0xbfff => ram.get((a as usize & 0x1fff) | ((*ram_bk as usize) << 13)).copied().unwrap_or(0xff),
+                _ => 0xff,
+            },
+            Self::Mbc6(m) => m.load(a),
+            Self::Mbc7(m) => m.load(a),
+            Self::Huc3(m) => m.load(a),
+            Self::Camera(m) => m.load(a),
+            Self::Tama5(m) => m.load(a),
+        }
+    }
+
+    /// Like [`Self::load`], but never asks the infrared port whether light is coming in, which
+    /// an embedder may count as a read.
+    pub(crate) fn peek(&mut self, a: u16) -> u8 {
+        match self {
+            Self::Huc1 { ir_mode: true, .. } if (0xa000..=0xbfff).contains(&a) => 0xc0,
+            Self::Huc3(m) => m.peek(a),
+            _ => self.load(a),
         }
     }
 
     pub(crate) fn store(&mut self, a: u16, d: u8) {
         match self {
             Self::None { rom: _, ram } => if let 0xa000..=0xbfff = a { ram.get_mut(a as usize - 0xa000).map(|r| *r = d).unwrap_or(()) },
-            Self::Mbc1 { ram, ram_en, rom_bk, ram_bk, mode, .. } => match a {
-                0x0000..=0x1fff => *ram_en = d == 0xa,
+            Self::Mbc1 { ram, ram_en, rom_bk, ram_bk, mode, multicart, .. } => match a {
+                0x0000..=0x1fff => *ram_en = d & 0xf == 0xa,
                 0x2000..=0x3fff => *rom_bk = d & 0x1f,
                 0x4000..=0x5fff => *ram_bk = d & 3,
                 0x6000..=0x7fff => *mode = d & 1 != 0,
-                0xa000..=0xbfff => if *ram_en {
-                    ram.get_mut((a as usize & 0x1fff) | (mbc1_get_ram_bank(*ram_bk as usize, *mode) << 13)).map(|r| *r = d);
+                0xa000..=0xbfff if *ram_en && !ram.is_empty() => {
+                    let (_, _, rbk) = mbc1_banks(*rom_bk, *ram_bk, *mode, *multicart);
+                    let len = ram.len();
+                    ram[((a as usize & 0x1fff) | (rbk << 13)) & (len - 1)] = d;
                 },
                 _ => {},
             },
-            Self::Mbc3 { ram, ram_en, rom_bk, ram_bk, .. } => match a {
+            Self::Mbc2 { ram, ram_en, rom_bk, .. } => match a {
+                // a8 selects the register
+                0x0000..=0x3fff if a & 0x100 == 0 => *ram_en = d & 0xf == 0xa,
+                0x0000..=0x3fff => *rom_bk = (d & 0xf).max(1),
+                0xa000..=0xbfff if *ram_en => ram[a as usize & 0x1ff] = d & 0xf,
+                _ => {},
+            },
+            Self::Mbc3 { ram, ram_en, rom_bk, ram_bk, rtc, .. } => match a {
                 0x0000..=0x1fff => *ram_en = d == 0xa,
                 0x2000..=0x23ff => *rom_bk = d.max(1),
-                0x4000..=0x5fff => if d < 4 { *ram_bk = d; },
-                0xa000..=0xbfff => if *ram_en {
-                    ram.get_mut((a as usize & 0x1fff) | ((*ram_bk as usize) << 13)).map(|r| *r = d);
+                0x4000..=0x5fff => *ram_bk = d & 0xf,
+                0x6000..=0x7fff => if let Some(rtc) = rtc { rtc.latch(d); },
+                0xa000..=0xbfff => match (*ram_en, *ram_bk, rtc) {
+                    (true, 0x00..=0x03, _) => if let Some(r) = ram.get_mut((a as usize & 0x1fff) | ((*ram_bk as usize) << 13)) { *r = d; },
+                    (true, 0x08..=0x0c, Some(rtc)) => rtc.store(*ram_bk, d),
+                    _ => {},
                 },
                 _ => {},
             },
-            Self::Mbc5 { ram, ram_en, rom_bk, ram_bk, .. } => match a {
+            Self::Mbc5 { ram, ram_en, rom_bk, ram_bk, rumble, motor, on_rumble, .. } => match a {
                 0x0000..=0x1fff => *ram_en = d & 0xf == 0xa,
                 0x2000..=0x2fff => {
                     *rom_bk &= !0xff;
@@ -208,16 +536,307 @@ impl Mapper {
                     *rom_bk &= !0x100;
                     *rom_bk |= (d as u16 & 1) << 8;
                 },
+                // rumble carts take bit 3 for the motor
+                0x4000..=0x5fff if *rumble => {
+                    *ram_bk = d & 7;
+
+                    let m = d & 8 != 0;
+                    if m != *motor {
+                        *motor = m;
+                        if let Some(f) = on_rumble { f(m); }
+                    }
+                },
                 0x4000..=0x5fff => if d < 0x10 { *ram_bk = d; },
                 0xa000..=0xbfff => if *ram_en {
                     ram.get_mut((a as usize & 0x1fff) | ((*ram_bk as usize) << 13)).map(|r| *r = d);
                 },
                 _ => {},
             },
+            Self::Mmm01 { ram, ram_en, rom_bk, rom_hi, ram_bk, ram_hi, rom_bk_mask, mode, mapped, .. } => match a {
+                0x0000..=0x1fff => {
+                    *ram_en = d & 0xf == 0xa;
+                    if !*mapped { *mapped = d & 0x40 != 0; }
+                },
+                0x2000..=0x3fff => {
+                    // bits 5 and 6 belong to the outer bank and lock with it
+                    let writable = if *mapped { 0x1f & !(*rom_bk_mask << 1) } else { 0x7f };
+                    *rom_bk = (*rom_bk & !writable) | (d & writable);
+                },
+                0x4000..=0x5fff => {
+                    *ram_bk = d & 3;
+                    if !*mapped {
+                        *ram_hi = (d >> 2) & 3;
+                        *rom_hi = (d >> 4) & 3;
+                    }
+                },
+                0x6000..=0x7fff => {
+                    *mode = d & 1 != 0;
+                    if !*mapped { *rom_bk_mask = (d >> 2) & 0xf; }
+                },
+                0xa000..=0xbfff if *ram_en && !ram.is_empty() => {
+                    let (_, _, rbk) = mmm01_banks(*rom_bk, *rom_hi, *ram_bk, *ram_hi, *rom_bk_mask, *mode, *mapped);
+                    let len = ram.len();
+                    ram[((a as usize & 0x1fff) | (rbk << 13)) & (len - 1)] = d;
+                },
+                _ => {},
+            },
+            Self::Huc1 { ram, ir_mode, rom_bk, ram_bk, infrared, led, .. } => match a {
+                0x0000..=0x1fff => *ir_mode = d == 0x0e,
+                0x2000..=0x3fff => *rom_bk = d & 0x3f,
+                0x4000..=0x5fff => *ram_bk = d & 3,
+                0xa000..=0xbfff if *ir_mode => {
+                    let l = d & 1 != 0;
+                    if l != *led {
+                        *led = l;
+                        if let Some(i) = infrared { i.set_led(l); }
+                    }
+                },
+                0xa000..=0xbfff => if let Some(r) = ram.get_mut((a as usize & 0x1fff) | ((*ram_bk as usize) << 13)) { *r = d; },
+                _ => {},
+            },
+            Self::Mbc6(m) => m.store(a, d),
+            Self::Mbc7(m) => m.store(a, d),
+            Self::Huc3(m) => m.store(a, d),
+            Self::Camera(m) => m.store(a, d),
+            Self::Tama5(m) => m.store(a, d),
         }
     }
 }
 
-fn mbc1_get_ram_bank(rbk: usize, mode: bool) -> usize {
-    if mode { rbk } else { 0 }
+/// Returns the banks mapped at 0x0000, 0x4000 and 0xa000.
+fn mbc1_banks(rom_bk: u8, ram_bk: u8, mode: bool, multicart: bool) -> (usize, usize, usize) {
+    // the zero check only looks at the 5 bit register, so bank 0x20 reads as 0x21 and so on
+    let bank1 = rom_bk.max(1) as usize;
+    let bank2 = ram_bk as usize;
+
+    // multicarts don't wire up bit 4 of the 5 bit register
+    let (bank1, shift) = if multicart { (bank1 & 0xf, 4) } else { (bank1, 5) };
+
+    let lo = if mode { bank2 << shift } else { 0 };
+    let hi = (bank2 << shift) | bank1;
+    let ram = if mode { bank2 } else { 0 };
+
+    (lo, hi, ram)
+}
+
+/// Returns the banks mapped at 0x0000, 0x4000 and 0xa000.
+fn mmm01_banks(rom_bk: u8, rom_hi: u8, ram_bk: u8, ram_hi: u8, rom_bk_mask: u8, mode: bool, mapped: bool) -> (usize, usize, usize) {
+    // the menu lives in the last 32 KiB, which the rom mask wraps these to
+    if !mapped { return (0x1fe, 0x1ff, 0); }
+
+    let frozen = rom_bk_mask << 1;
+    let outer = ((rom_hi as usize) << 7) | (rom_bk as usize & 0x60);
+
+    let bank1 = rom_bk as usize & 0x1f;
+
+    // only the unfrozen bits take part in the zero check
+    let lo = outer | (rom_bk & frozen) as usize;
+    let hi = outer | if rom_bk & 0x1f & !frozen == 0 { bank1 | 1 } else { bank1 };
+    let ram = ((ram_hi as usize) << 2) | if mode { ram_bk as usize } else { 0 };
+
+    (lo, hi, ram)
+}
+
+/// MBC1M compilations are 1 MiB with a second header at the start of every 256 KiB game.
+fn is_mbc1m(bin: &[u8]) -> bool {
+    bin.len() == 0x100000 && bin[0x40104..0x40134] == crate::cartridge::NINTENDO_LOGO
+}
+
+impl Mapper {
+    pub(crate) fn rom(&self) -> &[u8] {
+        match self {
+            Self::None { rom, .. }
+                | Self::Mbc1 { rom, .. }
+                | Self::Mbc2 { rom, .. }
+                | Self::Mbc3 { rom, .. }
+                | Self::Mbc5 { rom, .. }
+                | Self::Mmm01 { rom, .. }
+                | Self::Huc1 { rom, .. }
+                | Self::Mbc6(mbc6::Mbc6 { rom, .. })
+                | Self::Mbc7(mbc7::Mbc7 { rom, .. })
+                | Self::Huc3(huc3::Huc3 { rom, .. })
+                | Self::Camera(camera::Camera { rom, .. })
+                | Self::Tama5(tama5::Tama5 { rom, .. })
+            => rom,
+        }
+    }
+
+    /// The 16 KiB rom bank mapped at `a`, which has to be in 0x0000..=0x7fff.
+    pub(crate) fn rom_bank(&self, a: u16) -> usize {
+        let hi = a >= 0x4000;
+
+        let (bank, rom_mask) = match self {
+            Self::None { .. } => return hi as usize,
+            Self::Mbc1 { rom_mask, rom_bk, ram_bk, mode, multicart, .. } => {
+                let (lo, hi_bk, _) = mbc1_banks(*rom_bk, *ram_bk, *mode, *multicart);
+                (if hi { hi_bk } else { lo }, rom_mask)
+            },
+            Self::Mmm01 { rom_mask, rom_bk, rom_hi, ram_bk, ram_hi, rom_bk_mask, mode, mapped, .. } => {
+                let (lo, hi_bk, _) = mmm01_banks(*rom_bk, *rom_hi, *ram_bk, *ram_hi, *rom_bk_mask, *mode, *mapped);
+                (if hi { hi_bk } else { lo }, rom_mask)
+            },
+            _ if !hi => return 0,
+            Self::Mbc2 { rom_mask, rom_bk, .. }
+                | Self::Mbc3 { rom_mask, rom_bk, .. }
+                | Self::Huc1 { rom_mask, rom_bk, .. }
+            => (*rom_bk as usize, rom_mask),
+            Self::Mbc5 { rom_mask, rom_bk, .. } => (*rom_bk as usize, rom_mask),
+            Self::Mbc6(m) => return m.rom_bank(a),
+            Self::Mbc7(m) => return m.rom_bank(),
+            Self::Huc3(m) => return m.rom_bank(),
+            Self::Camera(m) => return m.rom_bank(),
+            Self::Tama5(m) => return m.rom_bank(),
+        };
+
+        ((bank << 14) & rom_mask) >> 14
+    }
+
+    fn kind(&self) -> u8 {
+        match self {
+            Self::None { .. } => 0,
+            Self::Mbc1 { .. } => 1,
+            Self::Mbc2 { .. } => 2,
+            Self::Mbc3 { .. } => 3,
+            Self::Mbc5 { .. } => 5,
+            Self::Mbc6(_) => 6,
+            Self::Mbc7(_) => 7,
+            Self::Mmm01 { .. } => 0x0b,
+            Self::Camera(_) => 0xfc,
+            Self::Tama5(_) => 0xfd,
+            Self::Huc3(_) => 0xfe,
+            Self::Huc1 { .. } => 0xff,
+        }
+    }
+}
+
+impl Snapshot for Mapper {
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.kind());
+
+        match self {
+            Self::None { ram, .. } => w.vec(ram),
+            Self::Mbc1 { ram, ram_en, rom_bk, ram_bk, mode, .. } => {
+                w.vec(ram);
+                w.bool(*ram_en);
+                w.u8(*rom_bk);
+                w.u8(*ram_bk);
+                w.bool(*mode);
+            },
+            Self::Mbc2 { ram, ram_en, rom_bk, .. } => {
+                w.vec(ram);
+                w.bool(*ram_en);
+                w.u8(*rom_bk);
+            },
+            Self::Mbc3 { ram, ram_en, rom_bk, ram_bk, rtc, .. } => {
+                w.vec(ram);
+                w.bool(*ram_en);
+                w.u8(*rom_bk);
+                w.u8(*ram_bk);
+
+                w.bool(rtc.is_some());
+                if let Some(rtc) = rtc { rtc.save_state(w); }
+            },
+            Self::Mbc5 { ram, ram_en, rom_bk, ram_bk, motor, .. } => {
+                w.vec(ram);
+                w.bool(*ram_en);
+                w.u16(*rom_bk);
+                w.u8(*ram_bk);
+                w.bool(*motor);
+            },
+            Self::Mmm01 { ram, ram_en, rom_bk, rom_hi, ram_bk, ram_hi, rom_bk_mask, mode, mapped, .. } => {
+                w.vec(ram);
+                w.bool(*ram_en);
+                w.u8(*rom_bk);
+                w.u8(*rom_hi);
+                w.u8(*ram_bk);
+                w.u8(*ram_hi);
+                w.u8(*rom_bk_mask);
+                w.bool(*mode);
+                w.bool(*mapped);
+            },
+            Self::Huc1 { ram, ir_mode, rom_bk, ram_bk, led, .. } => {
+                w.vec(ram);
+                w.bool(*ir_mode);
+                w.u8(*rom_bk);
+                w.u8(*ram_bk);
+                w.bool(*led);
+            },
+            Self::Mbc6(m) => m.save_state(w),
+            Self::Mbc7(m) => m.save_state(w),
+            Self::Huc3(m) => m.save_state(w),
+            Self::Camera(m) => m.save_state(w),
+            Self::Tama5(m) => m.save_state(w),
+        }
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        if r.u8()? != self.kind() { return Err(StateError::RomMismatch); }
+
+        match self {
+            Self::None { ram, .. } => r.vec(ram, "sram size")?,
+            Self::Mbc1 { ram, ram_en, rom_bk, ram_bk, mode, .. } => {
+                r.vec(ram, "sram size")?;
+                *ram_en = r.bool()?;
+                *rom_bk = r.u8()? & 0x1f;
+                *ram_bk = r.u8()? & 3;
+                *mode = r.bool()?;
+            },
+            Self::Mbc2 { ram, ram_en, rom_bk, .. } => {
+                r.vec(ram, "sram size")?;
+                *ram_en = r.bool()?;
+                *rom_bk = (r.u8()? & 0xf).max(1);
+            },
+            Self::Mbc3 { ram, ram_en, rom_bk, ram_bk, rtc, .. } => {
+                r.vec(ram, "sram size")?;
+                *ram_en = r.bool()?;
+                *rom_bk = r.u8()?;
+                *ram_bk = r.u8()? & 0xf;
+
+                if r.bool()? != rtc.is_some() { return Err(StateError::RomMismatch); }
+                if let Some(rtc) = rtc { rtc.load_state(r)?; }
+            },
+            Self::Mbc5 { ram, ram_en, rom_bk, ram_bk, motor, on_rumble, .. } => {
+                r.vec(ram, "sram size")?;
+                *ram_en = r.bool()?;
+                *rom_bk = r.u16()? & 0x1ff;
+                *ram_bk = r.u8()? & 0xf;
+
+                let m = r.bool()?;
+                if m != *motor {
+                    *motor = m;
+                    if let Some(f) = on_rumble { f(m); }
+                }
+            },
+            Self::Mmm01 { ram, ram_en, rom_bk, rom_hi, ram_bk, ram_hi, rom_bk_mask, mode, mapped, .. } => {
+                r.vec(ram, "sram size")?;
+                *ram_en = r.bool()?;
+                *rom_bk = r.u8()? & 0x7f;
+                *rom_hi = r.u8()? & 3;
+                *ram_bk = r.u8()? & 3;
+                *ram_hi = r.u8()? & 3;
+                *rom_bk_mask = r.u8()? & 0xf;
+                *mode = r.bool()?;
+                *mapped = r.bool()?;
+            },
+            Self::Huc1 { ram, ir_mode, rom_bk, ram_bk, infrared, led, .. } => {
+                r.vec(ram, "sram size")?;
+                *ir_mode = r.bool()?;
+                *rom_bk = r.u8()? & 0x3f;
+                *ram_bk = r.u8()? & 3;
+
+                let l = r.bool()?;
+                if l != *led {
+                    *led = l;
+                    if let Some(i) = infrared { i.set_led(l); }
+                }
+            },
+            Self::Mbc6(m) => m.load_state(r)?,
+            Self::Mbc7(m) => m.load_state(r)?,
+            Self::Huc3(m) => m.load_state(r)?,
+            Self::Camera(m) => m.load_state(r)?,
+            Self::Tama5(m) => m.load_state(r)?,
+        }
+
+        Ok(())
+    }
 }
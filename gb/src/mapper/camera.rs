@@ -0,0 +1,159 @@
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+pub const CAMERA_WIDTH: usize = 128;
+pub const CAMERA_HEIGHT: usize = 112;
+
+/// Fills a frame of 8 bit grayscale pixels, 0 being black.
+pub type ImageSource = Box<dyn FnMut(&mut [u8; CAMERA_WIDTH * CAMERA_HEIGHT]) + Send>;
+
+/// Game Boy Camera with its M64282FP sensor. The image is dithered with the game's matrix,
+/// but gain, exposure and edge enhancement are not modelled.
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub struct Camera {
+    #[derivative(Debug = "ignore")]
+    pub(super) rom: Vec<u8>,
+    #[derivative(Debug = "ignore")]
+    pub(super) ram: Vec<u8>,
+
+    rom_mask: usize,
+
+    ram_en: bool,
+    rom_bk: u8,
+    ram_bk: u8,
+
+    regs: [u8; 0x36],
+    /// Cycles left until the capture finishes.
+    busy: usize,
+
+    #[derivative(Debug = "ignore")]
+    pub(super) image_source: Option<ImageSource>,
+}
+
+impl Camera {
+    pub(super) fn new(rom: Vec<u8>, rom_mask: usize) -> Self {
+        Self {
+            rom,
+            ram: vec![0xff; 0x20000],
+
+            rom_mask,
+
+            ram_en: false,
+            rom_bk: 1,
+            ram_bk: 0,
+
+            regs: [0; 0x36],
+            busy: 0,
+
+            image_source: None,
+        }
+    }
+
+    pub(super) fn step(&mut self) {
+        if self.busy == 0 { return; }
+
+        self.busy -= 1;
+        if self.busy == 0 {
+            self.capture();
+            self.regs[0] &= !1;
+        }
+    }
+
+    pub(super) fn rom_bank(&self) -> usize {
+        ((self.rom_bk as usize) << 14 & self.rom_mask) >> 14
+    }
+
+    pub(super) fn load(&mut self, a: u16) -> u8 {
+        match a {
+            0x0000..=0x3fff => self.rom.get(a as usize).copied().unwrap_or(0xff),
+            0x4000..=0x7fff => self.rom.get(((a as usize & 0x3fff) | ((self.rom_bk as usize) << 14)) & self.rom_mask).copied().unwrap_or(0xff),
+            // only the busy flag of the sensor can be read back
+            0xa000..=0xbfff if self.ram_bk & 0x10 != 0 => if a & 0x7f == 0 { self.regs[0] & 7 } else { 0 },
+            0xa000..=0xbfff => self.ram[(a as usize & 0x1fff) | ((self.ram_bk as usize & 0xf) << 13)],
+            _ => 0xff,
+        }
+    }
+
+    pub(super) fn store(&mut self, a: u16, d: u8) {
+        match a {
+            0x0000..=0x1fff => self.ram_en = d & 0xf == 0xa,
+            0x2000..=0x3fff => self.rom_bk = d & 0x3f,
+            0x4000..=0x5fff => self.ram_bk = d & 0x1f,
+            0xa000..=0xbfff if self.ram_bk & 0x10 != 0 => match a & 0x7f {
+                0x00 => {
+                    if d & 1 != 0 && self.busy == 0 {
+                        let exposure = u16::from_be_bytes([self.regs[2], self.regs[3]]) as usize;
+                        self.busy = 4 * (32446 + if self.regs[1] & 0x80 != 0 { 0 } else { 512 } + 16 * exposure);
+                    } else if d & 1 == 0 {
+                        self.busy = 0;
+                    }
+
+                    self.regs[0] = d & 7;
+                },
+                r @ 0x01..=0x35 => self.regs[r as usize] = d,
+                _ => {},
+            },
+            // the capture overwrites ram regardless, the cpu can't write while it runs
+            0xa000..=0xbfff if self.ram_en && self.busy == 0 => self.ram[(a as usize & 0x1fff) | ((self.ram_bk as usize & 0xf) << 13)] = d,
+            _ => {},
+        }
+    }
+
+    fn capture(&mut self) {
+        let mut image = [0x80; CAMERA_WIDTH * CAMERA_HEIGHT];
+        if let Some(f) = &mut self.image_source { f(&mut image); }
+
+        let dither = &self.regs[6..0x36];
+        let ram = &mut self.ram[0x100..0x100 + CAMERA_WIDTH * CAMERA_HEIGHT / 4];
+        ram.fill(0);
+
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let v = image[y * CAMERA_WIDTH + x];
+                let m = ((y & 3) * 4 + (x & 3)) * 3;
+
+                let color = if v < dither[m] {
+                    3
+                } else if v < dither[m + 1] {
+                    2
+                } else if v < dither[m + 2] {
+                    1
+                } else {
+                    0
+                };
+
+                // 16x14 tiles, 2 bits per pixel split into 2 bit planes
+                let at = ((y / 8) * 16 + x / 8) * 16 + (y & 7) * 2;
+                let bit = 7 - (x & 7);
+                ram[at] |= (color & 1) << bit;
+                ram[at + 1] |= (color >> 1) << bit;
+            }
+        }
+    }
+}
+
+impl Snapshot for Camera {
+    fn save_state(&self, w: &mut Writer) {
+        w.vec(&self.ram);
+
+        w.bool(self.ram_en);
+        w.u8(self.rom_bk);
+        w.u8(self.ram_bk);
+
+        w.bytes(&self.regs);
+        w.usize(self.busy);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.vec(&mut self.ram, "sram size")?;
+
+        self.ram_en = r.bool()?;
+        self.rom_bk = r.u8()? & 0x3f;
+        self.ram_bk = r.u8()? & 0x1f;
+
+        r.bytes(&mut self.regs)?;
+        self.busy = r.usize()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use crate::rtc::Clock;
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+/// Minutes and days, then a unix timestamp, as little endian values.
+pub(crate) const TRAILER_SIZE: usize = 12;
+
+const DAY: u64 = 86400;
+
+/// HuC3 with its nibble command interface to the clock and an infrared port. The alarm and
+/// the speaker are accepted but do nothing.
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub struct Huc3 {
+    #[derivative(Debug = "ignore")]
+    pub(super) rom: Vec<u8>,
+    #[derivative(Debug = "ignore")]
+    pub(super) ram: Vec<u8>,
+
+    rom_mask: usize,
+
+    mode: u8,
+    rom_bk: u8,
+    ram_bk: u8,
+
+    #[derivative(Debug = "ignore")]
+    regs: Box<[u8; 0x100]>,
+    addr: u8,
+    cmd: u8,
+    response: u8,
+
+    /// Seconds, only minutes and days are visible to the game.
+    time: u64,
+    sub_cycles: usize,
+    #[derivative(Debug = "ignore")]
+    clock: Clock,
+    last_sync: u64,
+
+    #[derivative(Debug = "ignore")]
+    pub(super) infrared: Option<Box<dyn super::Infrared>>,
+    led: bool,
+}
+
+impl Huc3 {
+    pub(super) fn new(rom: Vec<u8>, ram: Vec<u8>, rom_mask: usize) -> Self {
+        Self {
+            rom,
+            ram,
+
+            rom_mask,
+
+            mode: 0,
+            rom_bk: 1,
+            ram_bk: 0,
+
+            regs: Box::new([0; 0x100]),
+            addr: 0,
+            cmd: 0,
+            response: 0,
+
+            time: 0,
+            sub_cycles: 0,
+            clock: Clock::Emulated,
+            last_sync: 0,
+
+            infrared: None,
+            led: false,
+        }
+    }
+
+    pub(super) fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+        self.last_sync = self.clock.now();
+    }
+
+    pub(super) fn step(&mut self) {
+        if !matches!(self.clock, Clock::Emulated) { return; }
+
+        self.sub_cycles += 1;
+        if self.sub_cycles >= crate::CLOCK_HZ {
+            self.sub_cycles = 0;
+            self.time += 1;
+        }
+    }
+
+    fn sync(&mut self) {
+        let now = self.clock.now();
+        self.time += now.saturating_sub(self.last_sync);
+        self.last_sync = now;
+    }
+
+    /// The live time, caught up with the wall clock if there is one.
+    fn current(&self) -> u64 {
+        self.time + self.clock.now().saturating_sub(self.last_sync)
+    }
+
+    fn minutes_days(&self) -> (u16, u16) {
+        let t = self.current();
+        ((t % DAY / 60) as u16, (t / DAY % 4096) as u16)
+    }
+
+    fn set_minutes_days(&mut self, minutes: u16, days: u16) {
+        self.time = days as u64 * DAY + (minutes as u64 % 1440) * 60;
+        self.sub_cycles = 0;
+    }
+
+    pub(super) fn rom_bank(&self) -> usize {
+        ((self.rom_bk as usize) << 14 & self.rom_mask) >> 14
+    }
+
+    pub(super) fn peek(&mut self, a: u16) -> u8 {
+        match a {
+            0xa000..=0xbfff if self.mode == 0xe => 0xc0,
+            _ => self.load(a),
+        }
+    }
+
+    pub(super) fn load(&mut self, a: u16) -> u8 {
+        match a {
+            0x0000..=0x3fff => self.rom.get(a as usize).copied().unwrap_or(0xff),
+            0x4000..=0x7fff => self.rom.get(((a as usize & 0x3fff) | ((self.rom_bk as usize) << 14)) & self.rom_mask).copied().unwrap_or(0xff),
+            0xa000..=0xbfff => match self.mode {
+                0x0 | 0xa => self.ram.get((a as usize & 0x1fff) | ((self.ram_bk as usize) << 13)).copied().unwrap_or(0xff),
+                0xc => 0x80 | (self.cmd << 4) | self.response,
+                // always ready
+                0xd => 0x01,
+                0xe => 0xc0 | self.infrared.as_mut().is_some_and(|i| i.receiving()) as u8,
+                _ => 0xff,
+            },
+            _ => 0xff,
+        }
+    }
+
+    pub(super) fn store(&mut self, a: u16, d: u8) {
+        match a {
+            0x0000..=0x1fff => self.mode = d & 0xf,
+            0x2000..=0x3fff => self.rom_bk = d & 0x7f,
+            0x4000..=0x5fff => self.ram_bk = d & 3,
+            0xa000..=0xbfff => match self.mode {
+                0xa => if let Some(r) = self.ram.get_mut((a as usize & 0x1fff) | ((self.ram_bk as usize) << 13)) { *r = d; },
+                0xb => self.command(d >> 4 & 7, d & 0xf),
+                0xe => {
+                    let led = d & 1 != 0;
+                    if led != self.led {
+                        self.led = led;
+                        if let Some(i) = &mut self.infrared { i.set_led(led); }
+                    }
+                },
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    fn command(&mut self, cmd: u8, arg: u8) {
+        self.cmd = cmd;
+
+        match cmd {
+            0x1 => {
+                self.response = self.regs[self.addr as usize] & 0xf;
+                self.addr = self.addr.wrapping_add(1);
+            },
+            0x3 => {
+                self.regs[self.addr as usize] = arg;
+                self.addr = self.addr.wrapping_add(1);
+            },
+            0x4 => self.addr = (self.addr & 0xf0) | arg,
+            0x5 => self.addr = (self.addr & 0x0f) | (arg << 4),
+            0x6 => match arg {
+                0x0 => {
+                    let (m, d) = self.minutes_days();
+                    for i in 0..3 {
+                        self.regs[i] = (m >> (i * 4)) as u8 & 0xf;
+                        self.regs[i + 3] = (d >> (i * 4)) as u8 & 0xf;
+                    }
+                },
+                0x1 => {
+                    let nibbles = |o: usize| (0..3).fold(0, |v, i| v | ((self.regs[o + i] as u16) << (i * 4)));
+                    let (m, d) = (nibbles(0), nibbles(3));
+
+                    self.sync();
+                    self.set_minutes_days(m, d);
+                },
+                0x2 => self.response = 1,
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    pub(super) fn get_trailer(&self) -> [u8; TRAILER_SIZE] {
+        let (m, d) = self.minutes_days();
+        let mut t = [0; TRAILER_SIZE];
+        t[0..2].copy_from_slice(&m.to_le_bytes());
+        t[2..4].copy_from_slice(&d.to_le_bytes());
+        t[4..].copy_from_slice(&self.clock.timestamp().to_le_bytes());
+        t
+    }
+
+    pub(super) fn set_trailer(&mut self, t: &[u8]) {
+        let m = u16::from_le_bytes([t[0], t[1]]);
+        let d = u16::from_le_bytes([t[2], t[3]]);
+        self.set_minutes_days(m, d);
+
+        if !matches!(self.clock, Clock::Emulated) {
+            let saved_at = u64::from_le_bytes(t[4..12].try_into().unwrap());
+            self.time += self.clock.now().saturating_sub(saved_at);
+            self.last_sync = self.clock.now();
+        }
+    }
+}
+
+impl Snapshot for Huc3 {
+    fn save_state(&self, w: &mut Writer) {
+        w.vec(&self.ram);
+
+        w.u8(self.mode);
+        w.u8(self.rom_bk);
+        w.u8(self.ram_bk);
+
+        w.bytes(&*self.regs);
+        w.u8(self.addr);
+        w.u8(self.cmd);
+        w.u8(self.response);
+
+        w.u64(self.current());
+        w.usize(self.sub_cycles);
+
+        w.bool(self.led);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.vec(&mut self.ram, "sram size")?;
+
+        self.mode = r.u8()? & 0xf;
+        self.rom_bk = r.u8()? & 0x7f;
+        self.ram_bk = r.u8()? & 3;
+
+        r.bytes(&mut *self.regs)?;
+        self.addr = r.u8()?;
+        self.cmd = r.u8()? & 7;
+        self.response = r.u8()? & 0xf;
+
+        self.time = r.u64()?;
+        self.sub_cycles = r.usize()? % crate::CLOCK_HZ;
+        self.last_sync = self.clock.now();
+
+        self.led = r.bool()?;
+
+        Ok(())
+    }
+}
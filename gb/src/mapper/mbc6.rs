@@ -0,0 +1,181 @@
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+const RAM_SIZE: usize = 0x8000;
+const FLASH_SIZE: usize = 0x100000;
+
+/// MBC6 with two independently switched 8 KiB rom or flash windows and two 4 KiB ram windows.
+/// The flash only understands program, erase and reset, with erases covering one 8 KiB bank.
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub struct Mbc6 {
+    #[derivative(Debug = "ignore")]
+    pub(super) rom: Vec<u8>,
+    /// The ram followed by the flash, so both end up in the save file.
+    #[derivative(Debug = "ignore")]
+    pub(super) ram: Vec<u8>,
+
+    rom_mask: usize,
+
+    ram_en: bool,
+    ram_bk: [u8; 2],
+    flash_en: bool,
+    flash_write_en: bool,
+    rom_bk: [u8; 2],
+    flash_sel: [bool; 2],
+
+    flash_state: Flash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flash {
+    Read,
+    Unlock1,
+    Unlock2,
+    Program,
+    EraseUnlock0,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+impl Mbc6 {
+    pub(super) fn new(rom: Vec<u8>, rom_mask: usize) -> Self {
+        Self {
+            rom,
+            ram: vec![0xff; RAM_SIZE + FLASH_SIZE],
+
+            rom_mask,
+
+            ram_en: false,
+            ram_bk: [0; 2],
+            flash_en: false,
+            flash_write_en: false,
+            rom_bk: [0; 2],
+            flash_sel: [false; 2],
+
+            flash_state: Flash::Read,
+        }
+    }
+
+    fn window(&self, a: u16) -> (bool, usize) {
+        let w = (a as usize - 0x4000) >> 13;
+        (self.flash_sel[w] && self.flash_en, ((self.rom_bk[w] as usize) << 13) | (a as usize & 0x1fff))
+    }
+
+    /// The 16 KiB rom bank holding the 8 KiB window at `a`.
+    pub(super) fn rom_bank(&self, a: u16) -> usize {
+        match a {
+            0x0000..=0x3fff => 0,
+            _ => (self.window(a).1 & self.rom_mask) >> 14,
+        }
+    }
+
+    pub(super) fn load(&mut self, a: u16) -> u8 {
+        match a {
+            0x0000..=0x3fff => self.rom.get(a as usize).copied().unwrap_or(0xff),
+            0x4000..=0x7fff => match self.window(a) {
+                (true, at) => self.ram[RAM_SIZE + (at & (FLASH_SIZE - 1))],
+                (false, at) => self.rom.get(at & self.rom_mask).copied().unwrap_or(0xff),
+            },
+            0xa000..=0xbfff if self.ram_en => {
+                let bk = self.ram_bk[(a as usize - 0xa000) >> 12] as usize & 7;
+                self.ram[(bk << 12) | (a as usize & 0xfff)]
+            },
+            _ => 0xff,
+        }
+    }
+
+    pub(super) fn store(&mut self, a: u16, d: u8) {
+        match a {
+            0x0000..=0x03ff => self.ram_en = d & 0xf == 0xa,
+            0x0400..=0x07ff => self.ram_bk[0] = d & 7,
+            0x0800..=0x0bff => self.ram_bk[1] = d & 7,
+            0x0c00..=0x0fff => self.flash_en = d & 1 != 0,
+            0x1000 => self.flash_write_en = d & 1 != 0,
+            0x2000..=0x27ff => self.rom_bk[0] = d & 0x7f,
+            0x2800..=0x2fff => self.flash_sel[0] = d == 0x08,
+            0x3000..=0x37ff => self.rom_bk[1] = d & 0x7f,
+            0x3800..=0x3fff => self.flash_sel[1] = d == 0x08,
+            0x4000..=0x7fff => if let (true, at) = self.window(a) {
+                self.flash_write(at & (FLASH_SIZE - 1), d);
+            },
+            0xa000..=0xbfff if self.ram_en => {
+                let bk = self.ram_bk[(a as usize - 0xa000) >> 12] as usize & 7;
+                self.ram[(bk << 12) | (a as usize & 0xfff)] = d;
+            },
+            _ => {},
+        }
+    }
+
+    fn flash_write(&mut self, at: usize, d: u8) {
+        if !self.flash_write_en { return; }
+
+        // the command addresses are 0x5555 and 0x2aaa as seen by the flash chip, which only
+        // gets the low 13 bits of them through a window
+        let cmd = at & 0x1fff;
+
+        self.flash_state = match (self.flash_state, cmd, d) {
+            (_, _, 0xf0) => Flash::Read,
+            (Flash::Read, 0x1555, 0xaa) => Flash::Unlock1,
+            (Flash::Unlock1, 0x0aaa, 0x55) => Flash::Unlock2,
+            (Flash::Unlock2, 0x1555, 0xa0) => Flash::Program,
+            (Flash::Unlock2, 0x1555, 0x80) => Flash::EraseUnlock0,
+            (Flash::EraseUnlock0, 0x1555, 0xaa) => Flash::EraseUnlock1,
+            (Flash::EraseUnlock1, 0x0aaa, 0x55) => Flash::EraseUnlock2,
+            (Flash::Program, _, _) => {
+                // programming can only clear bits
+                self.ram[RAM_SIZE + at] &= d;
+                Flash::Read
+            },
+            (Flash::EraseUnlock2, _, 0x30) => {
+                let bank = at & !0x1fff;
+                self.ram[RAM_SIZE + bank..RAM_SIZE + bank + 0x2000].fill(0xff);
+                Flash::Read
+            },
+            (Flash::EraseUnlock2, 0x1555, 0x10) => {
+                self.ram[RAM_SIZE..].fill(0xff);
+                Flash::Read
+            },
+            _ => Flash::Read,
+        };
+    }
+}
+
+impl Snapshot for Mbc6 {
+    fn save_state(&self, w: &mut Writer) {
+        w.vec(&self.ram);
+
+        w.bool(self.ram_en);
+        w.bytes(&self.ram_bk);
+        w.bool(self.flash_en);
+        w.bool(self.flash_write_en);
+        w.bytes(&self.rom_bk);
+        w.bool(self.flash_sel[0]);
+        w.bool(self.flash_sel[1]);
+
+        w.u8(self.flash_state as u8);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.vec(&mut self.ram, "sram size")?;
+
+        self.ram_en = r.bool()?;
+        r.bytes(&mut self.ram_bk)?;
+        self.flash_en = r.bool()?;
+        self.flash_write_en = r.bool()?;
+        r.bytes(&mut self.rom_bk)?;
+        self.flash_sel = [r.bool()?, r.bool()?];
+
+        self.flash_state = match r.u8()? {
+            0 => Flash::Read,
+            1 => Flash::Unlock1,
+            2 => Flash::Unlock2,
+            3 => Flash::Program,
+            4 => Flash::EraseUnlock0,
+            5 => Flash::EraseUnlock1,
+            6 => Flash::EraseUnlock2,
+            _ => return Err(StateError::Invalid("flash state")),
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+/// MBC7 with a 2 axis accelerometer and a 93LC56 serial eeprom (128 16 bit words).
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub struct Mbc7 {
+    #[derivative(Debug = "ignore")]
+    pub(super) rom: Vec<u8>,
+    #[derivative(Debug = "ignore")]
+    pub(super) eeprom: Vec<u8>,
+
+    rom_mask: usize,
+
+    ram_en: (bool, bool),
+    rom_bk: u8,
+
+    #[derivative(Debug = "ignore")]
+    pub(super) accelerometer: Option<super::Accelerometer>,
+    accel: (u16, u16),
+    accel_erased: bool,
+
+    cs: bool,
+    clk: bool,
+    di: bool,
+    do_: bool,
+
+    write_en: bool,
+    cmd: Command,
+    shift: u32,
+    bits: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Idle,
+    Opcode,
+    Read,
+    Write(u8),
+    WriteAll,
+    Done,
+}
+
+impl Mbc7 {
+    pub(super) fn new(rom: Vec<u8>, rom_mask: usize) -> Self {
+        Self {
+            rom,
+            eeprom: vec![0xff; 256],
+
+            rom_mask,
+
+            ram_en: (false, false),
+            rom_bk: 1,
+
+            accelerometer: None,
+            accel: (0x8000, 0x8000),
+            accel_erased: false,
+
+            cs: false,
+            clk: false,
+            di: false,
+            do_: true,
+
+            write_en: false,
+            cmd: Command::Idle,
+            shift: 0,
+            bits: 0,
+        }
+    }
+
+    pub(super) fn rom_bank(&self) -> usize {
+        ((self.rom_bk as usize) << 14 & self.rom_mask) >> 14
+    }
+
+    pub(super) fn load(&mut self, a: u16) -> u8 {
+        match a {
+            0x0000..=0x3fff => self.rom.get(a as usize).copied().unwrap_or(0xff),
+            0x4000..=0x7fff => self.rom.get(((a as usize & 0x3fff) | ((self.rom_bk as usize) << 14)) & self.rom_mask).copied().unwrap_or(0xff),
+            0xa000..=0xafff if self.ram_en == (true, true) => match (a >> 4) & 0xf {
+                0x2 => self.accel.0 as u8,
+                0x3 => (self.accel.0 >> 8) as u8,
+                0x4 => self.accel.1 as u8,
+                0x5 => (self.accel.1 >> 8) as u8,
+                0x6 => 0x00,
+                0x8 => {
+                    ((self.cs as u8) << 7)
+                        | ((self.clk as u8) << 6)
+                        | ((self.di as u8) << 1)
+                        | self.do_ as u8
+                },
+                _ => 0xff,
+            },
+            _ => 0xff,
+        }
+    }
+
+    pub(super) fn store(&mut self, a: u16, d: u8) {
+        match a {
+            0x0000..=0x1fff => self.ram_en.0 = d & 0xf == 0xa,
+            0x2000..=0x3fff => self.rom_bk = d,
+            0x4000..=0x5fff => self.ram_en.1 = d == 0x40,
+            0xa000..=0xafff if self.ram_en == (true, true) => match (a >> 4) & 0xf {
+                0x0 if d == 0x55 => {
+                    self.accel = (0x8000, 0x8000);
+                    self.accel_erased = true;
+                },
+                0x1 if d == 0xaa && self.accel_erased => {
+                    let (x, y) = self.accelerometer.as_ref().map_or((0.0, 0.0), |f| f());
+                    // centered on 0x81d0, roughly 0x70 per g
+                    self.accel = (
+                        (0x81d0 as f32 + x * 0x70 as f32).clamp(0.0, 65535.0) as u16,
+                        (0x81d0 as f32 + y * 0x70 as f32).clamp(0.0, 65535.0) as u16,
+                    );
+                    self.accel_erased = false;
+                },
+                0x8 => self.eeprom_write(d),
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+
+    fn eeprom_write(&mut self, d: u8) {
+        let cs = d & 0x80 != 0;
+        let clk = d & 0x40 != 0;
+        self.di = d & 2 != 0;
+
+        if !cs {
+            self.cs = false;
+            self.clk = clk;
+            self.cmd = Command::Idle;
+            return;
+        }
+
+        let rising = !self.clk && clk;
+        self.cs = true;
+        self.clk = clk;
+
+        if !rising { return; }
+
+        let bit = self.di as u32;
+
+        match self.cmd {
+            Command::Idle => if bit == 1 {
+                self.cmd = Command::Opcode;
+                self.shift = 0;
+                self.bits = 0;
+            },
+            Command::Opcode => {
+                self.shift = (self.shift << 1) | bit;
+                self.bits += 1;
+
+                // 2 bit opcode then 8 bit address
+                if self.bits == 10 {
+                    self.start_command(self.shift as u16);
+                }
+            },
+            Command::Read => {
+                self.do_ = self.shift & 0x8000 != 0;
+                self.shift <<= 1;
+                self.bits += 1;
+
+                if self.bits > 16 { self.cmd = Command::Done; }
+            },
+            Command::Write(addr) => {
+                self.shift = (self.shift << 1) | bit;
+                self.bits += 1;
+
+                if self.bits == 16 {
+                    if self.write_en { self.set_word(addr, self.shift as u16); }
+                    self.do_ = true;
+                    self.cmd = Command::Done;
+                }
+            },
+            Command::WriteAll => {
+                self.shift = (self.shift << 1) | bit;
+                self.bits += 1;
+
+                if self.bits == 16 {
+                    if self.write_en {
+                        for addr in 0..128 { self.set_word(addr, self.shift as u16); }
+                    }
+                    self.do_ = true;
+                    self.cmd = Command::Done;
+                }
+            },
+            Command::Done => {},
+        }
+    }
+
+    fn start_command(&mut self, cmd: u16) {
+        let addr = cmd as u8 & 0x7f;
+
+        self.shift = 0;
+        self.bits = 0;
+        self.cmd = Command::Done;
+
+        match cmd >> 8 {
+            0b10 => {
+                // a dummy 0 bit comes before the data
+                self.shift = self.get_word(addr) as u32;
+                self.do_ = false;
+                self.cmd = Command::Read;
+            },
+            0b01 => self.cmd = Command::Write(addr),
+            0b11 => {
+                if self.write_en { self.set_word(addr, 0xffff); }
+                self.do_ = true;
+            },
+            _ => match (cmd >> 6) & 3 {
+                0b11 => self.write_en = true,
+                0b00 => self.write_en = false,
+                0b10 => {
+                    if self.write_en { self.eeprom.fill(0xff); }
+                    self.do_ = true;
+                },
+                0b01 => self.cmd = Command::WriteAll,
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    fn get_word(&self, addr: u8) -> u16 {
+        u16::from_le_bytes([self.eeprom[addr as usize * 2], self.eeprom[addr as usize * 2 + 1]])
+    }
+
+    fn set_word(&mut self, addr: u8, v: u16) {
+        self.eeprom[addr as usize * 2..addr as usize * 2 + 2].copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+impl Snapshot for Mbc7 {
+    fn save_state(&self, w: &mut Writer) {
+        w.vec(&self.eeprom);
+
+        w.bool(self.ram_en.0);
+        w.bool(self.ram_en.1);
+        w.u8(self.rom_bk);
+
+        w.u16(self.accel.0);
+        w.u16(self.accel.1);
+        w.bool(self.accel_erased);
+
+        w.bool(self.cs);
+        w.bool(self.clk);
+        w.bool(self.di);
+        w.bool(self.do_);
+
+        w.bool(self.write_en);
+        let (cmd, arg) = match self.cmd {
+            Command::Idle => (0, 0),
+            Command::Opcode => (1, 0),
+            Command::Read => (2, 0),
+            Command::Write(a) => (3, a),
+            Command::WriteAll => (4, 0),
+            Command::Done => (5, 0),
+        };
+        w.u8(cmd);
+        w.u8(arg);
+        w.u32(self.shift);
+        w.u8(self.bits);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.vec(&mut self.eeprom, "eeprom size")?;
+
+        self.ram_en = (r.bool()?, r.bool()?);
+        self.rom_bk = r.u8()?;
+
+        self.accel = (r.u16()?, r.u16()?);
+        self.accel_erased = r.bool()?;
+
+        self.cs = r.bool()?;
+        self.clk = r.bool()?;
+        self.di = r.bool()?;
+        self.do_ = r.bool()?;
+
+        self.write_en = r.bool()?;
+        self.cmd = match (r.u8()?, r.u8()?) {
+            (0, _) => Command::Idle,
+            (1, _) => Command::Opcode,
+            (2, _) => Command::Read,
+            (3, a) => Command::Write(a & 0x7f),
+            (4, _) => Command::WriteAll,
+            (5, _) => Command::Done,
+            _ => return Err(StateError::Invalid("eeprom command")),
+        };
+        self.shift = r.u32()?;
+        self.bits = r.u8()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+use crate::rtc::Clock;
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+/// The clock registers, then a unix timestamp, as little endian values.
+pub(crate) const TRAILER_SIZE: usize = 0x18;
+
+/// Days from 2000-01-01 to 2100-01-01, the two digit year wraps around after that.
+const CENTURY: u64 = 36525;
+
+/// Bandai TAMA5, every register is reached through a nibble wide window at 0xa000 and 0xa001.
+/// Its 32 bytes of ram are battery backed. Only the time page of the TC8521 clock counts, the
+/// alarm and the other control registers are kept as they are written.
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub struct Tama5 {
+    #[derivative(Debug = "ignore")]
+    pub(super) rom: Vec<u8>,
+    pub(super) ram: Vec<u8>,
+
+    rom_mask: usize,
+
+    reg: u8,
+    rom_bk: u8,
+    data: u8,
+    addr: u8,
+    read: u8,
+
+    /// BCD digits from seconds up to years, with the day of the week between hours and days.
+    rtc: [u8; 0x10],
+    sub_cycles: usize,
+    #[derivative(Debug = "ignore")]
+    clock: Clock,
+    last_sync: u64,
+}
+
+impl Tama5 {
+    pub(super) fn new(rom: Vec<u8>, rom_mask: usize) -> Self {
+        Self {
+            rom,
+            ram: vec![0; 0x20],
+
+            rom_mask,
+
+            reg: 0,
+            rom_bk: 1,
+            data: 0,
+            addr: 0,
+            read: 0,
+
+            // 2000-01-01 was a saturday
+            rtc: [0, 0, 0, 0, 0, 0, 6, 1, 0, 1, 0, 0, 0, 0, 0, 0],
+            sub_cycles: 0,
+            clock: Clock::Emulated,
+            last_sync: 0,
+        }
+    }
+
+    pub(super) fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+        self.last_sync = self.clock.now();
+    }
+
+    pub(super) fn step(&mut self) {
+        if !matches!(self.clock, Clock::Emulated) { return; }
+
+        self.sub_cycles += 1;
+        if self.sub_cycles >= crate::CLOCK_HZ {
+            self.sub_cycles = 0;
+            advance(&mut self.rtc, 1);
+        }
+    }
+
+    fn sync(&mut self) {
+        self.rtc = self.current();
+        self.last_sync = self.clock.now();
+    }
+
+    /// The live registers, caught up with the wall clock if there is one.
+    fn current(&self) -> [u8; 0x10] {
+        let mut rtc = self.rtc;
+        advance(&mut rtc, self.clock.now().saturating_sub(self.last_sync));
+        rtc
+    }
+
+    pub(super) fn rom_bank(&self) -> usize {
+        ((self.rom_bk as usize) << 14 & self.rom_mask) >> 14
+    }
+
+    pub(super) fn load(&mut self, a: u16) -> u8 {
+        match a {
+            0x0000..=0x3fff => self.rom.get(a as usize).copied().unwrap_or(0xff),
+            0x4000..=0x7fff => self.rom.get(((a as usize & 0x3fff) | ((self.rom_bk as usize) << 14)) & self.rom_mask).copied().unwrap_or(0xff),
+            0xa000 => match self.reg {
+                // games wait for this before touching anything else
+                0xa => 0xf1,
+                0xc => 0xf0 | (self.read & 0xf),
+                0xd => 0xf0 | (self.read >> 4),
+                _ => 0xff,
+            },
+            _ => 0xff,
+        }
+    }
+
+    pub(super) fn store(&mut self, a: u16, d: u8) {
+        let d = d & 0xf;
+
+        match a {
+            0xa000 => match self.reg {
+                0x0 => self.rom_bk = (self.rom_bk & 0x10) | d,
+                0x1 => self.rom_bk = (self.rom_bk & 0x0f) | ((d & 1) << 4),
+                0x4 => self.data = (self.data & 0xf0) | d,
+                0x5 => self.data = (self.data & 0x0f) | (d << 4),
+                0x6 => self.addr = (self.addr & 0x0f) | (d << 4),
+                0x7 => {
+                    self.addr = (self.addr & 0xf0) | d;
+
+                    let at = (self.addr & 0x1f) as usize;
+                    match self.addr >> 5 {
+                        0x0 => self.ram[at] = self.data,
+                        0x1 => self.read = self.ram[at],
+                        0x2 => {
+                            self.sync();
+                            self.rtc[at & 0xf] = self.data & 0xf;
+                            if at & 0xf == 0 { self.sub_cycles = 0; }
+                        },
+                        0x4 => {
+                            self.sync();
+                            self.read = self.rtc[at & 0xf];
+                        },
+                        _ => {},
+                    }
+                },
+                _ => {},
+            },
+            0xa001 => self.reg = d,
+            _ => {},
+        }
+    }
+
+    pub(super) fn get_trailer(&self) -> [u8; TRAILER_SIZE] {
+        let mut t = [0; TRAILER_SIZE];
+        t[..0x10].copy_from_slice(&self.current());
+        t[0x10..].copy_from_slice(&self.clock.timestamp().to_le_bytes());
+        t
+    }
+
+    pub(super) fn set_trailer(&mut self, t: &[u8]) {
+        for (r, d) in self.rtc.iter_mut().zip(&t[..0x10]) { *r = d & 0xf; }
+
+        if !matches!(self.clock, Clock::Emulated) {
+            let saved_at = u64::from_le_bytes(t[0x10..0x18].try_into().unwrap());
+            advance(&mut self.rtc, self.clock.now().saturating_sub(saved_at));
+            self.last_sync = self.clock.now();
+        }
+    }
+}
+
+fn bcd(lo: u8, hi: u8) -> u64 {
+    (hi as u64 * 10 + lo as u64) % 100
+}
+
+/// Counts the time page on by `secs`. Digits a game set out of range are taken as they add up,
+/// like the 31st of february being the 3rd of march.
+fn advance(rtc: &mut [u8; 0x10], secs: u64) {
+    if secs == 0 { return; }
+
+    let (s, m, h) = (bcd(rtc[0], rtc[1]), bcd(rtc[2], rtc[3]), bcd(rtc[4], rtc[5]));
+    let (day, month, year) = (bcd(rtc[7], rtc[8]), bcd(rtc[9], rtc[10]), bcd(rtc[11], rtc[12]));
+
+    let then = days(year, month.clamp(1, 12), day.max(1));
+    let end = then * 86400 + h * 3600 + m * 60 + s + secs;
+    rtc[6] = ((rtc[6] as u64 + (end / 86400 - then) % 7) % 7) as u8;
+
+    let total = end % (CENTURY * 86400);
+    let (year, month, day) = civil(total / 86400);
+
+    let digits = [total % 60, total / 60 % 60, total / 3600 % 24];
+    for (i, v) in digits.into_iter().enumerate() {
+        rtc[i * 2] = (v % 10) as u8;
+        rtc[i * 2 + 1] = (v / 10) as u8;
+    }
+
+    for (i, v) in [day, month, year].into_iter().enumerate() {
+        rtc[7 + i * 2] = (v % 10) as u8;
+        rtc[8 + i * 2] = (v / 10) as u8;
+    }
+}
+
+/// Days since 2000-01-01, the year counting from 2000.
+fn days(year: u64, month: u64, day: u64) -> u64 {
+    // march based years put the leap day at the end
+    let y = 2000 + year - (month <= 2) as u64;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+
+    let (era, yoe) = (y / 400, y % 400);
+    era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 730425
+}
+
+/// The year counting from 2000, month and day of the date `days` after 2000-01-01.
+fn civil(days: u64) -> (u64, u64, u64) {
+    let z = days + 730425;
+    let (era, doe) = (z / 146097, z % 146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + yoe + (month <= 2) as u64;
+
+    (year - 2000, month, day)
+}
+
+impl Snapshot for Tama5 {
+    fn save_state(&self, w: &mut Writer) {
+        w.vec(&self.ram);
+
+        w.u8(self.reg);
+        w.u8(self.rom_bk);
+        w.u8(self.data);
+        w.u8(self.addr);
+        w.u8(self.read);
+
+        w.bytes(&self.current());
+        w.usize(self.sub_cycles);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.vec(&mut self.ram, "sram size")?;
+
+        self.reg = r.u8()? & 0xf;
+        self.rom_bk = r.u8()? & 0x1f;
+        self.data = r.u8()?;
+        self.addr = r.u8()?;
+        self.read = r.u8()?;
+
+        r.bytes(&mut self.rtc)?;
+        self.sub_cycles = r.usize()? % crate::CLOCK_HZ;
+
+        // don't count the time spent before the state was loaded
+        self.last_sync = self.clock.now();
+
+        Ok(())
+    }
+}
@@ -1,16 +1,37 @@
 use std::sync::{Arc, Mutex};
 
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+/// A DMG writes shades from 0 (lightest) to 3, a CGB writes 15 bit colors in the palette ram
+/// format, red in the low bits.
+pub type Framebuffer = Arc<Mutex<[u16; 160 * 144]>>;
+
 #[derive(derivative::Derivative)]
 #[derivative(Debug)]
 pub struct Ppu {
     #[derivative(Debug = "ignore")]
-    front_buffer: Arc<Mutex<[u8; 160 * 144]>>,
+    front_buffer: Framebuffer,
     #[derivative(Debug = "ignore")]
-    back_buffer: [u8; 160 * 144],
+    pub(crate) back_buffer: [u16; 160 * 144],
+    /// Set when the last line of a frame is drawn.
+    pub(crate) frame_done: bool,
 
-    pub(crate) vram: [u8; 0x2000],
+    #[derivative(Debug = "ignore")]
+    pub(crate) vram: [u8; 0x4000],
     pub(crate) oam: [u8; 0xa0],
 
+    pub(crate) cgb: bool,
+    /// A CGB running a DMG game, colors still go through the palette ram.
+    pub(crate) dmg_compat: bool,
+    vbk: u8,
+    #[derivative(Debug = "ignore")]
+    pub(crate) bg_pal: [u8; 64],
+    #[derivative(Debug = "ignore")]
+    pub(crate) obj_pal: [u8; 64],
+    bcps: u8,
+    ocps: u8,
+    opri: u8,
+
     pub(crate) ly: u8,
     lyc: u8,
     pub(crate) bgp: u8,
@@ -24,19 +45,92 @@ pub struct Ppu {
 
     pub(crate) hsync: usize,
     stat_request: u8,
+    mode: u8,
+
+    /// Objects the oam scan found on this line, in oam order.
+    objs: [LineObj; 10],
+    objc: usize,
+
+    fetcher: Fetcher,
+    /// Background pixels waiting to be shifted out, as two bit planes that share attributes.
+    bg_fifo: (u8, u8, u8),
+    bg_len: u8,
+    obj_fifo: [ObjPixel; 8],
+    obj_len: u8,
+
+    /// The next pixel to be drawn on this line.
+    lx: u8,
+    /// Pixels to throw away before drawing, from fine scrolling.
+    discard: u8,
+    /// The first tile of a line is fetched twice, the first one is thrown away.
+    first_fetch: bool,
+    /// Dots left until the object being fetched is mixed in.
+    obj_stall: u8,
+    obj_fetch: usize,
+    /// The last tile an object has waited on the background fetcher for.
+    obj_tile: Option<i16>,
+
+    /// WY matched LY at some point in this frame.
+    wy_hit: bool,
+    /// The window was drawn on this line, so its line counter moves on.
+    win_line: bool,
+    /// The first frame after turning the lcd on never makes it to the screen.
+    skip_frame: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct LineObj {
+    idx: u8,
+    y: u8,
+    x: u8,
+    tile: u8,
+    attr: u8,
+    done: bool,
+}
 
-    mode_3_penalty: usize,
+#[derive(Debug, Default, Clone, Copy)]
+struct ObjPixel {
+    c: u8,
+    pal: u8,
+    prio: bool,
+    idx: u8,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Fetcher {
+    /// Each step takes two dots, the seventh dot pushes once the fifo is empty.
+    step: u8,
+    /// Tiles pushed so far on this line.
+    x: u8,
+    window: bool,
+    tile: u8,
+    attr: u8,
+    lo: u8,
+    hi: u8,
 }
 
 impl Ppu {
-    pub fn new(front_buffer: Arc<Mutex<[u8; 160 * 144]>>) -> Self {
+    pub fn new(front_buffer: Framebuffer, cgb: bool) -> Self {
+        // the lcd starts off
+        front_buffer.lock().unwrap().fill(if cgb { 0x7fff } else { 0 });
+
         Self {
             front_buffer,
             back_buffer: [0; 160 * 144],
+            frame_done: false,
 
-            vram: [0; 0x2000],
+            vram: [0; 0x4000],
             oam: [0; 0xa0],
 
+            cgb,
+            dmg_compat: false,
+            vbk: 0,
+            bg_pal: [0xff; 64],
+            obj_pal: [0xff; 64],
+            bcps: 0,
+            ocps: 0,
+            opri: !cgb as u8,
+
             ly: 153,
             lyc: 0,
             bgp: 0x1b,
@@ -50,8 +144,27 @@ impl Ppu {
 
             hsync: 0,
             stat_request: 0,
-
-            mode_3_penalty: 0,
+            mode: 1,
+
+            objs: [LineObj::default(); 10],
+            objc: 0,
+
+            fetcher: Fetcher::default(),
+            bg_fifo: (0, 0, 0),
+            bg_len: 0,
+            obj_fifo: [ObjPixel::default(); 8],
+            obj_len: 0,
+
+            lx: 0,
+            discard: 0,
+            first_fetch: false,
+            obj_stall: 0,
+            obj_fetch: 0,
+            obj_tile: None,
+
+            wy_hit: false,
+            win_line: false,
+            skip_frame: false,
         }
     }
 
@@ -59,181 +172,303 @@ impl Ppu {
     pub fn step(&mut self, int_mgr: &mut sm83::cpu::InterruptManager) {
         if self.is_disabled() { return; }
 
-        let mode = self.get_mode();
         let prev_req = core::mem::take(&mut self.stat_request) != 0;
 
-        let hsync = self.hsync;
-        self.hsync = (self.hsync + 1) % 456;
-
-        let y = self.ly;
-        if hsync == 455 {
-            self.mode_3_penalty = 0;
-            self.ly = (self.ly + 1) % 154;
-        }
+        match (self.hsync, self.ly) {
+            (0, ..144) => {
+                self.mode = 2;
+                self.objc = 0;
+                if self.ly == self.window.1 { self.wy_hit = true; }
+            },
+            (0, 144) => {
+                self.mode = 1;
 
-        self.stat_request(mode == 0, 0x08);
-        self.stat_request(mode == 1, 0x10);
-        self.stat_request(mode == 2, 0x20);
-        self.stat_request(self.ly == self.lyc, 0x40);
+                if !core::mem::take(&mut self.skip_frame) {
+                    let mut fb = self.front_buffer.lock().unwrap();
+                    fb.copy_from_slice(&self.back_buffer);
+                    self.frame_done = true;
+                }
 
-        if hsync == 455 && y == 144 {
-            self.wly = 0;
-            self.check_stat(prev_req, int_mgr);
-            int_mgr.interrupt(0);
-            return;
-        } else if hsync != 80 || y >= 144 {
-            self.check_stat(prev_req, int_mgr);
-            return;
+                int_mgr.interrupt(0);
+            },
+            (80, ..144) => self.start_drawing(),
+            _ => {},
         }
 
-        let mut strip_bg = [0; 160];
-        let mut strip_ob = [(0, 0, false); 160];
-        let mut buf = [0; 2];
+        match self.mode {
+            2 if self.hsync.is_multiple_of(2) => self.scan_oam(self.hsync / 2),
+            3 => self.draw_dot(),
+            _ => {},
+        }
 
-        self.mode_3_penalty = self.scroll.0 as usize % 8;
+        self.stat_request(self.mode == 0, 0x08);
+        self.stat_request(self.mode == 1, 0x10);
+        // the oam interrupt also fires as vblank starts
+        self.stat_request(self.mode == 2 || (self.ly == 144 && self.hsync == 0), 0x20);
+        self.stat_request(self.get_ly() == self.lyc, 0x40);
+        self.check_stat(prev_req, int_mgr);
 
-        if self.lcdc & 1 != 0 {
-            self.render_bg(y, &mut strip_bg, &mut buf);
+        self.hsync = (self.hsync + 1) % 456;
+        if self.hsync == 0 {
+            if core::mem::take(&mut self.win_line) { self.wly += 1; }
 
-            if self.lcdc & 0x20 != 0 && self.window.0 <= 166 && self.window.1 <= y {
-                self.mode_3_penalty += self.render_window(&mut strip_bg, &mut buf) as usize * 6;
-                self.wly += 1;
+            self.ly = (self.ly + 1) % 154;
+            if self.ly == 0 {
+                self.wly = 0;
+                self.wy_hit = false;
             }
         }
+    }
 
-        if self.lcdc & 2 != 0 {
-            let long = self.lcdc & 4 != 0;
-            let height = if long { 16 } else { 8 };
-            let t_mask = !(long as u8);
+    /// Looks at one oam entry, the scan gets through two dots per entry.
+    fn scan_oam(&mut self, o: usize) {
+        if self.objc >= 10 { return; }
+
+        let obj = &self.oam[o * 4..o * 4 + 4];
+        let height = if self.lcdc & 4 != 0 { 16 } else { 8 };
+        let oy = obj[0] as isize - 16;
+
+        if (oy..oy + height).contains(&(self.ly as isize)) {
+            self.objs[self.objc] = LineObj {
+                idx: o as u8,
+                y: obj[0],
+                x: obj[1],
+                tile: obj[2],
+                attr: obj[3],
+                done: false,
+            };
+            self.objc += 1;
+        }
+    }
 
-            let mut objs = [(0, 0, 0, 0); 10];
-            let mut objc = 0;
+    fn start_drawing(&mut self) {
+        self.mode = 3;
+        self.fetcher = Fetcher::default();
+        self.bg_len = 0;
+        self.obj_len = 0;
+        self.lx = 0;
+        self.discard = self.scroll.0 & 7;
+        self.first_fetch = true;
+        self.obj_stall = 0;
+        self.obj_tile = None;
+    }
 
-            for o in 0..40 {
-                let obj = &self.oam[o * 4..o * 4 + 4];
-                let oy = obj[0] as isize - 16;
+    /// One dot of mode 3: the fetchers run and at most one pixel gets shifted out.
+    fn draw_dot(&mut self) {
+        if self.obj_stall != 0 {
+            self.obj_stall -= 1;
+            if self.obj_stall != 0 { return; }
 
-                if (oy..oy + height as isize).contains(&(y as isize)) {
-                    objs[objc] = TryInto::<[u8; 4]>::try_into(obj).unwrap().into();
-                    objc += 1;
-                    if objc >= 10 { break; }
-                }
+            self.fetch_obj();
+        }
+
+        self.tick_fetcher();
+        if self.bg_len == 0 { return; }
+
+        let wx = self.window.0;
+        if !self.fetcher.window && self.lcdc & 0x20 != 0 && self.wy_hit
+            && (wx == self.lx + 7 || (wx < 7 && self.lx == 0))
+        {
+            // the fetch of the first window tile starts on this dot
+            self.bg_len = 0;
+            self.fetcher = Fetcher { window: true, step: 1, ..Fetcher::default() };
+            self.discard = 7_u8.saturating_sub(wx);
+            self.win_line = true;
+            return;
+        }
+
+        if self.discard != 0 {
+            self.pop_bg();
+            self.discard -= 1;
+            return;
+        }
+
+        if self.lcdc & 2 != 0 {
+            let lx = self.lx as usize;
+            if let Some(i) = (0..self.objc).find(|i| !self.objs[*i].done && self.objs[*i].x as usize <= lx + 8) {
+                self.objs[i].done = true;
+                self.obj_fetch = i;
+                self.obj_stall = 6 + self.obj_penalty(self.objs[i].x);
+                return;
             }
+        }
 
-            let objs = &mut objs[..objc];
-            objs.sort_by_key(|o| o.1);
+        self.draw_pixel();
+        self.lx += 1;
 
-            for o in objs.iter() {
-                let x = o.1 - 8;
-                let iy = if o.3 & 0x40 != 0 { height - y + o.0 - 17 } else { y - o.0 + 16 };
+        if self.lx == 160 { self.mode = 0; }
+    }
 
-                let p = self.obp[(o.3 >> 4) as usize & 1];
+    /// How long an object waits for the background fetcher, only the first object over a tile
+    /// has to wait.
+    fn obj_penalty(&mut self, x: u8) -> u8 {
+        if x == 0 { return 5; }
 
-                let (_, r) = self.vram.split_at((o.2 & t_mask) as usize * 16 + iy as usize * 2);
-                buf.copy_from_slice(&r[..2]);
+        let px = if self.fetcher.window {
+            // window tiles never share with background tiles
+            x as i16 - 1 - self.window.0 as i16 + 0x100
+        } else {
+            x as i16 - 8 + self.scroll.0 as i16
+        };
 
-                for k in 0..8 {
-                    let kb = if o.3 & 0x20 != 0 { k } else { 7 - k };
-                    let c = (((buf[1] >> kb) & 1) << 1) | ((buf[0] >> kb) & 1);
+        let tile = px.div_euclid(8);
+        if self.obj_tile == Some(tile) { return 0; }
 
-                    if c != 0 {
-                        let x = (x + k) as usize;
+        self.obj_tile = Some(tile);
+        5_u8.saturating_sub(px.rem_euclid(8) as u8)
+    }
 
-                        if x < 160 && strip_ob[x].0 == 0 {
-                            strip_ob[x] = (c, p, o.3 & 0x80 != 0);
-                        };
-                    }
+    fn tick_fetcher(&mut self) {
+        let f = self.fetcher;
+
+        match f.step {
+            1 => {
+                let offset = if f.window {
+                    let base = if self.lcdc & 0x40 == 0 { 0x1800 } else { 0x1c00 };
+                    base + (self.wly as usize / 8) * 32 + f.x as usize % 32
+                } else {
+                    let base = if self.lcdc & 8 == 0 { 0x1800 } else { 0x1c00 };
+                    let tx = (self.scroll.0 / 8).wrapping_add(f.x) as usize % 32;
+                    base + (self.ly.wrapping_add(self.scroll.1) as usize / 8) * 32 + tx
+                };
+
+                (self.fetcher.tile, self.fetcher.attr) = self.tilemap_entry(offset);
+            },
+            3 => self.fetcher.lo = self.vram[self.tiledata_base(f.tile, f.attr, self.fetch_row())],
+            5 => self.fetcher.hi = self.vram[self.tiledata_base(f.tile, f.attr, self.fetch_row()) + 1],
+            6 => {
+                if self.bg_len != 0 { return; }
+
+                if core::mem::take(&mut self.first_fetch) {
+                    // thrown away, the real fetch of the first tile starts on this dot
+                    self.fetcher.step = 1;
+                    return;
                 }
 
-                self.mode_3_penalty += 6; // TODO: more accurate penalty algo
-            }
+                let (lo, hi) = if f.attr & 0x20 != 0 { (f.lo.reverse_bits(), f.hi.reverse_bits()) } else { (f.lo, f.hi) };
+                self.bg_fifo = (lo, hi, f.attr);
+                self.bg_len = 8;
+                self.fetcher.x = f.x.wrapping_add(1);
+                self.fetcher.step = 0;
+                return;
+            },
+            _ => {},
         }
 
-        for (x, (b, (o, p, pr))) in strip_bg.into_iter().zip(strip_ob).enumerate() {
-            self.back_buffer[y as usize * 160 + x] = if o == 0 || (pr && b != 0) {
-                (self.bgp >> (b * 2)) & 3
-            } else {
-                (p >> (o * 2)) & 3
-            };
-        }
+        self.fetcher.step += 1;
+    }
 
-        if y == 143 {
-            let mut fb = self.front_buffer.lock().unwrap();
-            fb.copy_from_slice(&self.back_buffer);
+    /// The row of the tile being fetched.
+    fn fetch_row(&self) -> usize {
+        if self.fetcher.window {
+            self.wly as usize % 8
+        } else {
+            self.ly.wrapping_add(self.scroll.1) as usize % 8
         }
+    }
+
+    fn pop_bg(&mut self) -> (u8, u8) {
+        let (lo, hi, attr) = self.bg_fifo;
+        self.bg_fifo = (lo << 1, hi << 1, attr);
+        self.bg_len -= 1;
 
-        self.check_stat(prev_req, int_mgr)
+        (((hi >> 7) << 1) | (lo >> 7), attr)
     }
 
-    fn tiledata_base(&self, tile: u8, iy: usize) -> usize {
-        if self.lcdc & 0x10 == 0 {
-            (0x1000 + tile as i8 as isize * 16) as usize + iy * 2
-        } else {
-            tile as usize * 16 + iy * 2
-        }
+    fn pop_obj(&mut self) -> ObjPixel {
+        if self.obj_len == 0 { return ObjPixel::default(); }
+
+        let p = self.obj_fifo[0];
+        self.obj_fifo.copy_within(1.., 0);
+        self.obj_fifo[7] = ObjPixel::default();
+        self.obj_len -= 1;
+        p
     }
 
-    fn render_bg(&self, y: u8, strip_bg: &mut [u8; 160], buf: &mut [u8; 2]) {
-        let ty = (y + self.scroll.1) as usize / 8;
-        let iy = (y + self.scroll.1) as usize % 8;
+    /// Fetches the row of the object that was waited on, and mixes it into the object fifo.
+    fn fetch_obj(&mut self) {
+        let o = self.objs[self.obj_fetch];
+        let cgb_mode = self.cgb && !self.dmg_compat;
 
-        let tilemap_base = if self.lcdc & 8 == 0 { 0x1800 } else { 0x1c00 } + ty * 32;
+        let long = self.lcdc & 4 != 0;
+        let height = if long { 16 } else { 8 };
+        let t_mask = !(long as u8);
 
-        'a: for sx in 0..21 {
-            let tx = (sx + self.scroll.0 / 8) as usize % 32;
-            let xo = self.scroll.0 % 8;
+        let row = self.ly.wrapping_add(16).wrapping_sub(o.y) & (height - 1);
+        let iy = if o.attr & 0x40 != 0 { height - 1 - row } else { row };
 
-            let offset = tilemap_base + tx;
-            let tile = self.vram[offset];
+        let (pal, bank) = if cgb_mode {
+            (o.attr & 7, (o.attr as usize >> 3 & 1) * 0x2000)
+        } else {
+            ((o.attr >> 4) & 1, 0)
+        };
 
-            let (_, r) = self.vram.split_at(self.tiledata_base(tile, iy));
-            buf.copy_from_slice(&r[..2]);
+        let at = bank + (o.tile & t_mask) as usize * 16 + iy as usize * 2;
+        let (mut lo, mut hi) = (self.vram[at], self.vram[at + 1]);
+        if o.attr & 0x20 != 0 { (lo, hi) = (lo.reverse_bits(), hi.reverse_bits()); }
 
-            for k in 0..8 {
-                let x = sx * 8;
-                let kb = 7 - k;
-                let c = (((buf[1] >> kb) & 1) << 1) | ((buf[0] >> kb) & 1);
+        // an object hanging off the left edge has some pixels already behind it
+        let skip = self.lx as usize + 8 - o.x as usize;
+        self.obj_len = 8;
 
-                let x = x as isize + k as isize - xo as isize;
-                if (0..160).contains(&x) {
-                    strip_bg[x as usize] = c;
-                } else if 0 <= x {
-                    break 'a;
-                }
+        for k in skip..8 {
+            let c = (((hi >> (7 - k)) & 1) << 1) | ((lo >> (7 - k)) & 1);
+            let cur = &mut self.obj_fifo[k - skip];
+
+            // a cgb goes by oam order unless told to act like a dmg
+            if c != 0 && (cur.c == 0 || (self.opri & 1 == 0 && o.idx < cur.idx)) {
+                *cur = ObjPixel { c, pal, prio: o.attr & 0x80 != 0, idx: o.idx };
             }
         }
     }
 
-    fn render_window(&self, strip_bg: &mut [u8; 160], buf: &mut [u8; 2]) -> bool {
-        let ty = self.wly as usize / 8;
-        let iy = self.wly as usize % 8;
-
-        let tilemap_base = if self.lcdc & 0x40 == 0 { 0x1800 } else { 0x1c00 } + ty * 32;
-        let mut any = false;
-
-        'a: for tx in 0..21 {
-            let offset = tilemap_base + tx;
-            let tile = self.vram[offset];
+    fn draw_pixel(&mut self) {
+        let (b, attr) = self.pop_bg();
+        let o = self.pop_obj();
+        let cgb_mode = self.cgb && !self.dmg_compat;
+
+        // in cgb mode lcdc bit 0 takes away the background's priority instead of hiding it
+        let blank = self.lcdc & 1 == 0 && !cgb_mode;
+        let (b, o) = (if blank { 0 } else { b }, if self.lcdc & 2 != 0 { o } else { ObjPixel::default() });
+
+        let bg_wins = o.c == 0 || (b != 0 && (o.prio || attr & 0x80 != 0) && (self.lcdc & 1 != 0 || !cgb_mode));
+        let bgp = if blank { 0 } else { self.bgp };
+        let obp = self.obp[o.pal as usize & 1];
+
+        self.back_buffer[self.ly as usize * 160 + self.lx as usize] = match (bg_wins, self.cgb, cgb_mode) {
+            (true, false, _) => ((bgp >> (b * 2)) & 3) as u16,
+            (false, false, _) => ((obp >> (o.c * 2)) & 3) as u16,
+            (true, true, false) => color(&self.bg_pal, 0, (bgp >> (b * 2)) & 3),
+            (false, true, false) => color(&self.obj_pal, o.pal, (obp >> (o.c * 2)) & 3),
+            (true, true, true) => color(&self.bg_pal, attr & 7, b),
+            (false, true, true) => color(&self.obj_pal, o.pal, o.c),
+        };
+    }
 
-            let (_, r) = self.vram.split_at(self.tiledata_base(tile, iy));
-            buf.copy_from_slice(&r[..2]);
+    pub(crate) fn set_compat_palette(&mut self, pal: &crate::compat::CompatPalette) {
+        let colors = pal.bg.iter().chain(&pal.obj0).chain(&pal.obj1);
+        for (i, c) in colors.enumerate() {
+            let (ram, at) = if i < 4 { (&mut self.bg_pal, i * 2) } else { (&mut self.obj_pal, i * 2 - 8) };
+            ram[at..at + 2].copy_from_slice(&c.to_le_bytes());
+        }
+    }
 
-            for k in 0..8 {
-                let x = tx * 8 + self.window.0 as usize - 7;
-                let kb = 7 - k;
-                let c = (((buf[1] >> kb) & 1) << 1) | ((buf[0] >> kb) & 1);
+    /// Where the row `iy` of a tile starts, from a tile map entry and its cgb attributes.
+    fn tiledata_base(&self, tile: u8, attr: u8, iy: usize) -> usize {
+        let iy = if attr & 0x40 != 0 { 7 - iy } else { iy };
+        let bank = (attr as usize >> 3 & 1) * 0x2000;
 
-                let x = x as isize + k as isize;
-                if (0..160).contains(&x) {
-                    strip_bg[x as usize] = c;
-                    any = true;
-                } else if 0 <= x {
-                    break 'a;
-                }
-            }
+        if self.lcdc & 0x10 == 0 {
+            bank + (0x1000 + tile as i8 as isize * 16) as usize + iy * 2
+        } else {
+            bank + tile as usize * 16 + iy * 2
         }
+    }
 
-        any
+    /// The tile and its attributes at a tile map offset, attributes only exist in cgb mode.
+    fn tilemap_entry(&self, offset: usize) -> (u8, u8) {
+        let attr = if self.cgb && !self.dmg_compat { self.vram[0x2000 + offset] } else { 0 };
+        (self.vram[offset], attr)
     }
 
     #[inline]
@@ -246,17 +481,22 @@ impl Ppu {
         if !prev && self.stat_request != 0 { int_mgr.interrupt(1); }
     }
 
-    fn get_mode(&self) -> usize {
-        match (self.hsync, self.ly) {
-            (0..80, ..144) => 2,
-            (80.., ..144) if (..172 + self.mode_3_penalty).contains(&(self.hsync - 80)) => 3,
-            (_, ..144) => 0,
-            _ => 1,
-        }
+    /// The mode STAT shows, an lcd that is off always looks like it is in hblank.
+    pub(crate) fn get_mode(&self) -> usize {
+        if self.is_disabled() { 0 } else { self.mode as usize }
     }
 
     fn get_stat(&self) -> u8 {
-        0x80 | self.stat | (((self.ly == self.lyc) as u8) << 2) | self.get_mode() as u8
+        0x80 | self.stat | (((self.get_ly() == self.lyc) as u8) << 2) | self.get_mode() as u8
+    }
+
+    /// LY as the cpu sees it, which is 0 with the lcd off and goes back to 0 early in line 153.
+    fn get_ly(&self) -> u8 {
+        match self.ly {
+            _ if self.is_disabled() => 0,
+            153 if self.hsync >= 4 => 0,
+            ly => ly,
+        }
     }
 
     fn set_stat(&mut self, v: u8) {
@@ -265,34 +505,123 @@ impl Ppu {
 
     fn is_disabled(&self) -> bool { self.lcdc & 0x80 == 0 }
 
-    pub(crate) fn load(&self, addr: u16) -> u8 {
+    /// The screen goes blank as soon as the lcd is turned off.
+    fn turn_off(&mut self) {
+        self.hsync = 0;
+        self.ly = 0;
+        self.mode = 0;
+        self.blank();
+    }
+
+    /// Shows a white screen until the next frame is done.
+    pub(crate) fn blank(&self) {
+        let white = if self.cgb { 0x7fff } else { 0 };
+        self.front_buffer.lock().unwrap().fill(white);
+    }
+
+    /// The first line after turning the lcd on is 4 dots short and skips the oam scan, so it
+    /// stays in mode 0 until drawing starts.
+    fn turn_on(&mut self) {
+        self.ly = 0;
+        self.hsync = 4;
+        self.mode = 0;
+        self.objc = 0;
+
+        self.wly = 0;
+        self.wy_hit = self.window.1 == 0;
+        self.win_line = false;
+        self.skip_frame = true;
+    }
+
+    /// Where a vram address is in the bank VBK picks.
+    pub(crate) fn vram_at(&self, addr: u16) -> usize {
+        self.vbk as usize * 0x2000 + (addr as usize & 0x1fff)
+    }
+
+    /// The oam row the scan is reading, if a dmg would corrupt it on a cpu access. The first
+    /// row is never corrupted.
+    pub(crate) fn oam_bug_row(&self) -> Option<usize> {
+        if self.cgb || self.is_disabled() || self.mode != 2 { return None; }
+        Some(self.hsync / 4).filter(|r| (1..20).contains(r))
+    }
+
+    fn oam_word(&self, row: usize, w: usize) -> u16 {
+        u16::from_le_bytes([self.oam[row * 8 + w * 2], self.oam[row * 8 + w * 2 + 1]])
+    }
+
+    fn set_oam_word(&mut self, row: usize, w: usize, v: u16) {
+        self.oam[row * 8 + w * 2..row * 8 + w * 2 + 2].copy_from_slice(&v.to_le_bytes());
+    }
+
+    /// The rest of a corrupted row comes from the row before it.
+    fn copy_oam_row(&mut self, from: usize, to: usize, start: usize) {
+        self.oam.copy_within(from * 8 + start..from * 8 + 8, to * 8 + start);
+    }
+
+    pub(crate) fn oam_bug_write(&mut self, row: usize) {
+        let (a, b, c) = (self.oam_word(row, 0), self.oam_word(row - 1, 0), self.oam_word(row - 1, 2));
+        self.set_oam_word(row, 0, ((a ^ c) & (b ^ c)) ^ c);
+        self.copy_oam_row(row - 1, row, 2);
+    }
+
+    pub(crate) fn oam_bug_read(&mut self, row: usize) {
+        let (a, b, c) = (self.oam_word(row, 0), self.oam_word(row - 1, 0), self.oam_word(row - 1, 2));
+        self.set_oam_word(row, 0, b | (a & c));
+        self.copy_oam_row(row - 1, row, 2);
+    }
+
+    /// A read on the same m-cycle as an increment or decrement, which also spreads the row before
+    /// to its neighbours unless it is near either end.
+    pub(crate) fn oam_bug_read_idu(&mut self, row: usize) {
+        if (4..19).contains(&row) {
+            let (a, b) = (self.oam_word(row - 2, 0), self.oam_word(row - 1, 0));
+            let (c, d) = (self.oam_word(row, 0), self.oam_word(row - 2, 2));
+            self.set_oam_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+            self.copy_oam_row(row - 1, row, 0);
+            self.copy_oam_row(row - 1, row - 2, 0);
+        }
+
+        self.oam_bug_read(row);
+    }
+
+    pub fn load(&self, addr: u16) -> u8 {
         match (addr, self.get_mode()) {
-            // TODO: get good timings to not glich games
-            (0x8000..=0x9fff, _) => self.vram[addr as usize - 0x8000],
+            // the ppu has vram to itself while drawing and oam from the oam scan on
+            (0x8000..=0x9fff, 3) => 0xff,
+            (0xfe00..=0xfe9f, 2 | 3) => 0xff,
+            (0x8000..=0x9fff, _) => self.vram[self.vram_at(addr)],
             (0xfe00..=0xfe9f, _) => self.oam[addr as usize - 0xfe00],
             (0xff40, _) => self.lcdc,
             (0xff41, _) => self.get_stat(),
             (0xff42, _) => self.scroll.1,
             (0xff43, _) => self.scroll.0,
-            (0xff44, _) => self.ly,
+            (0xff44, _) => self.get_ly(),
             (0xff45, _) => self.lyc,
             (0xff47, _) => self.bgp,
             (0xff48..=0xff49, _) => self.obp[addr as usize - 0xff48],
             (0xff4a, _) => self.window.1,
             (0xff4b, _) => self.window.0,
+            (0xff4f, _) if self.cgb => 0xfe | self.vbk,
+            (0xff68, _) if self.cgb => 0x40 | self.bcps,
+            (0xff69 | 0xff6b, 3) if self.cgb => 0xff,
+            (0xff69, _) if self.cgb => self.bg_pal[self.bcps as usize & 0x3f],
+            (0xff6a, _) if self.cgb => 0x40 | self.ocps,
+            (0xff6b, _) if self.cgb => self.obj_pal[self.ocps as usize & 0x3f],
+            (0xff6c, _) if self.cgb => 0xfe | self.opri,
             _ => 0xff,
         }
     }
 
-    pub(crate) fn store(&mut self, addr: u16, data: u8) {
+    pub fn store(&mut self, addr: u16, data: u8) {
         match (addr, self.get_mode()) {
-            // TODO: get good timings to not glich games
-            (0x8000..=0x9fff, _) => self.vram[addr as usize - 0x8000] = data,
+            (0x8000..=0x9fff, 3) | (0xfe00..=0xfe9f, 2 | 3) => {},
+            (0x8000..=0x9fff, _) => self.vram[self.vram_at(addr)] = data,
             (0xfe00..=0xfe9f, _) => self.oam[addr as usize - 0xfe00] = data,
             (0xff40, _) => {
-                if data & 0x80 == 0 {
-                    self.hsync = 0;
-                    self.ly = 0;
+                match (self.is_disabled(), data & 0x80 != 0) {
+                    (false, false) => self.turn_off(),
+                    (true, true) => self.turn_on(),
+                    _ => {},
                 }
                 self.lcdc = data;
             },
@@ -304,7 +633,282 @@ impl Ppu {
             (0xff48..=0xff49, _) => self.obp[addr as usize - 0xff48] = data,
             (0xff4a, _) => self.window.1 = data,
             (0xff4b, _) => self.window.0 = data,
+            (0xff4f, _) if self.cgb => self.vbk = data & 1,
+            (0xff68, _) if self.cgb => self.bcps = data & 0xbf,
+            // the index still moves on when the write is dropped
+            (0xff69, 3) if self.cgb => next_index(&mut self.bcps),
+            (0xff6b, 3) if self.cgb => next_index(&mut self.ocps),
+            (0xff69, _) if self.cgb => write_palette(&mut self.bg_pal, &mut self.bcps, data),
+            (0xff6a, _) if self.cgb => self.ocps = data & 0xbf,
+            (0xff6b, _) if self.cgb => write_palette(&mut self.obj_pal, &mut self.ocps, data),
+            (0xff6c, _) if self.cgb => self.opri = data & 1,
+            (0xff4f | 0xff68..=0xff6c, _) => {},
             _ => eprintln!("ppu write fail {addr:04x} {data:02x} {}", self.get_mode()),
         }
     }
 }
+
+/// The ppu registers as the cpu reads them, for debugging tools.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp: [u8; 2],
+    pub wy: u8,
+    pub wx: u8,
+    /// The rest only mean something on a cgb.
+    pub vbk: u8,
+    pub bcps: u8,
+    pub ocps: u8,
+    pub opri: u8,
+}
+
+/// Read only views into the ppu for debugging tools, none of them take time or change anything.
+impl Ppu {
+    /// Both banks, the second one starts at 0x2000 and only a cgb has it.
+    pub fn vram(&self) -> &[u8; 0x4000] { &self.vram }
+    pub fn oam(&self) -> &[u8; 0xa0] { &self.oam }
+
+    /// Palette ram, 8 palettes of 4 little endian colors each.
+    pub fn bg_palette_ram(&self) -> &[u8; 64] { &self.bg_pal }
+    pub fn obj_palette_ram(&self) -> &[u8; 64] { &self.obj_pal }
+
+    /// Whether the framebuffer holds colors rather than shades.
+    pub fn cgb(&self) -> bool { self.cgb }
+
+    /// Whether tile maps have attributes and objects pick from 8 palettes, which is not the case
+    /// for a dmg game on a cgb.
+    pub fn cgb_mode(&self) -> bool { self.cgb && !self.dmg_compat }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            lcdc: self.lcdc,
+            stat: self.get_stat(),
+            scy: self.scroll.1,
+            scx: self.scroll.0,
+            ly: self.get_ly(),
+            lyc: self.lyc,
+            bgp: self.bgp,
+            obp: self.obp,
+            wy: self.window.1,
+            wx: self.window.0,
+            vbk: self.vbk,
+            bcps: self.bcps,
+            ocps: self.ocps,
+            opri: self.opri,
+        }
+    }
+
+    /// The color indices of a tile, `index` counts tiles from 0x8000 in `bank`.
+    pub fn tile(&self, bank: usize, index: usize) -> [[u8; 8]; 8] {
+        let at = bank * 0x2000 + index * 16;
+        let data = &self.vram[at..at + 16];
+
+        core::array::from_fn(|y| core::array::from_fn(|x| {
+            let (lo, hi) = (data[y * 2] >> (7 - x) & 1, data[y * 2 + 1] >> (7 - x) & 1);
+            (hi << 1) | lo
+        }))
+    }
+
+    /// What the framebuffer gets for color `c` of a palette. A dmg only has BGP and the two OBPs,
+    /// so `palette` picks an OBP for objects and is ignored for the background.
+    pub fn color(&self, obj: bool, palette: u8, c: u8) -> u16 {
+        let dmg = if obj { self.obp[palette as usize & 1] } else { self.bgp };
+        let shade = (dmg >> (c * 2)) & 3;
+        let ram = if obj { &self.obj_pal } else { &self.bg_pal };
+
+        match (self.cgb, self.cgb_mode()) {
+            (false, _) => shade as u16,
+            (true, false) => color(ram, if obj { palette & 1 } else { 0 }, shade),
+            (true, true) => color(ram, palette & 7, c),
+        }
+    }
+}
+
+/// Where the tile a map names is counted from 0x8000, 0x8800 addressing goes both ways from
+/// 0x9000.
+pub fn tile_index(tile: u8, signed: bool) -> usize {
+    if signed { (256 + tile as i8 as isize) as usize } else { tile as usize }
+}
+
+/// Looks up a color in palette ram, as 15 bit rgb.
+fn color(pal: &[u8; 64], p: u8, c: u8) -> u16 {
+    let at = p as usize * 8 + c as usize * 2;
+    u16::from_le_bytes([pal[at], pal[at + 1]]) & 0x7fff
+}
+
+/// A write through BCPD or OCPD, which moves the index along if it was asked to.
+fn write_palette(pal: &mut [u8; 64], spec: &mut u8, data: u8) {
+    pal[*spec as usize & 0x3f] = data;
+    next_index(spec);
+}
+
+fn next_index(spec: &mut u8) {
+    if *spec & 0x80 != 0 { *spec = 0x80 | ((*spec + 1) & 0x3f); }
+}
+
+impl Snapshot for Ppu {
+    fn save_state(&self, w: &mut Writer) {
+        for p in self.back_buffer { w.u16(p); }
+        w.bytes(&self.vram);
+        w.bytes(&self.oam);
+
+        w.bool(self.dmg_compat);
+        w.u8(self.vbk);
+        w.bytes(&self.bg_pal);
+        w.bytes(&self.obj_pal);
+        w.u8(self.bcps);
+        w.u8(self.ocps);
+        w.u8(self.opri);
+
+        w.u8(self.ly);
+        w.u8(self.lyc);
+        w.u8(self.bgp);
+        w.u8(self.scroll.0);
+        w.u8(self.scroll.1);
+        w.u8(self.window.0);
+        w.u8(self.window.1);
+        w.u8(self.lcdc);
+        w.u8(self.obp[0]);
+        w.u8(self.obp[1]);
+
+        w.u8(self.stat);
+        w.u8(self.wly);
+
+        w.usize(self.hsync);
+        w.u8(self.stat_request);
+        w.u8(self.mode);
+
+        w.u8(self.objc as u8);
+        for o in self.objs {
+            w.u8(o.idx);
+            w.u8(o.y);
+            w.u8(o.x);
+            w.u8(o.tile);
+            w.u8(o.attr);
+            w.bool(o.done);
+        }
+
+        let f = self.fetcher;
+        w.u8(f.step);
+        w.u8(f.x);
+        w.bool(f.window);
+        w.u8(f.tile);
+        w.u8(f.attr);
+        w.u8(f.lo);
+        w.u8(f.hi);
+
+        w.u8(self.bg_fifo.0);
+        w.u8(self.bg_fifo.1);
+        w.u8(self.bg_fifo.2);
+        w.u8(self.bg_len);
+        for p in self.obj_fifo {
+            w.u8(p.c);
+            w.u8(p.pal);
+            w.bool(p.prio);
+            w.u8(p.idx);
+        }
+        w.u8(self.obj_len);
+
+        w.u8(self.lx);
+        w.u8(self.discard);
+        w.bool(self.first_fetch);
+        w.u8(self.obj_stall);
+        w.u8(self.obj_fetch as u8);
+        w.bool(self.obj_tile.is_some());
+        w.u16(self.obj_tile.unwrap_or(0) as u16);
+
+        w.bool(self.wy_hit);
+        w.bool(self.win_line);
+        w.bool(self.skip_frame);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        for p in self.back_buffer.iter_mut() { *p = r.u16()?; }
+        r.bytes(&mut self.vram)?;
+        r.bytes(&mut self.oam)?;
+
+        self.dmg_compat = r.bool()?;
+        self.vbk = r.u8()? & 1;
+        r.bytes(&mut self.bg_pal)?;
+        r.bytes(&mut self.obj_pal)?;
+        self.bcps = r.u8()? & 0xbf;
+        self.ocps = r.u8()? & 0xbf;
+        self.opri = r.u8()? & 1;
+
+        self.ly = r.u8()?;
+        self.lyc = r.u8()?;
+        self.bgp = r.u8()?;
+        self.scroll = (r.u8()?, r.u8()?);
+        self.window = (r.u8()?, r.u8()?);
+        self.lcdc = r.u8()?;
+        self.obp = [r.u8()?, r.u8()?];
+
+        self.stat = r.u8()?;
+        self.wly = r.u8()?;
+
+        self.hsync = r.usize()?;
+        self.stat_request = r.u8()?;
+
+        if self.hsync >= 456 || self.ly >= 154 {
+            return Err(StateError::Invalid("ppu timing"));
+        }
+
+        self.mode = r.u8()?;
+
+        self.objc = r.u8()? as usize;
+        for o in self.objs.iter_mut() {
+            *o = LineObj {
+                idx: r.u8()?,
+                y: r.u8()?,
+                x: r.u8()?,
+                tile: r.u8()?,
+                attr: r.u8()?,
+                done: r.bool()?,
+            };
+        }
+
+        self.fetcher = Fetcher {
+            step: r.u8()?,
+            x: r.u8()?,
+            window: r.bool()?,
+            tile: r.u8()?,
+            attr: r.u8()?,
+            lo: r.u8()?,
+            hi: r.u8()?,
+        };
+
+        self.bg_fifo = (r.u8()?, r.u8()?, r.u8()?);
+        self.bg_len = r.u8()?;
+        for p in self.obj_fifo.iter_mut() {
+            *p = ObjPixel { c: r.u8()?, pal: r.u8()?, prio: r.bool()?, idx: r.u8()? };
+        }
+        self.obj_len = r.u8()?;
+
+        self.lx = r.u8()?;
+        self.discard = r.u8()?;
+        self.first_fetch = r.bool()?;
+        self.obj_stall = r.u8()?;
+        self.obj_fetch = r.u8()? as usize;
+        let has_tile = r.bool()?;
+        let tile = r.u16()? as i16;
+        self.obj_tile = has_tile.then_some(tile);
+
+        self.wy_hit = r.bool()?;
+        self.win_line = r.bool()?;
+        self.skip_frame = r.bool()?;
+
+        if self.mode > 3 || self.objc > 10 || self.obj_fetch >= 10 || self.fetcher.step > 6
+            || self.bg_len > 8 || self.obj_len > 8 || self.lx > 160
+        {
+            return Err(StateError::Invalid("ppu pipeline"));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+/// Size of the rtc trailer appended to `.sav` files, in the layout shared by most emulators.
+pub const TRAILER_SIZE: usize = 48;
+
+pub enum Clock {
+    /// Ticks with emulated cycles, so time stops while the emulator isn't running.
+    Emulated,
+    /// Follows a wall clock returning unix seconds.
+    Wall(Box<dyn Fn() -> u64 + Send>),
+}
+
+impl Clock {
+    pub fn system() -> Self {
+        Self::Wall(Box::new(unix_now))
+    }
+
+    pub(crate) fn now(&self) -> u64 {
+        match self {
+            Self::Emulated => 0,
+            Self::Wall(f) => f(),
+        }
+    }
+
+    /// The unix time a save is stamped with, which other emulators read too. An emulated clock
+    /// has no idea of it and takes the system's.
+    pub(crate) fn timestamp(&self) -> u64 {
+        match self {
+            Self::Emulated => unix_now(),
+            Self::Wall(f) => f(),
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Time {
+    s: u8,
+    m: u8,
+    h: u8,
+    days: u16,
+    halt: bool,
+    carry: bool,
+}
+
+impl Time {
+    fn from_regs(r: &[u8]) -> Self {
+        Self {
+            s: r[0] & 0x3f,
+            m: r[1] & 0x3f,
+            h: r[2] & 0x1f,
+            days: r[3] as u16 | ((r[4] as u16 & 1) << 8),
+            halt: r[4] & 0x40 != 0,
+            carry: r[4] & 0x80 != 0,
+        }
+    }
+
+    fn regs(&self) -> [u8; 5] {
+        [
+            self.s,
+            self.m,
+            self.h,
+            self.days as u8,
+            ((self.carry as u8) << 7) | ((self.halt as u8) << 6) | (self.days >> 8) as u8,
+        ]
+    }
+
+    fn tick(&mut self) {
+        // the counters are plain binary, out of range values count up to the bit limit and wrap
+        // without carrying
+        self.s = (self.s + 1) & 0x3f;
+        if self.s != 60 { return; }
+        self.s = 0;
+
+        self.m = (self.m + 1) & 0x3f;
+        if self.m != 60 { return; }
+        self.m = 0;
+
+        self.h = (self.h + 1) & 0x1f;
+        if self.h != 24 { return; }
+        self.h = 0;
+
+        self.days += 1;
+        if self.days == 512 {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+
+    fn advance(&mut self, mut secs: u64) {
+        if self.halt { return; }
+
+        while secs != 0 && (self.s >= 60 || self.m >= 60 || self.h >= 24) {
+            self.tick();
+            secs -= 1;
+        }
+
+        let total = self.s as u64 + self.m as u64 * 60 + self.h as u64 * 3600 + self.days as u64 * 86400 + secs;
+        let days = total / 86400;
+
+        self.s = (total % 60) as u8;
+        self.m = (total / 60 % 60) as u8;
+        self.h = (total / 3600 % 24) as u8;
+        self.days = (days % 512) as u16;
+        self.carry |= days >= 512;
+    }
+}
+
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub struct Rtc {
+    time: Time,
+
+    latched: [u8; 5],
+    latch_prev: u8,
+
+    sub_cycles: usize,
+    #[derivative(Debug = "ignore")]
+    clock: Clock,
+    last_sync: u64,
+}
+
+impl Rtc {
+    pub(crate) fn new() -> Self {
+        Self {
+            time: Time::default(),
+
+            latched: [0; 5],
+            latch_prev: 0xff,
+
+            sub_cycles: 0,
+            clock: Clock::Emulated,
+            last_sync: 0,
+        }
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+        self.last_sync = self.clock.now();
+    }
+
+    pub(crate) fn step(&mut self) {
+        if self.time.halt || !matches!(self.clock, Clock::Emulated) { return; }
+
+        self.sub_cycles += 1;
+        if self.sub_cycles >= crate::CLOCK_HZ {
+            self.sub_cycles = 0;
+            self.time.tick();
+        }
+    }
+
+    /// The live time, caught up with the wall clock if there is one.
+    fn current(&self) -> Time {
+        let mut t = self.time;
+
+        if !matches!(self.clock, Clock::Emulated) {
+            t.advance(self.clock.now().saturating_sub(self.last_sync));
+        }
+
+        t
+    }
+
+    fn sync(&mut self) {
+        self.time = self.current();
+        self.last_sync = self.clock.now();
+    }
+
+    pub(crate) fn latch(&mut self, d: u8) {
+        if self.latch_prev == 0 && d == 1 {
+            self.latched = self.current().regs();
+        }
+
+        self.latch_prev = d;
+    }
+
+    pub(crate) fn load(&self, reg: u8) -> u8 {
+        let v = self.latched[reg as usize - 8];
+
+        match reg {
+            0x08 | 0x09 => v | 0xc0,
+            0x0a => v | 0xe0,
+            0x0b => v,
+            0x0c => v | 0x3e,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn store(&mut self, reg: u8, d: u8) {
+        self.sync();
+
+        let t = &mut self.time;
+        match reg {
+            0x08 => {
+                t.s = d & 0x3f;
+                self.sub_cycles = 0;
+            },
+            0x09 => t.m = d & 0x3f,
+            0x0a => t.h = d & 0x1f,
+            0x0b => t.days = (t.days & 0x100) | d as u16,
+            0x0c => {
+                t.days = (t.days & 0xff) | ((d as u16 & 1) << 8);
+                t.halt = d & 0x40 != 0;
+                t.carry = d & 0x80 != 0;
+            },
+            _ => unreachable!(),
+        }
+
+        self.latched[reg as usize - 8] = self.time.regs()[reg as usize - 8];
+    }
+
+    /// Live registers, latched registers and a unix timestamp, as 32 bit and 64 bit little
+    /// endian values.
+    pub(crate) fn get_trailer(&self) -> [u8; TRAILER_SIZE] {
+        let mut t = [0; TRAILER_SIZE];
+        for (i, r) in self.current().regs().into_iter().chain(self.latched).enumerate() {
+            t[i * 4..i * 4 + 4].copy_from_slice(&(r as u32).to_le_bytes());
+        }
+
+        t[40..].copy_from_slice(&self.clock.timestamp().to_le_bytes());
+        t
+    }
+
+    /// Also takes the older 44 byte layout with a 32 bit timestamp. With a wall clock, the time
+    /// passed since the save was written is added on.
+    pub(crate) fn set_trailer(&mut self, t: &[u8]) {
+        let regs = (0..10).map(|i| t[i * 4]).collect::<Vec<_>>();
+        self.time = Time::from_regs(&regs[..5]);
+        self.latched.copy_from_slice(&regs[5..]);
+
+        let saved_at = if t.len() >= 48 {
+            u64::from_le_bytes(t[40..48].try_into().unwrap())
+        } else {
+            u32::from_le_bytes(t[40..44].try_into().unwrap()) as u64
+        };
+
+        if !matches!(self.clock, Clock::Emulated) {
+            self.time.advance(self.clock.now().saturating_sub(saved_at));
+            self.last_sync = self.clock.now();
+        }
+    }
+}
+
+impl Snapshot for Rtc {
+    fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.current().regs());
+        w.bytes(&self.latched);
+        w.u8(self.latch_prev);
+        w.usize(self.sub_cycles);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        let mut regs = [0; 5];
+        r.bytes(&mut regs)?;
+        self.time = Time::from_regs(&regs);
+
+        r.bytes(&mut self.latched)?;
+        self.latch_prev = r.u8()?;
+        self.sub_cycles = r.usize()? % crate::CLOCK_HZ;
+
+        // don't count the time spent before the state was loaded
+        self.last_sync = self.clock.now();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+/// The other end of the link port, implemented by the embedder.
+pub trait LinkCable {
+    /// Called when this side finishes a transfer on its internal clock. Returns the byte the
+    /// other side shifted back, 0xff when nothing answers.
+    fn transfer(&mut self, data: u8) -> u8;
+
+    /// Called once per bit period while this side is not clocking a transfer itself. `data` is
+    /// the byte waiting to be shifted out if the game armed a transfer on the external clock,
+    /// `None` if the port isn't listening. Returns the byte shifted in when the other side
+    /// clocked a transfer, which also finishes ours.
+    fn poll(&mut self, data: Option<u8>) -> Option<u8>;
+}
+
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub(crate) struct Serial<'a> {
+    pub(crate) sb: u8,
+    pub(crate) sc: u8,
+    /// Bit 1 of SC picks the fast clock, only on a CGB.
+    cgb: bool,
+
+    bits: u8,
+    clk_prev: bool,
+
+    #[derivative(Debug = "ignore")]
+    pub(crate) cable: Option<Box<dyn LinkCable + 'a>>,
+}
+
+impl<'a> Serial<'a> {
+    pub(crate) fn new(cgb: bool) -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            cgb,
+
+            bits: 0,
+            clk_prev: false,
+
+            cable: None,
+        }
+    }
+
+    pub(crate) fn load(&self, a: u16) -> u8 {
+        match a {
+            0xff01 => self.sb,
+            0xff02 => self.sc | !self.sc_mask(),
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn store(&mut self, a: u16, d: u8) {
+        match a {
+            0xff01 => self.sb = d,
+            0xff02 => {
+                self.sc = d & self.sc_mask();
+                self.bits = 0;
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn step(&mut self, div: usize, int_mgr: &mut sm83::cpu::InterruptManager) {
+        // one bit every 512 cycles on the falling edge of div bit 8, or every 16 on bit 3 with
+        // the fast clock
+        let clk = div & if self.sc & 2 != 0 { 0x8 } else { 0x100 } != 0;
+        let prev = core::mem::replace(&mut self.clk_prev, clk);
+        if !prev || clk { return; }
+
+        let armed = self.sc & 0x80 != 0;
+
+        if armed && self.sc & 1 != 0 {
+            self.bits += 1;
+            if self.bits < 8 { return; }
+
+            self.sb = self.cable.as_mut().map_or(0xff, |c| c.transfer(self.sb));
+            self.finish(int_mgr);
+        } else if let Some(cable) = &mut self.cable {
+            // without a cable nothing ever clocks an external transfer, so it hangs forever
+            if let (Some(d), true) = (cable.poll(armed.then_some(self.sb)), armed) {
+                self.sb = d;
+                self.finish(int_mgr);
+            }
+        }
+    }
+
+    fn sc_mask(&self) -> u8 {
+        if self.cgb { 0x83 } else { 0x81 }
+    }
+
+    fn finish(&mut self, int_mgr: &mut sm83::cpu::InterruptManager) {
+        self.sc &= !0x80;
+        self.bits = 0;
+        int_mgr.interrupt(3);
+    }
+}
+
+impl Snapshot for Serial<'_> {
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.sb);
+        w.u8(self.sc);
+        w.u8(self.bits);
+        w.bool(self.clk_prev);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.sb = r.u8()?;
+        self.sc = r.u8()? & self.sc_mask();
+        self.bits = r.u8()? & 7;
+        self.clk_prev = r.bool()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,434 @@
+use std::sync::{Arc, Mutex};
+
+use crate::state::{Reader, Snapshot, StateError, Writer};
+
+/// What the SNES shows: the colored game in the middle of its border, as 15 bit colors in the
+/// same format as a CGB's.
+pub type Framebuffer = Arc<Mutex<[u16; 256 * 224]>>;
+
+/// Where the game sits inside the border.
+const GAME_X: usize = 48;
+const GAME_Y: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    Off,
+    Freeze,
+    Black,
+    Color0,
+}
+
+/// What the next frame gets copied into, games show the data as tiles on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transfer {
+    Palettes,
+    Tiles(bool),
+    Border,
+    Attributes,
+}
+
+#[derive(derivative::Derivative)]
+#[derivative(Debug)]
+pub(crate) struct Sgb {
+    #[derivative(Debug = "ignore")]
+    pub(crate) screen: Option<Framebuffer>,
+
+    /// The next bit of the packet coming in, `None` until a reset pulse starts one.
+    bit: Option<u8>,
+    packet: [u8; 16],
+    /// Packets of the command so far, the first one says how many there are.
+    #[derivative(Debug = "ignore")]
+    command: Vec<u8>,
+    prev_sel: u8,
+
+    players: u8,
+    pub(crate) player: u8,
+
+    pal: [[u16; 4]; 4],
+    #[derivative(Debug = "ignore")]
+    system_pal: Box<[u16; 512 * 4]>,
+    #[derivative(Debug = "ignore")]
+    attrs: [u8; 20 * 18],
+    #[derivative(Debug = "ignore")]
+    attr_files: Box<[u8; 45 * 90]>,
+    mask: Mask,
+
+    #[derivative(Debug = "ignore")]
+    border_tiles: Box<[u8; 256 * 32]>,
+    #[derivative(Debug = "ignore")]
+    border_map: Box<[u8; 32 * 28 * 2]>,
+    #[derivative(Debug = "ignore")]
+    border_pal: [[u16; 16]; 4],
+
+    transfer: Option<Transfer>,
+}
+
+impl Sgb {
+    pub(crate) fn new() -> Self {
+        Self {
+            screen: None,
+
+            bit: None,
+            packet: [0; 16],
+            command: Vec::new(),
+            prev_sel: 0x30,
+
+            players: 1,
+            player: 0,
+
+            // the grays it shows before a game picks anything
+            pal: [[0x7fff, 0x56b5, 0x294a, 0x0000]; 4],
+            system_pal: Box::new([0; 512 * 4]),
+            attrs: [0; 20 * 18],
+            attr_files: Box::new([0; 45 * 90]),
+            mask: Mask::Off,
+
+            border_tiles: Box::new([0; 256 * 32]),
+            border_map: Box::new([0; 32 * 28 * 2]),
+            border_pal: [[0; 16]; 4],
+
+            transfer: None,
+        }
+    }
+
+    /// Whether reads of the joypad with nothing selected give the player instead.
+    pub(crate) fn multiplayer(&self) -> bool { self.players > 1 }
+
+    /// Follows writes to the joypad register, which is also how packets come in.
+    pub(crate) fn write_joypad(&mut self, sel: u8) {
+        let prev = core::mem::replace(&mut self.prev_sel, sel);
+
+        // the next player gets read once p15 goes back up
+        if prev & 0x20 == 0 && sel & 0x20 != 0 && self.multiplayer() {
+            self.player = (self.player + 1) % self.players;
+        }
+
+        // a bit is a pulse low on one of the lines, both low resets
+        if prev != 0x30 { return; }
+
+        let bit = match sel {
+            0x00 => {
+                self.bit = Some(0);
+                self.packet = [0; 16];
+                return;
+            },
+            0x10 => 1,
+            0x20 => 0,
+            _ => return,
+        };
+
+        let Some(i) = self.bit else { return };
+        self.packet[i as usize / 8] |= bit << (i % 8);
+
+        if i < 127 {
+            self.bit = Some(i + 1);
+        } else {
+            self.bit = None;
+            self.packet_done();
+        }
+    }
+
+    fn packet_done(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+
+        let packets = (self.command[0] & 7).max(1) as usize;
+        if self.command.len() >= packets * 16 {
+            let command = core::mem::take(&mut self.command);
+            self.run(&command);
+        }
+    }
+
+    fn run(&mut self, c: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([c[i], c[i + 1]]) & 0x7fff;
+
+        match c[0] >> 3 {
+            // PAL01, PAL23, PAL03, PAL12, color 0 is shared by all of them
+            id @ 0x00..=0x03 => {
+                let (a, b) = [(0, 1), (2, 3), (0, 3), (1, 2)][id as usize];
+
+                for p in self.pal.iter_mut() { p[0] = color(1); }
+                for i in 1..4 {
+                    self.pal[a][i] = color(1 + i * 2);
+                    self.pal[b][i] = color(7 + i * 2);
+                }
+            },
+            // ATTR_BLK
+            0x04 => {
+                for set in c[2..].chunks_exact(6).take(c[1] as usize) {
+                    self.attr_block(set);
+                }
+            },
+            // ATTR_LIN
+            0x05 => {
+                for &l in c[2..].iter().take(c[1] as usize) {
+                    let (at, pal) = (l as usize & 0x1f, (l >> 5) & 3);
+
+                    if l & 0x80 != 0 {
+                        if at < 18 { self.attrs[at * 20..at * 20 + 20].fill(pal); }
+                    } else if at < 20 {
+                        for y in 0..18 { self.attrs[y * 20 + at] = pal; }
+                    }
+                }
+            },
+            // ATTR_DIV
+            0x06 => {
+                let (after, before, on) = (c[1] & 3, (c[1] >> 2) & 3, (c[1] >> 4) & 3);
+                let at = c[2] as usize;
+                let rows = c[1] & 0x40 != 0;
+
+                for y in 0..18 {
+                    for x in 0..20 {
+                        let v = if rows { y } else { x };
+                        self.attrs[y * 20 + x] = match v.cmp(&at) {
+                            core::cmp::Ordering::Less => before,
+                            core::cmp::Ordering::Equal => on,
+                            core::cmp::Ordering::Greater => after,
+                        };
+                    }
+                }
+            },
+            // ATTR_CHR
+            0x07 => {
+                let (mut x, mut y) = (c[1] as usize, c[2] as usize);
+                let count = (u16::from_le_bytes([c[3], c[4]]) as usize).min(360);
+                let down = c[5] & 1 != 0;
+
+                for i in 0..count {
+                    let Some(&b) = c.get(6 + i / 4) else { break };
+                    if x >= 20 || y >= 18 { break; }
+
+                    self.attrs[y * 20 + x] = (b >> (6 - (i % 4) * 2)) & 3;
+
+                    if down {
+                        y += 1;
+                        if y == 18 { y = 0; x += 1; }
+                    } else {
+                        x += 1;
+                        if x == 20 { x = 0; y += 1; }
+                    }
+                }
+            },
+            // PAL_SET
+            0x0a => {
+                for p in 0..4 {
+                    let id = u16::from_le_bytes([c[1 + p * 2], c[2 + p * 2]]) as usize & 0x1ff;
+                    self.pal[p].copy_from_slice(&self.system_pal[id * 4..id * 4 + 4]);
+                }
+
+                if c[9] & 0x80 != 0 { self.attr_file(c[9] & 0x3f); }
+                if c[9] & 0x40 != 0 { self.mask = Mask::Off; }
+            },
+            0x0b => self.transfer = Some(Transfer::Palettes), // PAL_TRN
+            // MLT_REQ
+            0x11 => {
+                self.players = [1, 2, 1, 4][c[1] as usize & 3];
+                self.player = 0;
+            },
+            0x13 => self.transfer = Some(Transfer::Tiles(c[1] & 1 != 0)), // CHR_TRN
+            0x14 => self.transfer = Some(Transfer::Border), // PCT_TRN
+            0x15 => self.transfer = Some(Transfer::Attributes), // ATTR_TRN
+            // ATTR_SET
+            0x16 => {
+                self.attr_file(c[1] & 0x3f);
+                if c[1] & 0x40 != 0 { self.mask = Mask::Off; }
+            },
+            // MASK_EN
+            0x17 => self.mask = [Mask::Off, Mask::Freeze, Mask::Black, Mask::Color0][c[1] as usize & 3],
+            // sound and the snes side of things have nothing to show
+            _ => {},
+        }
+    }
+
+    fn attr_block(&mut self, set: &[u8]) {
+        let (inside, line, outside) = (set[0] & 1 != 0, set[0] & 2 != 0, set[0] & 4 != 0);
+        let (p_in, p_line, p_out) = (set[1] & 3, (set[1] >> 2) & 3, (set[1] >> 4) & 3);
+        let (x1, y1, x2, y2) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+
+        // the border goes with whichever side is the only one asked for
+        let line = match (inside, line, outside) {
+            (_, true, _) => Some(p_line),
+            (true, false, false) => Some(p_in),
+            (false, false, true) => Some(p_out),
+            _ => None,
+        };
+
+        for y in 0..18 {
+            for x in 0..20 {
+                let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                let strictly = x1 < x && x < x2 && y1 < y && y < y2;
+
+                let pal = match (strictly, within) {
+                    (true, _) => inside.then_some(p_in),
+                    (false, true) => line,
+                    (false, false) => outside.then_some(p_out),
+                };
+
+                if let Some(p) = pal { self.attrs[y * 20 + x] = p; }
+            }
+        }
+    }
+
+    fn attr_file(&mut self, file: u8) {
+        let Some(data) = self.attr_files.chunks_exact(90).nth(file as usize) else { return };
+
+        for (i, a) in self.attrs.iter_mut().enumerate() {
+            *a = (data[i / 4] >> (6 - (i % 4) * 2)) & 3;
+        }
+    }
+
+    /// Called with the shades of each finished frame, does pending transfers and draws.
+    pub(crate) fn frame(&mut self, shades: &[u16; 160 * 144]) {
+        if let Some(t) = self.transfer.take() {
+            self.vram_transfer(t, &screen_data(shades));
+        }
+
+        let Some(screen) = &self.screen else { return };
+        let mut fb = screen.lock().unwrap();
+
+        for y in 0..224 {
+            for x in 0..256 {
+                let game = (GAME_X..GAME_X + 160).contains(&x) && (GAME_Y..GAME_Y + 144).contains(&y);
+                if !game {
+                    fb[y * 256 + x] = self.border_pixel(x, y);
+                    continue;
+                }
+
+                let (gx, gy) = (x - GAME_X, y - GAME_Y);
+                fb[y * 256 + x] = match self.mask {
+                    Mask::Off => self.pal[self.attrs[gy / 8 * 20 + gx / 8] as usize][shades[gy * 160 + gx] as usize & 3],
+                    Mask::Freeze => continue,
+                    Mask::Black => 0,
+                    Mask::Color0 => self.pal[0][0],
+                };
+            }
+        }
+    }
+
+    fn border_pixel(&self, x: usize, y: usize) -> u16 {
+        let at = (y / 8 * 32 + x / 8) * 2;
+        let entry = u16::from_le_bytes([self.border_map[at], self.border_map[at + 1]]);
+
+        let tile = &self.border_tiles[(entry as usize & 0xff) * 32..][..32];
+        let r = if entry & 0x8000 != 0 { 7 - y % 8 } else { y % 8 };
+        let k = if entry & 0x4000 != 0 { x % 8 } else { 7 - x % 8 };
+
+        // snes tiles keep planes 0 and 1 together, then 2 and 3
+        let c = [tile[r * 2], tile[r * 2 + 1], tile[16 + r * 2], tile[17 + r * 2]].iter()
+            .enumerate()
+            .fold(0, |c, (p, b)| c | (((b >> k) & 1) << p));
+
+        if c == 0 { self.pal[0][0] } else { self.border_pal[(entry as usize >> 10) & 3][c as usize] }
+    }
+
+    fn vram_transfer(&mut self, t: Transfer, data: &[u8; 0x1000]) {
+        let colors = data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]]) & 0x7fff);
+
+        match t {
+            Transfer::Palettes => {
+                for (p, c) in self.system_pal.iter_mut().zip(colors) { *p = c; }
+            },
+            Transfer::Tiles(high) => {
+                let at = high as usize * 0x1000;
+                self.border_tiles[at..at + 0x1000].copy_from_slice(data);
+            },
+            Transfer::Border => {
+                self.border_map.copy_from_slice(&data[..32 * 28 * 2]);
+                for (p, c) in self.border_pal.iter_mut().flatten().zip(colors.skip(0x400)) { *p = c; }
+            },
+            Transfer::Attributes => self.attr_files.copy_from_slice(&data[..45 * 90]),
+        }
+    }
+}
+
+/// The first 256 tiles on screen, read back as tile data the way the SGB sees a transfer.
+fn screen_data(shades: &[u16; 160 * 144]) -> [u8; 0x1000] {
+    let mut data = [0; 0x1000];
+
+    for (t, tile) in data.chunks_exact_mut(16).enumerate() {
+        let (tx, ty) = (t % 20 * 8, t / 20 * 8);
+
+        for r in 0..8 {
+            for k in 0..8 {
+                let s = shades[(ty + r) * 160 + tx + k] as u8;
+                tile[r * 2] |= (s & 1) << (7 - k);
+                tile[r * 2 + 1] |= ((s >> 1) & 1) << (7 - k);
+            }
+        }
+    }
+
+    data
+}
+
+impl Snapshot for Sgb {
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.bit.map_or(0xff, |b| b));
+        w.bytes(&self.packet);
+        w.u8((self.command.len() / 16) as u8);
+        w.bytes(&self.command);
+        w.u8(self.prev_sel);
+
+        w.u8(self.players);
+        w.u8(self.player);
+
+        for c in self.pal.iter().flatten().chain(self.system_pal.iter()) { w.u16(*c); }
+        w.bytes(&self.attrs);
+        w.bytes(&*self.attr_files);
+        w.u8(self.mask as u8);
+
+        w.bytes(&*self.border_tiles);
+        w.bytes(&*self.border_map);
+        for c in self.border_pal.iter().flatten() { w.u16(*c); }
+
+        w.u8(match self.transfer {
+            None => 0,
+            Some(Transfer::Palettes) => 1,
+            Some(Transfer::Tiles(false)) => 2,
+            Some(Transfer::Tiles(true)) => 3,
+            Some(Transfer::Border) => 4,
+            Some(Transfer::Attributes) => 5,
+        });
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.bit = match r.u8()? {
+            0xff => None,
+            b @ 0..128 => Some(b),
+            _ => return Err(StateError::Invalid("sgb packet")),
+        };
+        r.bytes(&mut self.packet)?;
+
+        let packets = r.u8()? as usize;
+        if packets >= 7 { return Err(StateError::Invalid("sgb packet")); }
+        self.command = vec![0; packets * 16];
+        r.bytes(&mut self.command)?;
+        self.prev_sel = r.u8()? & 0x30;
+
+        self.players = r.u8()?;
+        self.player = r.u8()?;
+        if ![1, 2, 4].contains(&self.players) || self.player >= self.players {
+            return Err(StateError::Invalid("sgb players"));
+        }
+
+        for c in self.pal.iter_mut().flatten().chain(self.system_pal.iter_mut()) { *c = r.u16()? & 0x7fff; }
+        r.bytes(&mut self.attrs)?;
+        for a in self.attrs.iter_mut() { *a &= 3; }
+        r.bytes(&mut *self.attr_files)?;
+        self.mask = [Mask::Off, Mask::Freeze, Mask::Black, Mask::Color0][r.u8()? as usize & 3];
+
+        r.bytes(&mut *self.border_tiles)?;
+        r.bytes(&mut *self.border_map)?;
+        for c in self.border_pal.iter_mut().flatten() { *c = r.u16()? & 0x7fff; }
+
+        self.transfer = match r.u8()? {
+            0 => None,
+            1 => Some(Transfer::Palettes),
+            2 => Some(Transfer::Tiles(false)),
+            3 => Some(Transfer::Tiles(true)),
+            4 => Some(Transfer::Border),
+            5 => Some(Transfer::Attributes),
+            _ => return Err(StateError::Invalid("sgb transfer")),
+        };
+
+        Ok(())
+    }
+}
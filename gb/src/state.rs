@@ -0,0 +1,126 @@
+use core::fmt;
+
+pub const MAGIC: [u8; 4] = *b"GWSS";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    RomMismatch,
+    ModelMismatch,
+    BootRomMissing,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a save state"),
+            Self::UnsupportedVersion(v) => write!(f, "save state version {v} is not supported (expected {VERSION})"),
+            Self::Truncated => write!(f, "save state is truncated"),
+            Self::RomMismatch => write!(f, "save state was made with a different rom"),
+            Self::ModelMismatch => write!(f, "save state was made on a different model"),
+            Self::BootRomMissing => write!(f, "save state was made while the boot rom was mapped, but no boot rom is loaded"),
+            Self::Invalid(what) => write!(f, "save state has an invalid {what}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+pub(crate) trait Snapshot {
+    fn save_state(&self, w: &mut Writer);
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError>;
+}
+
+pub(crate) struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Self {
+        let mut buf = Vec::new();
+        buf.extend(MAGIC);
+        buf.extend(VERSION.to_le_bytes());
+        buf.extend(0_u32.to_le_bytes()); // payload length, patched in `finish`
+
+        Self { buf }
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let len = (self.buf.len() - 10) as u32;
+        self.buf[6..10].copy_from_slice(&len.to_le_bytes());
+        self.buf
+    }
+
+    pub(crate) fn u8(&mut self, v: u8) { self.buf.push(v); }
+    pub(crate) fn u16(&mut self, v: u16) { self.buf.extend(v.to_le_bytes()); }
+    pub(crate) fn u32(&mut self, v: u32) { self.buf.extend(v.to_le_bytes()); }
+    pub(crate) fn u64(&mut self, v: u64) { self.buf.extend(v.to_le_bytes()); }
+    pub(crate) fn usize(&mut self, v: usize) { self.u64(v as u64); }
+    pub(crate) fn bool(&mut self, v: bool) { self.u8(v as u8); }
+
+    /// Fixed size data, the reader must know the length.
+    pub(crate) fn bytes(&mut self, v: &[u8]) { self.buf.extend_from_slice(v); }
+
+    /// Length prefixed data.
+    pub(crate) fn vec(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.bytes(v);
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Checks the header, so that a truncated state is rejected before anything gets
+    /// overwritten.
+    pub(crate) fn new(buf: &'a [u8]) -> Result<Self, StateError> {
+        if buf.len() < 10 { return Err(StateError::Truncated); }
+        if buf[..4] != MAGIC { return Err(StateError::BadMagic); }
+
+        let version = u16::from_le_bytes([buf[4], buf[5]]);
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let len = u32::from_le_bytes(buf[6..10].try_into().unwrap()) as usize;
+        if buf.len() - 10 != len { return Err(StateError::Truncated); }
+
+        Ok(Self { buf: &buf[10..] })
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.buf.len() < n { return Err(StateError::Truncated); }
+
+        let (l, r) = self.buf.split_at(n);
+        self.buf = r;
+        Ok(l)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> { Ok(self.take(1)?[0]) }
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> { Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap())) }
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> { Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap())) }
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> { Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap())) }
+    pub(crate) fn usize(&mut self) -> Result<usize, StateError> { Ok(self.u64()? as usize) }
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> { Ok(self.u8()? != 0) }
+
+    pub(crate) fn bytes(&mut self, v: &mut [u8]) -> Result<(), StateError> {
+        v.copy_from_slice(self.take(v.len())?);
+        Ok(())
+    }
+
+    /// Reads length prefixed data into `v`, which must already have the right length.
+    pub(crate) fn vec(&mut self, v: &mut [u8], what: &'static str) -> Result<(), StateError> {
+        if self.u32()? as usize != v.len() { return Err(StateError::Invalid(what)); }
+        self.bytes(v)
+    }
+
+    pub(crate) fn finish(self) -> Result<(), StateError> {
+        if self.buf.is_empty() { Ok(()) } else { Err(StateError::Invalid("length")) }
+    }
+}
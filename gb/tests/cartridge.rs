@@ -0,0 +1,73 @@
+//! Reading the cartridge header.
+
+mod common;
+
+use gb::cartridge::{CgbFlag, Header, Licensee, NINTENDO_LOGO};
+
+#[test]
+fn dmg_header() {
+    let mut rom = common::rom(&[]);
+    rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x134..0x144].copy_from_slice(b"SIXTEEN BYTES!!!");
+    rom[0x146] = 0x03;
+    rom[0x147] = 0x13;
+    rom[0x148] = 0x05;
+    rom[0x149] = 0x03;
+    rom[0x14a] = 0x01;
+    rom[0x14b] = 0x01;
+    rom[0x14c] = 0x02;
+    common::checksum(&mut rom);
+
+    let header = Header::parse(&rom).unwrap();
+    assert_eq!(header.entry_point, [0x00, 0xc3, 0x50, 0x01]);
+    assert!(header.logo_valid);
+    assert_eq!(header.title, "SIXTEEN BYTES!!!");
+    assert_eq!(header.manufacturer, None);
+    assert_eq!(header.cgb, CgbFlag::DmgOnly);
+    assert_eq!(header.licensee, Licensee::Old(0x01));
+    assert!(header.sgb);
+    assert_eq!(header.cart_type_name(), "MBC3+RAM+BATTERY");
+    assert_eq!(header.rom_banks(), Some(64));
+    assert_eq!(header.ram_banks(), Some(4));
+    assert!(!header.japanese);
+    assert_eq!(header.version, 2);
+    assert!(header.header_checksum_valid());
+    assert!(!header.global_checksum_valid());
+}
+
+#[test]
+fn cgb_header() {
+    let mut rom = common::rom(&[]);
+    rom[0x134..0x13f].copy_from_slice(b"TITLE\0\0\0\0\0\0");
+    rom[0x13f..0x143].copy_from_slice(b"ABCD");
+    rom[0x143] = 0xc0;
+    rom[0x144..0x146].copy_from_slice(b"01");
+    rom[0x14b] = 0x33;
+    rom[0x148] = 0x09;
+    rom[0x149] = 0x01;
+    common::checksum(&mut rom);
+
+    let sum = rom.iter().fold(0_u16, |c, b| c.wrapping_add(*b as u16));
+    rom[0x14e..0x150].copy_from_slice(&sum.to_be_bytes());
+
+    let header = Header::parse(&rom).unwrap();
+    assert!(!header.logo_valid);
+    assert_eq!(header.title, "TITLE");
+    assert_eq!(header.manufacturer, Some(*b"ABCD"));
+    assert_eq!(header.cgb, CgbFlag::CgbOnly);
+    assert_eq!(header.licensee, Licensee::New(*b"01"));
+    assert!(header.japanese);
+    assert_eq!(header.rom_banks(), None);
+    assert_eq!(header.ram_banks(), None);
+    assert!(header.global_checksum_valid());
+
+    // dmg carts have no manufacturer code, those bytes are still the title
+    rom[0x143] = 0x00;
+    let header = Header::parse(&rom).unwrap();
+    assert_eq!((header.title.as_str(), header.manufacturer), ("TITLE", None));
+}
+
+#[test]
+fn too_small() {
+    assert_eq!(Header::parse(&[0; 0x14f]), Err(gb::mapper::RomError::TooSmall(0x14f)));
+}
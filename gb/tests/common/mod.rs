@@ -0,0 +1,55 @@
+//! A tiny rom and the machine to run it, for tests that only need a few instructions.
+
+// every test uses a different part of this
+#![allow(dead_code)]
+
+use std::sync::{atomic::AtomicU8, Arc, Mutex};
+
+/// Counts up in `a` and stores every count at 0xc000.
+pub const COUNTER: [u8; 9] = [
+    0x3e, 0x00,       // 0150 ld a, 0
+    0x3c,             // 0152 inc a
+    0xea, 0x00, 0xc0, // 0153 ld [$c000], a
+    0xc3, 0x52, 0x01, // 0156 jp $0152
+];
+
+/// A 32 KiB rom without a mapper, which jumps from the entry point to `program` at 0x0150.
+pub fn rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+    rom[0x150..0x150 + program.len()].copy_from_slice(program);
+    checksum(&mut rom);
+    rom
+}
+
+/// Fills in the header checksum, after changing the header.
+pub fn checksum(rom: &mut [u8]) {
+    rom[0x14d] = rom[0x134..0x14d].iter().fold(0_u8, |c, b| c.wrapping_sub(*b).wrapping_sub(1));
+}
+
+/// Starts `rom` on a dmg without a boot rom, with nothing pressed.
+pub fn gameboy(rom: &[u8]) -> gb::Gameboy<'static> {
+    gameboy_on(gb::Model::Dmg, rom)
+}
+
+pub fn gameboy_on(model: gb::Model, rom: &[u8]) -> gb::Gameboy<'static> {
+    machine(model, gb::mapper::Mapper::from_bin(rom).unwrap())
+}
+
+/// Starts a cartridge the test already set up.
+pub fn machine(model: gb::Model, mapper: gb::mapper::Mapper) -> gb::Gameboy<'static> {
+    let fb = Arc::new(Mutex::new([0; 160 * 144]));
+    gb::Gameboy::new(model, mapper, None, fb, Box::new(|_| {}), Arc::new(AtomicU8::new(0)))
+}
+
+pub fn run(gb: &mut gb::Gameboy, steps: usize) {
+    for _ in 0..steps { gb.step(); }
+}
+
+/// A 32 KiB `cart_type` cartridge with one bank of sram, ignoring the header checksum.
+pub fn mapper(cart_type: u8) -> gb::mapper::Mapper {
+    let mut rom = vec![0; 0x8000];
+    rom[0x147] = cart_type;
+    rom[0x149] = 0x02;
+    gb::mapper::Mapper::from_bin_permissive(&rom).unwrap()
+}
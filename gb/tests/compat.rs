@@ -0,0 +1,48 @@
+use gb::compat::{for_rom, CompatPalette, Preset};
+
+const WHITE_RED: [u16; 4] = [0x7fff, 0x421f, 0x1cf2, 0x0000];
+const WHITE_GREEN: [u16; 4] = [0x7fff, 0x1bef, 0x0200, 0x0000];
+const WHITE_BLUE: [u16; 4] = [0x7fff, 0x7e8c, 0x7c00, 0x0000];
+
+/// A header with nothing but a title and an old licensee code.
+fn rom(title: &str, licensee: u8) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x134..0x134 + title.len()].copy_from_slice(title.as_bytes());
+    rom[0x14b] = licensee;
+    rom
+}
+
+#[test]
+fn tetris() {
+    let yellow = [0x7fff, 0x03ff, 0x001f, 0x0000];
+    assert_eq!(for_rom(&rom("TETRIS", 0x01)), CompatPalette { bg: yellow, obj0: yellow, obj1: yellow });
+}
+
+#[test]
+fn pokemon_red_with_new_licensee() {
+    let mut bin = rom("POKEMON RED", 0x33);
+    bin[0x144..0x146].copy_from_slice(b"01");
+
+    assert_eq!(for_rom(&bin), CompatPalette { bg: WHITE_RED, obj0: WHITE_GREEN, obj1: WHITE_RED });
+}
+
+#[test]
+fn fourth_letter() {
+    // POKEMON BLUE shares its checksum with a title that has an A there
+    assert_eq!(for_rom(&rom("POKEMON BLUE", 0x01)), CompatPalette { bg: WHITE_BLUE, obj0: WHITE_RED, obj1: WHITE_BLUE });
+
+    // and BATMAN shares the checksum of KIRBY2 but has no letter of its own
+    assert_eq!(for_rom(&rom("BATMAN", 0x01)), Preset::DarkGreen.palette());
+    assert_eq!(for_rom(&rom("KIRBY2", 0x01)), for_rom(&rom("KIRBY DREAM LAND", 0x01)));
+}
+
+#[test]
+fn default() {
+    let default = CompatPalette { bg: [0x7fff, 0x1bef, 0x6180, 0x0000], obj0: WHITE_RED, obj1: WHITE_RED };
+
+    assert_eq!(for_rom(&rom("TETRIS", 0x08)), default);
+    assert_eq!(for_rom(&rom("NOT LISTED", 0x01)), default);
+    // right and a held at boot
+    assert_eq!(Preset::from_keys(0x11), Some(Preset::DarkGreen));
+    assert_eq!(Preset::DarkGreen.palette(), default);
+}
@@ -0,0 +1,91 @@
+//! Stopping a headless Game Boy with breakpoints, watchpoints and steps.
+
+mod common;
+
+use gb::debug::{Breakpoint, Condition, Debugger, Reg, Step, Stop, Watchpoint};
+
+/// Counts up in `a` through a call and stores every count at 0xc000.
+const PROGRAM: [u8; 18] = [
+    0x3e, 0x00,       // 0150 ld a, 0
+    0xcd, 0x60, 0x01, // 0152 call $0160
+    0xea, 0x00, 0xc0, // 0155 ld [$c000], a
+    0x18, 0xf8,       // 0158 jr $0152
+    0, 0, 0, 0, 0, 0,
+    0x3c,             // 0160 inc a
+    0xc9,             // 0161 ret
+];
+
+/// A frame, far more than the program needs to get anywhere.
+const DOTS: usize = 70224;
+
+fn gameboy() -> gb::Gameboy<'static> {
+    let mut gb = common::gameboy(&common::rom(&PROGRAM));
+    gb.set_debugger(Some(Debugger::new()));
+    gb
+}
+
+fn pc(gb: &gb::Gameboy) -> u16 {
+    Reg::Pc.get(&gb.cpu_state())
+}
+
+fn go(gb: &mut gb::Gameboy, step: Step) -> Option<Stop> {
+    gb.resume(step);
+    gb.run_until_break(DOTS)
+}
+
+#[test]
+fn breakpoint() {
+    let mut gb = gameboy();
+    gb.debugger().unwrap().breakpoints.push(Breakpoint { pc: 0x0160, bank: None, cond: None });
+
+    assert_eq!(go(&mut gb, Step::Continue), Some(Stop::Breakpoint(0x0160)));
+    assert_eq!(gb.cpu_state().a, 0);
+
+    // resuming from a breakpoint runs on to its next hit
+    assert_eq!(go(&mut gb, Step::Continue), Some(Stop::Breakpoint(0x0160)));
+    assert_eq!(gb.cpu_state().a, 1);
+}
+
+#[test]
+fn conditional_breakpoint() {
+    let mut gb = gameboy();
+    let cond = Some(Condition { reg: Reg::A, value: 3 });
+    gb.debugger().unwrap().breakpoints.push(Breakpoint { pc: 0x0155, bank: None, cond });
+
+    assert_eq!(go(&mut gb, Step::Continue), Some(Stop::Breakpoint(0x0155)));
+    assert_eq!(gb.peek(0xc000), 2);
+}
+
+#[test]
+fn watchpoint() {
+    let mut gb = gameboy();
+    gb.debugger().unwrap().watchpoints.push(Watchpoint { addr: 0xc000, read: false, write: true });
+
+    assert_eq!(go(&mut gb, Step::Continue), Some(Stop::Watchpoint { addr: 0xc000, value: 1, write: true }));
+    assert_eq!(pc(&gb), 0x0158);
+
+    // nothing reads it
+    gb.debugger().unwrap().watchpoints[0] = Watchpoint { addr: 0xc000, read: true, write: false };
+    assert_eq!(gb.run_until_break(DOTS), None);
+}
+
+#[test]
+fn stepping() {
+    let mut gb = gameboy();
+    gb.debugger().unwrap().breakpoints.push(Breakpoint { pc: 0x0152, bank: None, cond: None });
+    assert_eq!(go(&mut gb, Step::Continue), Some(Stop::Breakpoint(0x0152)));
+    gb.debugger().unwrap().breakpoints.clear();
+
+    assert_eq!(go(&mut gb, Step::Over), Some(Stop::Step));
+    assert_eq!((pc(&gb), gb.cpu_state().a), (0x0155, 1));
+
+    assert_eq!(go(&mut gb, Step::Into), Some(Stop::Step));
+    assert_eq!(pc(&gb), 0x0158);
+    assert_eq!(go(&mut gb, Step::Into), Some(Stop::Step));
+    assert_eq!(pc(&gb), 0x0152);
+
+    assert_eq!(go(&mut gb, Step::Into), Some(Stop::Step));
+    assert_eq!(pc(&gb), 0x0160);
+    assert_eq!(go(&mut gb, Step::Out), Some(Stop::Step));
+    assert_eq!((pc(&gb), gb.cpu_state().a), (0x0155, 2));
+}
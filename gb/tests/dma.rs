@@ -0,0 +1,71 @@
+//! CGB copies into vram, general purpose and one block every hblank.
+
+mod common;
+
+use gb::Model;
+
+/// Copies 32 bytes from `src` to 0x8000 with `hdma5`. Returns what ended up in vram, and HDMA5
+/// read right after starting the copy.
+fn copy(src: u16, hdma5: u8) -> (Vec<u8>, u8) {
+    let [src_hi, src_lo] = src.to_be_bytes();
+    let mut rom = common::rom(&[
+        0x3e, 0x0a,       // 0150 ld a, $0a
+        0xea, 0x00, 0x00, // 0152 ld [$0000], a
+        0x3e, src_hi,     // 0155 ld a, src_hi
+        0xe0, 0x51,       // 0157 ldh [$51], a
+        0x3e, src_lo,     // 0159 ld a, src_lo
+        0xe0, 0x52,       // 015b ldh [$52], a
+        0xaf,             // 015d xor a
+        0xe0, 0x53,       // 015e ldh [$53], a
+        0xe0, 0x54,       // 0160 ldh [$54], a
+        0x3e, hdma5,      // 0162 ld a, hdma5
+        0xe0, 0x55,       // 0164 ldh [$55], a
+        0xf0, 0x55,       // 0166 ldh a, [$55]
+        0xea, 0x40, 0xa0, // 0168 ld [$a040], a
+        0xf0, 0x55,       // 016b ldh a, [$55]
+        0x07,             // 016d rlca
+        0xd2, 0x6b, 0x01, // 016e jp nc, $016b
+        0xaf,             // 0171 xor a
+        0xe0, 0x40,       // 0172 ldh [$40], a
+        0x21, 0x00, 0x80, // 0174 ld hl, $8000
+        0x11, 0x00, 0xa0, // 0177 ld de, $a000
+        0x2a,             // 017a ld a, [hl+]
+        0x12,             // 017b ld [de], a
+        0x1c,             // 017c inc e
+        0x7b,             // 017d ld a, e
+        0xfe, 0x20,       // 017e cp $20
+        0xc2, 0x7a, 0x01, // 0180 jp nz, $017a
+        0xc3, 0x83, 0x01, // 0183 jp $0183
+    ]);
+    rom[0x147] = 0x03;
+    rom[0x149] = 0x02;
+    common::checksum(&mut rom);
+    for (i, b) in rom[0x200..0x220].iter_mut().enumerate() { *b = i as u8 ^ 0x5a; }
+
+    let mut gb = common::gameboy_on(Model::Cgb, &rom);
+    common::run(&mut gb, 5000);
+
+    let sram = gb.get_sram().unwrap();
+    (sram[..0x20].to_vec(), sram[0x40])
+}
+
+fn data() -> Vec<u8> {
+    (0..0x20).map(|i| i ^ 0x5a).collect()
+}
+
+#[test]
+fn general_purpose() {
+    // the cpu waits for the whole copy
+    assert_eq!(copy(0x0200, 0x01), (data(), 0xff));
+}
+
+#[test]
+fn hblank() {
+    // nothing is copied until the next hblank, two blocks are left
+    assert_eq!(copy(0x0200, 0x81), (data(), 0x01));
+}
+
+#[test]
+fn past_wram() {
+    assert_eq!(copy(0xfe00, 0x01), (vec![0xff; 0x20], 0xff));
+}
@@ -0,0 +1,102 @@
+//! Drives the gdb stub over TCP like a client would, against a headless Game Boy.
+
+mod common;
+
+use std::{io::{Read, Write}, net::TcpStream, time::Duration};
+
+use gb::gdb::GdbStub;
+
+use common::COUNTER;
+
+struct Session<'a> {
+    gb: gb::Gameboy<'a>,
+    stub: GdbStub,
+    client: TcpStream,
+}
+
+impl Session<'_> {
+    fn new() -> Self {
+        Self::with_program(&COUNTER)
+    }
+
+    fn with_program(program: &[u8]) -> Self {
+        let gb = common::gameboy(&common::rom(program));
+
+        let stub = GdbStub::listen(0).unwrap();
+        let client = TcpStream::connect(stub.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(1))).unwrap();
+
+        Self { gb, stub, client }
+    }
+
+    /// Sends a packet and runs the Game Boy until the reply comes back.
+    fn ask(&mut self, packet: &str) -> String {
+        let sum = packet.bytes().fold(0u8, |s, b| s.wrapping_add(b));
+        write!(self.client, "${packet}#{sum:02x}").unwrap();
+
+        let mut got = Vec::new();
+        for _ in 0..10000 {
+            self.stub.run(&mut self.gb, 100);
+
+            let mut buf = [0; 256];
+            if let Ok(n) = self.client.read(&mut buf) { got.extend(&buf[..n]); }
+
+            let text = String::from_utf8_lossy(&got).into_owned();
+            let text = text.trim_start_matches('+');
+            if let Some(end) = text.find('#').filter(|e| text.len() >= e + 3) {
+                return text[1..end].to_string();
+            }
+        }
+
+        panic!("no reply to {packet}, got {:?}", String::from_utf8_lossy(&got));
+    }
+
+    fn pc(&mut self) -> String {
+        self.ask("p9")
+    }
+}
+
+#[test]
+fn registers_and_memory() {
+    let mut s = Session::new();
+
+    assert_eq!(s.ask("?"), "S05");
+    assert!(s.ask("qXfer:features:read:target.xml:0,fff").contains(r#"<reg name="ime""#));
+
+    let regs = s.ask("g");
+    assert_eq!(regs.len(), 30);
+
+    assert_eq!(s.ask("P0=7f"), "OK");
+    assert_eq!(s.ask("p0"), "7f");
+
+    assert_eq!(s.ask("Mc000,2:1234"), "OK");
+    assert_eq!(s.ask("mc000,2"), "1234");
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let mut s = Session::new();
+    assert_eq!(s.ask("?"), "S05");
+
+    assert_eq!(s.ask("Z0,153,1"), "OK");
+    assert_eq!(s.ask("c"), "S05");
+    assert_eq!(s.pc(), "5301");
+    assert_eq!(s.ask("z0,153,1"), "OK");
+
+    assert_eq!(s.ask("s"), "S05");
+    assert_eq!(s.pc(), "5601");
+
+    assert_eq!(s.ask("Z2,c000,1"), "OK");
+    assert_eq!(s.ask("c"), "T05watch:c000;");
+    assert_eq!(s.ask("mc000,1"), "02");
+}
+
+#[test]
+fn locked_up() {
+    // 0150 is an illegal opcode, the cpu never gets to the end of it
+    let mut s = Session::with_program(&[0xd3]);
+    common::run(&mut s.gb, 1000);
+
+    assert_eq!(s.ask("?"), "S04");
+    assert_eq!(s.ask("p9"), "5001");
+}
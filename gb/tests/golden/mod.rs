@@ -0,0 +1,186 @@
+//! Golden screenshot tests: a rom runs for some frames with scripted input, then the screen has
+//! to match a stored png. On a mismatch the screen and a diff go to the target directory, and
+//! running with `GOLDEN_UPDATE=1` stores the screen as the new golden instead.
+//!
+//! Roms and goldens are found relative to the workspace root, a missing one fails the test.
+//! Tests of roms fetched by `get_tests.sh` are ignored, so they only run with `--ignored`.
+
+use std::{fs::File, io::{BufReader, BufWriter}, path::{Path, PathBuf}, sync::{atomic::*, Arc, Mutex}};
+
+const FRAME_CYCLES: usize = 70224;
+
+/// The joypad bits, as `Gameboy::new` takes them.
+#[allow(dead_code)]
+pub mod keys {
+    pub const RIGHT: u8 = 1 << 0;
+    pub const LEFT: u8 = 1 << 1;
+    pub const UP: u8 = 1 << 2;
+    pub const DOWN: u8 = 1 << 3;
+    pub const A: u8 = 1 << 4;
+    pub const B: u8 = 1 << 5;
+    pub const SELECT: u8 = 1 << 6;
+    pub const START: u8 = 1 << 7;
+}
+
+/// The DMG shades as the dmg-acid2 reference draws them.
+pub const GRAYS: [[u8; 3]; 4] = [[0xff; 3], [0xaa; 3], [0x55; 3], [0x00; 3]];
+
+pub struct Golden {
+    rom: Rom,
+    model: Option<gb::Model>,
+    frames: usize,
+    input: Vec<(usize, u8)>,
+    palette: [[u8; 3]; 4],
+}
+
+enum Rom {
+    File(PathBuf),
+    Bin(Vec<u8>),
+}
+
+#[allow(dead_code)]
+impl Golden {
+    pub fn new(rom: &str) -> Self {
+        Self::with_rom(Rom::File(root().join(rom)))
+    }
+
+    /// A rom the test puts together itself.
+    pub fn bin(rom: Vec<u8>) -> Self {
+        Self::with_rom(Rom::Bin(rom))
+    }
+
+    fn with_rom(rom: Rom) -> Self {
+        Self {
+            rom,
+            model: None,
+            frames: 60,
+            input: Vec::new(),
+            palette: GRAYS,
+        }
+    }
+
+    /// The model to run on, picked from the cartridge header by default.
+    pub fn model(mut self, model: gb::Model) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    pub fn frames(mut self, frames: usize) -> Self {
+        self.frames = frames;
+        self
+    }
+
+    /// Holds `keys` from the start of `frame` until the next press, 0 lets go of everything.
+    pub fn press(mut self, frame: usize, keys: u8) -> Self {
+        self.input.push((frame, keys));
+        self.input.sort_by_key(|&(f, _)| f);
+        self
+    }
+
+    /// The colors of the four DMG shades, the goldens are drawn with `GRAYS` by default.
+    pub fn palette(mut self, palette: [[u8; 3]; 4]) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    /// Runs the rom and returns the screen as rgb.
+    pub fn screen(&self) -> Vec<[u8; 3]> {
+        let bin = match &self.rom {
+            Rom::File(path) => std::fs::read(path).unwrap_or_else(|e| panic!("{}: {e}", path.display())),
+            Rom::Bin(bin) => bin.clone(),
+        };
+
+        let header = gb::cartridge::Header::parse(&bin).unwrap();
+        let mapper = gb::mapper::Mapper::from_bin_permissive(&bin).unwrap();
+        let model = self.model.unwrap_or_else(|| gb::Model::from_header(&header));
+        let fb = Arc::new(Mutex::new([0; 160 * 144]));
+        let keys = Arc::new(AtomicU8::new(0));
+
+        let mut gb = gb::Gameboy::new(model, mapper, None, fb.clone(), Box::new(|_| {}), keys.clone());
+
+        for frame in 0..self.frames {
+            if let Some(&(_, k)) = self.input.iter().rev().find(|&&(f, _)| f <= frame) {
+                keys.store(k, Ordering::Relaxed);
+            }
+
+            for _ in 0..FRAME_CYCLES { gb.step(); }
+        }
+
+        let fb = fb.lock().unwrap();
+        fb.iter().map(|&p| match gb.model() {
+            gb::Model::Dmg | gb::Model::Sgb => self.palette[p as usize & 3],
+            gb::Model::Cgb => [p, p >> 5, p >> 10].map(|c| {
+                let c = c as u8 & 0x1f;
+                c << 3 | c >> 2
+            }),
+        }).collect()
+    }
+
+    /// Runs the rom and panics if the screen does not match `golden`.
+    pub fn check(&self, golden: &str) {
+        let screen = self.screen();
+        let golden = root().join(golden);
+
+        if std::env::var_os("GOLDEN_UPDATE").is_some() {
+            write_png(&golden, &screen);
+            return;
+        }
+
+        let Some(expected) = read_png(&golden) else {
+            panic!("{} is missing, record it with GOLDEN_UPDATE=1", golden.display());
+        };
+
+        let wrong = screen.iter().zip(&expected).filter(|(a, b)| a != b).count();
+        if wrong == 0 { return; }
+
+        let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        let name = golden.file_stem().unwrap().to_string_lossy();
+        std::fs::create_dir_all(&out).unwrap();
+
+        // right pixels are faded out so the wrong ones stand out in red
+        let diff = screen.iter().zip(&expected).map(|(a, b)| {
+            if a == b { b.map(|c| ((c as u16 + 0x1fe) / 3) as u8) } else { [0xff, 0, 0] }
+        }).collect::<Vec<_>>();
+
+        let (actual_path, diff_path) = (out.join(format!("{name}.png")), out.join(format!("{name}-diff.png")));
+        write_png(&actual_path, &screen);
+        write_png(&diff_path, &diff);
+
+        panic!(
+            "{wrong} pixels differ from {}, see {} and {}",
+            golden.display(), actual_path.display(), diff_path.display(),
+        );
+    }
+}
+
+fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..")
+}
+
+/// Reads a 160x144 png as rgb, `None` if it does not exist.
+fn read_png(path: &Path) -> Option<Vec<[u8; 3]>> {
+    let file = File::open(path).ok()?;
+
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().unwrap();
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    assert_eq!((info.width, info.height), (160, 144), "{} has the wrong size", path.display());
+
+    Some(buf[..info.buffer_size()].chunks_exact(info.color_type.samples()).map(|p| match p.len() {
+        // gray, with or without alpha
+        1 | 2 => [p[0]; 3],
+        _ => [p[0], p[1], p[2]],
+    }).collect())
+}
+
+fn write_png(path: &Path, px: &[[u8; 3]]) {
+    let file = BufWriter::new(File::create(path).unwrap());
+
+    let mut encoder = png::Encoder::new(file, 160, 144);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(px.as_flattened()).unwrap();
+}
@@ -0,0 +1,135 @@
+//! Loading roms into a mapper, and how the mappers bank them.
+
+mod common;
+
+use gb::mapper::{Mapper, RomError};
+
+/// A rom of `banks` 16 KiB banks for `cart_type`, with a header that fits.
+fn rom(cart_type: u8, banks: usize) -> Vec<u8> {
+    let mut rom = vec![0; banks << 14];
+    rom[0x147] = cart_type;
+    rom[0x148] = banks.trailing_zeros() as u8 - 1;
+    common::checksum(&mut rom);
+    rom
+}
+
+#[test]
+fn rom_errors() {
+    assert_eq!(Mapper::from_bin(&[0; 0x100]).err(), Some(RomError::TooSmall(0x100)));
+
+    let mut bad = rom(0x00, 2);
+    bad[0x14d] ^= 1;
+    assert_eq!(Mapper::from_bin(&bad).err(), Some(RomError::ChecksumMismatch { expected: 0xe6, computed: 0xe7 }));
+    assert!(Mapper::from_bin_permissive(&bad).is_ok());
+
+    let mut bad = rom(0x00, 2);
+    bad[0x148] = 9;
+    assert_eq!(Mapper::from_bin_permissive(&bad).err(), Some(RomError::IllegalRomSize(9)));
+
+    let mut bad = rom(0x00, 2);
+    bad[0x149] = 1;
+    assert_eq!(Mapper::from_bin_permissive(&bad).err(), Some(RomError::IllegalRamSize(1)));
+
+    let mut bad = rom(0x00, 2);
+    bad[0x147] = 0xaa;
+    assert_eq!(Mapper::from_bin_permissive(&bad).err(), Some(RomError::UnknownMapper(0xaa)));
+}
+
+#[test]
+fn truncated() {
+    assert!(Mapper::from_bin(&rom(0x01, 8)).is_ok());
+
+    let mut short = rom(0x01, 8);
+    short.truncate(0x18000);
+    assert_eq!(Mapper::from_bin(&short).err(), Some(RomError::SizeMismatch { expected: 0x20000, actual: 0x18000 }));
+
+    let long = [rom(0x00, 2), vec![0; 0x4000]].concat();
+    assert_eq!(Mapper::from_bin(&long).err(), Some(RomError::SizeMismatch { expected: 0x8000, actual: 0xc000 }));
+}
+
+#[test]
+fn mbc2_ram() {
+    let mut rom = common::rom(&[
+        0x3e, 0x0a,       // 0150 ld a, $0a
+        0xea, 0x00, 0x01, // 0152 ld [$0100], a ; a8 set, so this picks a rom bank
+        0x3e, 0x05,       // 0155 ld a, $05
+        0xea, 0x00, 0xa0, // 0157 ld [$a000], a ; ram is still off
+        0x3e, 0x0a,       // 015a ld a, $0a
+        0xea, 0x00, 0x00, // 015c ld [$0000], a
+        0x3e, 0x5c,       // 015f ld a, $5c
+        0xea, 0x01, 0xa2, // 0161 ld [$a201], a
+        0xfa, 0x01, 0xa0, // 0164 ld a, [$a001]
+        0xcb, 0x37,       // 0167 swap a
+        0xea, 0x02, 0xa0, // 0169 ld [$a002], a
+        0xfa, 0x01, 0xbe, // 016c ld a, [$be01]
+        0xea, 0x03, 0xa0, // 016f ld [$a003], a
+        0xc3, 0x72, 0x01, // 0172 jp $0172
+    ]);
+    rom[0x147] = 0x06;
+    common::checksum(&mut rom);
+
+    let mut gb = common::gameboy(&rom);
+    gb.set_sram(&[0xf0; 512]);
+    assert_eq!(gb.get_sram().unwrap(), [0; 512]);
+    common::run(&mut gb, 1000);
+
+    // the upper nibble reads as 1s and the 512 nibbles repeat all over 0xa000..0xc000
+    let sram = gb.get_sram().unwrap();
+    assert_eq!(sram[..4], [0x0, 0xc, 0xf, 0xc]);
+    assert!(sram[4..].iter().all(|b| *b == 0));
+}
+
+/// Runs a program that reads through the mbc1 bank registers on a 1 MiB rom, and returns what it
+/// read from 0x4000 with bank 0x20, from 0x0000 in mode 1 and from 0x4000 with bank 0x12.
+fn mbc1_banks(multicart: bool) -> [u8; 3] {
+    let mut rom = vec![0; 0x100000];
+    rom[0x100..0x200].copy_from_slice(&common::rom(&[
+        0x3e, 0x0a,       // 0150 ld a, $0a
+        0xea, 0x00, 0x00, // 0152 ld [$0000], a
+        0x3e, 0x20,       // 0155 ld a, $20
+        0xea, 0x00, 0x20, // 0157 ld [$2000], a
+        0x3e, 0x01,       // 015a ld a, $01
+        0xea, 0x00, 0x40, // 015c ld [$4000], a
+        0xfa, 0x00, 0x60, // 015f ld a, [$6000]
+        0xea, 0x00, 0xa0, // 0162 ld [$a000], a
+        0x3e, 0x01,       // 0165 ld a, $01
+        0xea, 0x00, 0x60, // 0167 ld [$6000], a
+        0xfa, 0x00, 0x20, // 016a ld a, [$2000]
+        0xea, 0x01, 0xa0, // 016d ld [$a001], a
+        0x3e, 0x12,       // 0170 ld a, $12
+        0xea, 0x00, 0x20, // 0172 ld [$2000], a
+        0xfa, 0x00, 0x60, // 0175 ld a, [$6000]
+        0xea, 0x02, 0xa0, // 0178 ld [$a002], a
+        0xc3, 0x7b, 0x01, // 017b jp $017b
+    ])[0x100..0x200]);
+    rom[0x147] = 0x03;
+    rom[0x148] = 0x05;
+    rom[0x149] = 0x02;
+    common::checksum(&mut rom);
+
+    // mode 1 maps bank 0x10 or 0x20 over the program, so it has to be there too
+    for bank in 0..64 {
+        rom[(bank << 14) | 0x2000] = bank as u8;
+    }
+    rom.copy_within(0x100..0x200, 0x40100);
+    rom.copy_within(0x100..0x200, 0x80100);
+    if multicart {
+        rom[0x40104..0x40134].copy_from_slice(&gb::cartridge::NINTENDO_LOGO);
+    }
+
+    let mut gb = common::gameboy(&rom);
+    common::run(&mut gb, 2000);
+    gb.get_sram().unwrap()[..3].try_into().unwrap()
+}
+
+#[test]
+fn mbc1_large_rom() {
+    // the upper bits go on top of the 5 bit bank, and on top of bank 0 in mode 1
+    assert_eq!(mbc1_banks(false), [0x21, 0x20, 0x32]);
+}
+
+#[test]
+fn mbc1_multicart() {
+    // same with the upper bits one lower, bit 4 of the 5 bit bank goes nowhere
+    assert_eq!(mbc1_banks(true), [0x11, 0x10, 0x12]);
+}
@@ -0,0 +1,49 @@
+//! Roms from the mooneye test suite, which `get_tests.sh` fetches into the workspace root. They
+//! are ignored by default, `cargo test -p gb --test mooneye -- --ignored` runs them.
+
+use std::{cell::RefCell, path::Path, rc::Rc, sync::{atomic::AtomicU8, Arc, Mutex}};
+
+/// Mooneye roms send the fibonacci numbers down the link cable when they pass, and 0x42s when
+/// they fail.
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl gb::serial::LinkCable for Capture {
+    fn transfer(&mut self, data: u8) -> u8 {
+        self.0.borrow_mut().push(data);
+        0xff
+    }
+
+    fn poll(&mut self, _data: Option<u8>) -> Option<u8> { None }
+}
+
+fn run(rom: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../mooneye-tests").join(rom);
+    let bin = std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {e}, run get_tests.sh", path.display()));
+
+    let header = gb::cartridge::Header::parse(&bin).unwrap();
+    let mapper = gb::mapper::Mapper::from_bin(&bin).unwrap();
+    let fb = Arc::new(Mutex::new([0; 160 * 144]));
+    let out = Rc::new(RefCell::new(Vec::new()));
+
+    let mut gb = gb::Gameboy::new(gb::Model::from_header(&header), mapper, None, fb, Box::new(|_| {}), Arc::new(AtomicU8::new(0)));
+    gb.set_link_cable(Some(Box::new(Capture(out.clone()))));
+
+    for _ in 0..gb::CLOCK_HZ * 10 {
+        gb.step();
+        if out.borrow().len() >= 6 { break; }
+    }
+
+    assert_eq!(out.borrow()[..], [3, 5, 8, 13, 21, 34], "{rom} failed");
+}
+
+#[test]
+#[ignore = "needs get_tests.sh"]
+fn halt_ime0_nointr_timing() { run("acceptance/halt_ime0_nointr_timing.gb"); }
+
+#[test]
+#[ignore = "needs get_tests.sh"]
+fn ie_push() { run("acceptance/interrupts/ie_push.gb"); }
+
+#[test]
+#[ignore = "needs get_tests.sh"]
+fn di_timing() { run("acceptance/di_timing-GS.gb"); }
@@ -0,0 +1,46 @@
+//! Peeking is for debuggers, so it must not do anything a game could notice.
+
+mod common;
+
+use std::sync::{atomic::*, Arc};
+
+struct Counting(Arc<AtomicUsize>);
+
+impl gb::mapper::Infrared for Counting {
+    fn set_led(&mut self, _on: bool) {}
+
+    fn receiving(&mut self) -> bool {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Maps the infrared port in with 0x0e, which both carts use for it.
+const PROGRAM: [u8; 8] = [
+    0x3e, 0x0e,       // 0150 ld a, $0e
+    0xea, 0x00, 0x00, // 0152 ld [$0000], a
+    0xc3, 0x55, 0x01, // 0155 jp $0155
+];
+
+fn peek_infrared(cart_type: u8) {
+    let mut rom = common::rom(&PROGRAM);
+    rom[0x147] = cart_type;
+    rom[0x149] = 0x02;
+    common::checksum(&mut rom);
+
+    let reads = Arc::new(AtomicUsize::new(0));
+    let mut mapper = gb::mapper::Mapper::from_bin(&rom).unwrap();
+    mapper.set_infrared(Box::new(Counting(reads.clone())));
+
+    let mut gb = common::machine(gb::Model::Dmg, mapper);
+    common::run(&mut gb, 1000);
+
+    assert_eq!(gb.peek(0xa000), 0xc0);
+    assert_eq!(reads.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn huc1_infrared() { peek_infrared(0xff); }
+
+#[test]
+fn huc3_infrared() { peek_infrared(0xfe); }
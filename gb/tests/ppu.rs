@@ -0,0 +1,259 @@
+//! The pixel pipeline, on a ppu stepped by hand one dot at a time, and golden screenshots of
+//! roms that lock down what it draws. New goldens are recorded with
+//! `GOLDEN_UPDATE=1 cargo test -p gb --test ppu`, after checking the screen by eye.
+
+mod golden;
+
+use std::sync::{Arc, Mutex};
+
+use gb::ppu::Ppu;
+use golden::Golden;
+use sm83::cpu::InterruptManager;
+
+/// A dmg ppu set up with the lcd off and then turned on with `lcdc`, at the start of line 1 as
+/// the first line after turning it on has no oam scan.
+fn ppu_with(lcdc: u8, setup: impl FnOnce(&mut Ppu)) -> Ppu {
+    let mut ppu = Ppu::new(Arc::new(Mutex::new([0; 160 * 144])), false);
+    setup(&mut ppu);
+    ppu.store(0xff40, lcdc);
+    while ppu.load(0xff44) != 1 { step(&mut ppu, 1); }
+    ppu
+}
+
+/// A ppu with the lcd, background and objects on.
+fn ppu(setup: impl FnOnce(&mut Ppu)) -> Ppu { ppu_with(0x93, setup) }
+
+fn step(ppu: &mut Ppu, dots: usize) {
+    let mut ints = InterruptManager { pending: 0, enabled: 0 };
+    for _ in 0..dots { ppu.step(&mut ints); }
+}
+
+fn mode(ppu: &Ppu) -> u8 { ppu.load(0xff41) & 3 }
+
+/// Runs up to the next mode 3 and returns how many dots it lasts.
+fn mode_3(ppu: &mut Ppu) -> usize {
+    while mode(ppu) != 3 { step(ppu, 1); }
+
+    // the dot that started it was already part of it
+    let mut dots = 1;
+    while mode(ppu) == 3 {
+        step(ppu, 1);
+        dots += 1;
+    }
+    dots
+}
+
+/// Puts object `i` on line 1 at `x`.
+fn obj(ppu: &mut Ppu, i: u16, x: u8) {
+    ppu.store(0xfe00 + i * 4, 17);
+    ppu.store(0xfe01 + i * 4, x);
+}
+
+#[test]
+fn mode_3_length() {
+    assert_eq!(mode_3(&mut ppu(|_| {})), 172);
+
+    // fine scroll throws away pixels from the first tile
+    assert_eq!(mode_3(&mut ppu(|p| p.store(0xff43, 3))), 175);
+    assert_eq!(mode_3(&mut ppu(|p| p.store(0xff43, 0x27))), 179);
+}
+
+#[test]
+fn mode_3_objects() {
+    // 6 dots for the fetch, and up to 5 more waiting for the background tile under it
+    assert_eq!(mode_3(&mut ppu(|p| obj(p, 0, 8))), 172 + 11);
+    assert_eq!(mode_3(&mut ppu(|p| obj(p, 0, 0))), 172 + 11);
+    assert_eq!(mode_3(&mut ppu(|p| obj(p, 0, 13))), 172 + 6);
+    assert_eq!(mode_3(&mut ppu(|p| obj(p, 0, 10))), 172 + 9);
+
+    // only the first object over a tile waits for it
+    assert_eq!(mode_3(&mut ppu(|p| { obj(p, 0, 8); obj(p, 1, 10); })), 172 + 11 + 6);
+
+    // the wait goes by the scrolled tile
+    assert_eq!(mode_3(&mut ppu(|p| { p.store(0xff43, 3); obj(p, 0, 13); })), 172 + 3 + 11);
+
+    // and with objects off there is none
+    assert_eq!(mode_3(&mut ppu_with(0x91, |p| obj(p, 0, 8))), 172);
+}
+
+#[test]
+fn locked_memory() {
+    let mut ppu = ppu(|p| {
+        p.store(0x8000, 0x12);
+        p.store(0xfe00, 0x34);
+    });
+    while mode(&ppu) != 2 { step(&mut ppu, 1); }
+
+    // the oam scan has oam to itself and drawing has both, writes there go nowhere
+    for _ in 0..456 {
+        let m = mode(&ppu);
+        let expected = match m {
+            2 => (0x12, 0xff),
+            3 => (0xff, 0xff),
+            _ => (0x12, 0x34),
+        };
+        assert_eq!((ppu.load(0x8000), ppu.load(0xfe00)), expected, "mode {m}");
+
+        match m {
+            2 => ppu.store(0xfe01, 0x56),
+            3 => ppu.store(0x8001, 0x78),
+            _ => assert_eq!((ppu.load(0x8001), ppu.load(0xfe01)), (0, 0)),
+        }
+
+        step(&mut ppu, 1);
+    }
+}
+
+#[test]
+fn lcd_off_and_on() {
+    let mut ppu = ppu(|_| {});
+    step(&mut ppu, 456 * 50);
+
+    ppu.store(0xff40, 0x13);
+    for _ in 0..1000 {
+        assert_eq!((ppu.load(0xff44), mode(&ppu)), (0, 0));
+        step(&mut ppu, 1);
+    }
+
+    // the first line starts 4 dots in and waits in mode 0 where the oam scan would be
+    ppu.store(0xff40, 0x93);
+    step(&mut ppu, 76);
+    assert_eq!((ppu.load(0xff44), mode(&ppu)), (0, 0));
+    step(&mut ppu, 1);
+    assert_eq!(mode(&ppu), 3);
+
+    step(&mut ppu, 456 - 4 - 77 + 1);
+    assert_eq!((ppu.load(0xff44), mode(&ppu)), (1, 2));
+}
+
+#[test]
+fn line_153() {
+    let mut ppu = ppu(|p| p.store(0xff45, 153));
+    step(&mut ppu, 152 * 456);
+
+    // LY goes back to 0 4 dots into the line, and LYC compares against that
+    for _ in 0..4 {
+        assert_eq!((ppu.load(0xff44), ppu.load(0xff41) & 4), (153, 4));
+        step(&mut ppu, 1);
+    }
+    assert_eq!((ppu.load(0xff44), ppu.load(0xff41) & 4), (0, 0));
+
+    ppu.store(0xff45, 0);
+    assert_eq!(ppu.load(0xff41) & 4, 4);
+}
+
+#[test]
+#[ignore = "needs get_tests.sh"]
+fn dmg_acid2() {
+    // the reference screenshot comes from upstream, see get_tests.sh
+    Golden::new("dmg-acid2.gb").model(gb::Model::Dmg).check("dmg-acid2.png");
+}
+
+/// Turns the lcd off in vblank, copies the tile data, both tile maps and oam out of the rom,
+/// then writes the registers from a list of port and value pairs, lcdc last.
+const PROGRAM: [u8; 73] = [
+    0xf3,             // 0150 di
+    0x31, 0xfe, 0xff, // 0151 ld sp, $fffe
+    0xf0, 0x44,       // 0154 ldh a, [$44]
+    0xfe, 0x90,       // 0156 cp 144
+    0x38, 0xfa,       // 0158 jr c, $0154
+    0xaf,             // 015a xor a
+    0xe0, 0x40,       // 015b ldh [$40], a
+    0x21, 0x00, 0x10, // 015d ld hl, $1000
+    0x11, 0x00, 0x80, // 0160 ld de, $8000
+    0x01, 0x00, 0x08, // 0163 ld bc, $0800
+    0xcd, 0x90, 0x01, // 0166 call $0190
+    0x21, 0x00, 0x20, // 0169 ld hl, $2000
+    0x11, 0x00, 0x98, // 016c ld de, $9800
+    0x01, 0x00, 0x08, // 016f ld bc, $0800
+    0xcd, 0x90, 0x01, // 0172 call $0190
+    0x21, 0x00, 0x30, // 0175 ld hl, $3000
+    0x11, 0x00, 0xfe, // 0178 ld de, $fe00
+    0x01, 0xa0, 0x00, // 017b ld bc, $00a0
+    0xcd, 0x90, 0x01, // 017e call $0190
+    0x21, 0x00, 0x31, // 0181 ld hl, $3100
+    0x2a,             // 0184 ld a, [hl+]
+    0x4f,             // 0185 ld c, a
+    0x2a,             // 0186 ld a, [hl+]
+    0xe2,             // 0187 ldh [c], a
+    0x79,             // 0188 ld a, c
+    0xfe, 0x40,       // 0189 cp $40
+    0x20, 0xf7,       // 018b jr nz, $0184
+    0x76,             // 018d halt
+    0x18, 0xfd,       // 018e jr $018d
+    0x2a,             // 0190 ld a, [hl+]
+    0x12,             // 0191 ld [de], a
+    0x13,             // 0192 inc de
+    0x0b,             // 0193 dec bc
+    0x78,             // 0194 ld a, b
+    0xb1,             // 0195 or c
+    0x20, 0xf8,       // 0196 jr nz, $0190
+    0xc9,             // 0198 ret
+];
+
+/// The shade of each pixel of the tiles the scene uses: blank, the three solid shades, a
+/// checkerboard, a gradient, a box and an F that shows how it is flipped.
+fn pixel(tile: usize, x: usize, y: usize) -> u8 {
+    match tile {
+        1..=3 => tile as u8,
+        4 => [3, 0][(x + y) % 2],
+        5 => ((x + y) / 4) as u8,
+        6 => if x == 0 || y == 0 || x == 7 || y == 7 { 3 } else { 1 },
+        7 => match (x, y) {
+            (1, 1..=6) | (1..=6, 1) => 3,
+            (2..=4, 3) => 2,
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+/// Background, window and objects in one scene: fine scrolling, flips, both object palettes,
+/// objects behind the background, overlaps, the screen edges and the 10 objects a line.
+fn scene() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+    rom[0x150..0x150 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+
+    for tile in 0..8 {
+        for y in 0..8 {
+            let row = (0..8).fold([0, 0], |[lo, hi], x| {
+                let c = pixel(tile, x, y);
+                [lo | (c & 1) << (7 - x), hi | (c >> 1) << (7 - x)]
+            });
+            rom[0x1000 + tile * 16 + y * 2..][..2].copy_from_slice(&row);
+        }
+    }
+
+    for i in 0..0x400 {
+        let (x, y) = (i % 32, i / 32);
+        // blank at the top where most objects go, so they are easy to tell apart
+        rom[0x2000 + i] = if y < 6 { if x % 8 == 7 { 5 } else { 0 } } else { [4, 5, 6, 1, 0, 2, 7, 3][(x + 3 * y) % 8] };
+        rom[0x2400 + i] = if y == 1 { 7 } else { 6 };
+    }
+
+    let objects: &[[u8; 4]] = &[
+        // as is, flipped each way, and on the other palette
+        [32, 24, 7, 0x00], [32, 40, 7, 0x20], [32, 56, 7, 0x40], [32, 72, 7, 0x70],
+        // behind the background, and overlapping where the one further left wins
+        [56, 24, 2, 0x80], [56, 48, 6, 0x00], [60, 44, 7, 0x00],
+        // half off the left and top, and over the window
+        [116, 4, 7, 0x00], [12, 108, 7, 0x00], [136, 128, 4, 0x10],
+    ];
+    for (i, o) in objects.iter().enumerate() { rom[0x3000 + i * 4..][..4].copy_from_slice(o); }
+    // eleven on one line, the last one is never drawn
+    for i in 0..11 { rom[0x3000 + (objects.len() + i) * 4..][..4].copy_from_slice(&[80, 16 + 12 * i as u8, 3, 0]); }
+
+    // scy, scx, wy, wx, bgp, obp0, obp1, then lcdc with the window map at 0x9c00
+    rom[0x3100..0x3110].copy_from_slice(&[
+        0x42, 5, 0x43, 3, 0x4a, 96, 0x4b, 7 + 88,
+        0x47, 0xe4, 0x48, 0xe4, 0x49, 0x1b, 0x40, 0xf3,
+    ]);
+
+    rom
+}
+
+#[test]
+fn scene_dmg() {
+    Golden::bin(scene()).model(gb::Model::Dmg).check("gb/tests/golden/scene-dmg.png");
+}
@@ -0,0 +1,77 @@
+//! The clock trailer of `.sav` files, against an injected wall clock.
+
+mod common;
+
+use gb::{mapper::Mapper, rtc::Clock};
+
+const NOW: u64 = 1_700_000_000;
+
+fn mapper(cart_type: u8) -> Mapper {
+    let mut m = common::mapper(cart_type);
+    m.set_rtc_clock(Clock::Wall(Box::new(|| NOW)));
+    m
+}
+
+/// Loads `trailer` after the mapper's sram, like a frontend does with a `.sav`.
+fn load(m: &mut Mapper, trailer: &[u8]) {
+    let mut sav = m.get_sram().unwrap().to_vec();
+    sav.extend(trailer);
+    m.set_sram(&sav);
+}
+
+#[test]
+fn mbc3() {
+    let mut m = mapper(0x10);
+    let rtc = m.get_rtc().unwrap();
+    assert_eq!(rtc[40..48], NOW.to_le_bytes());
+
+    // 00:00:50 saved 100 seconds ago
+    let mut trailer = [0; 48];
+    trailer[0] = 50;
+    trailer[40..].copy_from_slice(&(NOW - 100).to_le_bytes());
+    load(&mut m, &trailer);
+
+    let rtc = m.get_rtc().unwrap();
+    assert_eq!((rtc[0], rtc[4], rtc[8]), (30, 2, 0));
+}
+
+#[test]
+fn huc3() {
+    let mut m = mapper(0xfe);
+    let rtc = m.get_rtc().unwrap();
+    assert_eq!(rtc[4..12], NOW.to_le_bytes());
+
+    // 10 minutes into day 3, saved two minutes ago
+    let mut trailer = [0; 12];
+    trailer[0..2].copy_from_slice(&10u16.to_le_bytes());
+    trailer[2..4].copy_from_slice(&3u16.to_le_bytes());
+    trailer[4..].copy_from_slice(&(NOW - 120).to_le_bytes());
+    load(&mut m, &trailer);
+
+    let rtc = m.get_rtc().unwrap();
+    assert_eq!(rtc[0..4], [12, 0, 3, 0]);
+}
+
+#[test]
+fn tama5() {
+    let mut m = mapper(0xfd);
+    let rtc = m.get_rtc().unwrap();
+    assert_eq!(rtc[0x10..0x18], NOW.to_le_bytes());
+
+    // 23:59:30 on sunday 2023-12-31 as bcd digits, saved a minute ago
+    let mut trailer = [0; 0x18];
+    trailer[..13].copy_from_slice(&[0, 3, 9, 5, 3, 2, 0, 1, 3, 2, 1, 3, 2]);
+    trailer[0x10..].copy_from_slice(&(NOW - 60).to_le_bytes());
+    load(&mut m, &trailer);
+
+    let rtc = m.get_rtc().unwrap();
+    assert_eq!(rtc[..13], [0, 3, 0, 0, 0, 0, 1, 1, 0, 1, 0, 4, 2]);
+
+    // noon on wednesday 2024-02-28, saved two days ago
+    trailer[..13].copy_from_slice(&[0, 0, 0, 0, 2, 1, 3, 8, 2, 2, 0, 4, 2]);
+    trailer[0x10..].copy_from_slice(&(NOW - 2 * 86400).to_le_bytes());
+    load(&mut m, &trailer);
+
+    let rtc = m.get_rtc().unwrap();
+    assert_eq!(rtc[..13], [0, 0, 0, 0, 2, 1, 5, 1, 0, 3, 0, 4, 2]);
+}
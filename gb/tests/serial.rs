@@ -0,0 +1,135 @@
+//! Transfers over the link port, against a cable that answers by itself, between two machines and over TCP.
+
+mod common;
+
+use std::{cell::RefCell, net::{Ipv4Addr, TcpListener}, rc::Rc, thread, time::Duration};
+
+use gb::{link::{LinkedPair, TcpCable}, serial::LinkCable, Model};
+
+/// Sends back the inverse of every byte clocked by the game, and 0x55 to a game that waits for
+/// the other side. Keeps everything the game sent.
+struct Echo(Rc<RefCell<Vec<u8>>>);
+
+impl LinkCable for Echo {
+    fn transfer(&mut self, data: u8) -> u8 {
+        self.0.borrow_mut().push(data);
+        !data
+    }
+
+    fn poll(&mut self, data: Option<u8>) -> Option<u8> {
+        let data = data?;
+        self.0.borrow_mut().push(data);
+        Some(0x55)
+    }
+}
+
+/// Sends 0x42 with `sc`, then sends whatever came back on the internal clock at the same speed.
+fn transfer(model: Model, sc: u8, steps: usize) -> Vec<u8> {
+    let rom = common::rom(&[
+        0x3e, 0x42,       // 0150 ld a, $42
+        0xe0, 0x01,       // 0152 ldh [$01], a
+        0x3e, sc,         // 0154 ld a, sc
+        0xe0, 0x02,       // 0156 ldh [$02], a
+        0xf0, 0x02,       // 0158 ldh a, [$02]
+        0x07,             // 015a rlca
+        0xda, 0x58, 0x01, // 015b jp c, $0158
+        0x3e, sc | 1,     // 015e ld a, sc | 1
+        0xe0, 0x02,       // 0160 ldh [$02], a
+        0xc3, 0x62, 0x01, // 0162 jp $0162
+    ]);
+
+    let sent = Rc::new(RefCell::new(Vec::new()));
+    let mut gb = common::gameboy_on(model, &rom);
+    gb.set_link_cable(Some(Box::new(Echo(sent.clone()))));
+    common::run(&mut gb, steps);
+
+    sent.take()
+}
+
+#[test]
+fn internal_clock() {
+    assert_eq!(transfer(Model::Dmg, 0x81, 10000), [0x42, 0xbd]);
+}
+
+#[test]
+fn external_clock() {
+    assert_eq!(transfer(Model::Dmg, 0x80, 10000), [0x42, 0x55]);
+}
+
+#[test]
+fn fast_clock() {
+    // 8 bits take 128 cycles instead of 4096
+    assert_eq!(transfer(Model::Cgb, 0x83, 1000), [0x42, 0xbd]);
+    assert_eq!(transfer(Model::Cgb, 0x81, 1000), []);
+
+    // a dmg has no fast clock
+    assert_eq!(transfer(Model::Dmg, 0x83, 1000), []);
+    assert_eq!(transfer(Model::Dmg, 0x83, 10000), [0x42, 0xbd]);
+}
+
+/// Sends `data` with `sc` and keeps what came back at 0xa000.
+fn linked(data: u8, sc: u8) -> gb::Gameboy<'static> {
+    let mut rom = common::rom(&[
+        0x3e, 0x0a,       // 0150 ld a, $0a
+        0xea, 0x00, 0x00, // 0152 ld [$0000], a
+        0x3e, data,       // 0155 ld a, data
+        0xe0, 0x01,       // 0157 ldh [$01], a
+        0x3e, sc,         // 0159 ld a, sc
+        0xe0, 0x02,       // 015b ldh [$02], a
+        0xf0, 0x02,       // 015d ldh a, [$02]
+        0x07,             // 015f rlca
+        0xda, 0x5d, 0x01, // 0160 jp c, $015d
+        0xf0, 0x01,       // 0163 ldh a, [$01]
+        0xea, 0x00, 0xa0, // 0165 ld [$a000], a
+        0xc3, 0x68, 0x01, // 0168 jp $0168
+    ]);
+    rom[0x147] = 0x03;
+    rom[0x149] = 0x02;
+    common::checksum(&mut rom);
+
+    common::gameboy(&rom)
+}
+
+#[test]
+fn linked_pair() {
+    let mut pair = LinkedPair::new(linked(0x12, 0x81), linked(0x34, 0x80));
+    for _ in 0..10000 { pair.step(); }
+
+    let (a, b) = pair.split();
+    assert_eq!(a.get_sram().unwrap()[0], 0x34);
+    assert_eq!(b.get_sram().unwrap()[0], 0x12);
+}
+
+#[test]
+fn linked_pair_not_listening() {
+    // the other side never arms a transfer, so its input stays pulled up
+    let mut pair = LinkedPair::new(linked(0x12, 0x81), linked(0x34, 0x00));
+    for _ in 0..10000 { pair.step(); }
+
+    let (a, b) = pair.split();
+    assert_eq!(a.get_sram().unwrap()[0], 0xff);
+    assert_eq!(b.get_sram().unwrap()[0], 0x34);
+}
+
+#[test]
+fn tcp_cable() {
+    // find a free port, the cable only takes a port number
+    let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap().port();
+    let master = thread::spawn(move || TcpCable::listen(port).unwrap().transfer(0x12));
+
+    let mut cable = (0..100)
+        .find_map(|_| TcpCable::connect((Ipv4Addr::LOCALHOST, port)).ok().or_else(|| {
+            thread::sleep(Duration::from_millis(10));
+            None
+        }))
+        .unwrap();
+
+    // the master clocks the transfer, this side answers with the byte it armed
+    let got = loop {
+        if let Some(d) = cable.poll(Some(0x34)) { break d; }
+        thread::sleep(Duration::from_millis(1));
+    };
+
+    assert_eq!(got, 0x12);
+    assert_eq!(master.join().unwrap(), 0x34);
+}
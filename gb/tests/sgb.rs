@@ -0,0 +1,94 @@
+//! Super Game Boy packets, sent a bit at a time through the joypad register.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use gb::Model;
+
+/// Sends the packet at 0x0200 through P1, then carries on with `tail` at 0x0185.
+const SEND: [u8; 0x35] = [
+    0x21, 0x00, 0x02, // 0150 ld hl, $0200
+    0xaf,             // 0153 xor a
+    0xe0, 0x00,       // 0154 ldh [$00], a ; reset pulse
+    0x3e, 0x30,       // 0156 ld a, $30
+    0xe0, 0x00,       // 0158 ldh [$00], a
+    0x1e, 0x00,       // 015a ld e, 0
+    0x2a,             // 015c ld a, [hl+]
+    0x57,             // 015d ld d, a
+    0x7a,             // 015e ld a, d
+    0xe6, 0x01,       // 015f and 1
+    0x3e, 0x20,       // 0161 ld a, $20 ; a 0 pulses p14
+    0xca, 0x68, 0x01, // 0163 jp z, $0168
+    0x3e, 0x10,       // 0166 ld a, $10 ; a 1 pulses p15
+    0xe0, 0x00,       // 0168 ldh [$00], a
+    0x3e, 0x30,       // 016a ld a, $30
+    0xe0, 0x00,       // 016c ldh [$00], a
+    0xcb, 0x3a,       // 016e srl d
+    0x1c,             // 0170 inc e
+    0x7b,             // 0171 ld a, e
+    0xe6, 0x07,       // 0172 and 7
+    0xc2, 0x5e, 0x01, // 0174 jp nz, $015e
+    0x7d,             // 0177 ld a, l
+    0xfe, 0x10,       // 0178 cp $10
+    0xc2, 0x5c, 0x01, // 017a jp nz, $015c
+    0x3e, 0x20,       // 017d ld a, $20 ; stop bit
+    0xe0, 0x00,       // 017f ldh [$00], a
+    0x3e, 0x30,       // 0181 ld a, $30
+    0xe0, 0x00,       // 0183 ldh [$00], a
+];
+
+fn gameboy(model: Model, packet: [u8; 16], tail: &[u8]) -> gb::Gameboy<'static> {
+    let mut rom = common::rom(&[&SEND[..], tail].concat());
+    rom[0x147] = 0x03;
+    rom[0x149] = 0x02;
+    common::checksum(&mut rom);
+    rom[0x200..0x210].copy_from_slice(&packet);
+
+    common::gameboy_on(model, &rom)
+}
+
+/// Asks for two players with MLT_REQ, then reads P1 before and after moving to the next one.
+fn players(model: Model) -> [u8; 2] {
+    let mut packet = [0; 16];
+    packet[..2].copy_from_slice(&[0x89, 0x01]); // MLT_REQ, one packet
+
+    let mut gb = gameboy(model, packet, &[
+        0x3e, 0x0a,       // 0185 ld a, $0a
+        0xea, 0x00, 0x00, // 0187 ld [$0000], a
+        0xf0, 0x00,       // 018a ldh a, [$00]
+        0xea, 0x00, 0xa0, // 018c ld [$a000], a
+        0x3e, 0x10,       // 018f ld a, $10
+        0xe0, 0x00,       // 0191 ldh [$00], a
+        0x3e, 0x30,       // 0193 ld a, $30
+        0xe0, 0x00,       // 0195 ldh [$00], a
+        0xf0, 0x00,       // 0197 ldh a, [$00]
+        0xea, 0x01, 0xa0, // 0199 ld [$a001], a
+        0xc3, 0x9c, 0x01, // 019c jp $019c
+    ]);
+    common::run(&mut gb, 20000);
+
+    gb.get_sram().unwrap()[..2].try_into().unwrap()
+}
+
+#[test]
+fn mlt_req() {
+    // with nothing selected P1 gives the player, p15 going back up moves on to the next one
+    assert_eq!(players(Model::Sgb), [0xff, 0xfe]);
+    assert_eq!(players(Model::Dmg), [0x3f, 0x3f]);
+}
+
+#[test]
+fn pal01() {
+    let mut packet = [0; 16];
+    packet[0] = 0x01; // PAL01, one packet
+    packet[1..3].copy_from_slice(&0x1234_u16.to_le_bytes());
+
+    let mut gb = gameboy(Model::Sgb, packet, &[0xc3, 0x85, 0x01]);
+    let fb = Arc::new(Mutex::new([0xffff; 256 * 224]));
+    gb.set_sgb_framebuffer(Some(fb.clone()));
+    common::run(&mut gb, 3 * 70224);
+
+    // a blank screen is all color 0, and so is a border that hasn't been sent
+    assert!(fb.lock().unwrap().iter().all(|c| *c == 0x1234));
+}
@@ -0,0 +1,28 @@
+//! Loading `.sav` files that don't match the size of the cartridge's battery backed memory.
+
+mod common;
+
+use common::mapper;
+
+/// Every cart type with a battery.
+const CARTS: [u8; 11] = [0x03, 0x06, 0x0d, 0x10, 0x1b, 0x20, 0x22, 0xfc, 0xfd, 0xfe, 0xff];
+
+#[test]
+fn short() {
+    for cart in CARTS {
+        let mut m = mapper(cart);
+        m.set_sram(&[0x0a; 4]);
+        assert_eq!(m.get_sram().unwrap()[..4], [0x0a; 4], "cart {cart:02x}");
+    }
+}
+
+#[test]
+fn long() {
+    for cart in CARTS {
+        let mut m = mapper(cart);
+        let len = m.get_sram().unwrap().len();
+
+        m.set_sram(&vec![0x0a; len + 0x10000]);
+        assert!(m.get_sram().unwrap().iter().all(|&b| b == 0x0a), "cart {cart:02x}");
+    }
+}
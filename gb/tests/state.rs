@@ -0,0 +1,90 @@
+//! Save states, restored into a machine that ran on and against ones that don't fit.
+
+mod common;
+
+use gb::{state::StateError, Model};
+
+use common::{run, COUNTER};
+
+/// Offset of the cpu cycle counter, after the header, the rom id, the model, the registers and
+/// ime.
+const CYCLES: usize = 10 + 0x1c + 1 + 9 + 4 + 1;
+
+fn gameboy(title: &[u8]) -> gb::Gameboy<'static> {
+    let mut rom = common::rom(&COUNTER);
+    rom[0x134..0x134 + title.len()].copy_from_slice(title);
+    common::checksum(&mut rom);
+    common::gameboy(&rom)
+}
+
+/// Compares two states, pointing at the first byte they differ in rather than printing both.
+fn assert_same(a: &[u8], b: &[u8]) {
+    assert_eq!(a.len(), b.len());
+    if let Some(at) = a.iter().zip(b).position(|(x, y)| x != y) {
+        panic!("states differ at {at:#x}: {:02x?} and {:02x?}", &a[at..(at + 8).min(a.len())], &b[at..(at + 8).min(b.len())]);
+    }
+}
+
+#[test]
+fn roundtrip() {
+    for model in [Model::Dmg, Model::Cgb] {
+        let mut gb = common::gameboy_on(model, &common::rom(&COUNTER));
+        run(&mut gb, 1000);
+
+        let saved = gb.save_state();
+        run(&mut gb, 3000);
+        let ahead = gb.save_state();
+
+        gb.load_state(&saved).unwrap();
+        assert_same(&gb.save_state(), &saved);
+
+        run(&mut gb, 3000);
+        assert_same(&gb.save_state(), &ahead);
+    }
+}
+
+#[test]
+fn other_rom() {
+    let saved = gameboy(b"COUNTER").save_state();
+    assert_eq!(gameboy(b"OTHER").load_state(&saved), Err(StateError::RomMismatch));
+}
+
+#[test]
+fn other_model() {
+    let rom = common::rom(&COUNTER);
+    let saved = common::gameboy_on(Model::Cgb, &rom).save_state();
+    assert_eq!(common::gameboy_on(Model::Dmg, &rom).load_state(&saved), Err(StateError::ModelMismatch));
+}
+
+#[test]
+fn bad_header() {
+    let mut gb = gameboy(b"COUNTER");
+    let saved = gb.save_state();
+
+    let mut other = saved.clone();
+    other[4] = 2;
+    assert_eq!(gb.load_state(&other), Err(StateError::UnsupportedVersion(2)));
+
+    assert_eq!(gb.load_state(&saved[..saved.len() - 1]), Err(StateError::Truncated));
+    assert_eq!(gb.load_state(b"GWSX"), Err(StateError::Truncated));
+    assert_eq!(gb.load_state(&[b"XXXX", &saved[4..]].concat()), Err(StateError::BadMagic));
+}
+
+#[test]
+fn bad_cycle_counter() {
+    let mut gb = gameboy(b"COUNTER");
+    let mut saved = gb.save_state();
+
+    saved[CYCLES..CYCLES + 8].fill(0);
+    assert_eq!(gb.load_state(&saved), Err(StateError::Invalid("cpu cycle counter")));
+}
+
+#[test]
+fn bad_cpu_mode() {
+    let mut gb = gameboy(b"COUNTER");
+    let mut saved = gb.save_state();
+
+    // after the cycle counter and the ei delay
+    saved[CYCLES + 8 + 1] = 3;
+    assert_eq!(gb.load_state(&saved), Err(StateError::Invalid("cpu mode")));
+}
@@ -0,0 +1,72 @@
+//! The instruction log, in the format gameboy-doctor compares.
+
+mod common;
+
+use std::{io::Write, sync::{Arc, Mutex}};
+
+use sm83::trace::Tracer;
+
+use common::COUNTER;
+
+/// Collects what the tracer writes, so the test can still see it once the tracer has it.
+#[derive(Clone, Default)]
+struct Log(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Log {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+fn gameboy() -> gb::Gameboy<'static> {
+    // the boot rom leaves h and c set for the header checksum of an empty title, 0xe7
+    common::gameboy(&common::rom(&COUNTER))
+}
+
+fn lines(log: &Log) -> Vec<String> {
+    String::from_utf8(log.0.lock().unwrap().clone()).unwrap().lines().map(str::to_string).collect()
+}
+
+#[test]
+fn doctor() {
+    let log = Log::default();
+    let mut gb = gameboy();
+    gb.set_tracer(Some(Tracer::new(log.clone())));
+    for _ in 0..100 { gb.step(); }
+
+    let lines = lines(&log);
+    assert_eq!(lines[0], "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01");
+    assert_eq!(lines[1], "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,00");
+    assert_eq!(lines[3], "A:00 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:3C,EA,00,C0");
+}
+
+#[test]
+fn filtered() {
+    let log = Log::default();
+    let mut gb = gameboy();
+    let mut tracer = Tracer::new(log.clone());
+    tracer.pc = Some(0x0152..=0x0152);
+    gb.set_tracer(Some(tracer));
+    for _ in 0..400 { gb.step(); }
+
+    let lines = lines(&log);
+    assert!(lines.len() > 1);
+    assert!(lines.iter().all(|l| l.contains("PC:0152")));
+    assert!(lines[1].starts_with("A:01 F:10"));
+}
+
+#[test]
+fn ring() {
+    let log = Log::default();
+    let mut gb = gameboy();
+    gb.set_tracer(Some(Tracer::ring(log.clone(), 2)));
+    for _ in 0..400 { gb.step(); }
+    assert!(lines(&log).is_empty());
+
+    // dropping a tracer only dumps it on a panic
+    gb.set_tracer(None);
+    assert!(lines(&log).is_empty());
+}
@@ -0,0 +1,34 @@
+use clap::Parser;
+
+#[derive(Parser)]
+pub struct Args {
+    /// Where `get_tests.sh` put the test roms
+    #[arg(default_value = ".")]
+    pub dir: String,
+
+    /// Only run roms whose path contains this
+    #[arg(short, long)]
+    pub filter: Option<String>,
+
+    /// How many cycles to give each rom before it counts as timed out
+    #[arg(long, default_value_t = gb::CLOCK_HZ * 60)]
+    pub cycles: usize,
+
+    /// Roms that are known to pass, one path per line, only these count as regressions when they
+    /// fail. Every failure is a regression without one
+    #[arg(long)]
+    pub baseline: Option<String>,
+
+    /// Write the roms that passed to this file, to be used as a baseline later
+    #[arg(long)]
+    pub write_baseline: Option<String>,
+
+    /// Where to find the reference screenshots, named after their rom with a png extension. They
+    /// are looked for next to the rom by default
+    #[arg(long)]
+    pub references: Option<String>,
+
+    /// How many roms to run at once, one per cpu by default
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+}
@@ -0,0 +1,332 @@
+use std::{cell::RefCell, collections::HashSet, fmt, path::{Path, PathBuf}, rc::Rc, sync::{atomic::*, *}, thread};
+
+use clap::Parser;
+
+mod args;
+mod screen;
+
+const FRAME_CYCLES: usize = 70224;
+
+/// The registers mooneye roms leave behind when they pass, in b, c, d, e, h, l order.
+const FIBONACCI: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+/// Blargg roms that report through cartridge ram put this after the status byte.
+const BLARGG_SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Suite {
+    Blargg,
+    Mooneye,
+    Acid2,
+    Mealybug,
+}
+
+impl Suite {
+    /// Screenshot roms run `ld b, b` when the screen is ready instead of reporting anything.
+    fn screenshot(self) -> bool {
+        matches!(self, Self::Acid2 | Self::Mealybug)
+    }
+}
+
+impl fmt::Display for Suite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Blargg => "blargg",
+            Self::Mooneye => "mooneye",
+            Self::Acid2 => "acid2",
+            Self::Mealybug => "mealybug",
+        })
+    }
+}
+
+struct Test {
+    suite: Suite,
+    path: PathBuf,
+    /// The path relative to the test directory, which is also what baselines list.
+    name: String,
+}
+
+impl Test {
+    /// Mooneye roms name the models they are for after the last dash, the header decides
+    /// otherwise.
+    fn model(&self) -> Option<gb::Model> {
+        if self.suite != Suite::Mooneye { return None; }
+
+        let stem = self.path.file_stem()?.to_str()?;
+        let (_, tag) = stem.rsplit_once('-')?;
+
+        if tag.chars().all(|c| "GSCA".contains(c)) {
+            // model groups, G is dmg and mgb, S is sgb and sgb2, C and A are cgb and agb
+            match tag.chars().next()? {
+                'G' => Some(gb::Model::Dmg),
+                'S' => Some(gb::Model::Sgb),
+                _ => Some(gb::Model::Cgb),
+            }
+        } else if tag.contains("dmg") || tag.contains("mgb") {
+            Some(gb::Model::Dmg)
+        } else if tag.contains("sgb") {
+            Some(gb::Model::Sgb)
+        } else if tag.contains("cgb") || tag.contains("agb") || tag.contains("ags") {
+            Some(gb::Model::Cgb)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    Pass,
+    Fail(String),
+    Timeout,
+    Skip(String),
+}
+
+impl Outcome {
+    fn failed(&self) -> bool {
+        matches!(self, Self::Fail(_) | Self::Timeout)
+    }
+}
+
+/// Collects what the rom sends down the link cable.
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl gb::serial::LinkCable for Capture {
+    fn transfer(&mut self, data: u8) -> u8 {
+        self.0.borrow_mut().push(data);
+        0xff
+    }
+
+    fn poll(&mut self, _data: Option<u8>) -> Option<u8> { None }
+}
+
+fn main() {
+    let args = args::Args::parse();
+    let dir = Path::new(&args.dir);
+
+    let mut tests = find_tests(dir);
+    if let Some(filter) = &args.filter {
+        tests.retain(|t| t.name.contains(filter.as_str()));
+    }
+
+    if tests.is_empty() {
+        eprintln!("No test roms in {}, run get_tests.sh there first", dir.display());
+        std::process::exit(1);
+    }
+
+    let baseline = args.baseline.as_ref().map(|path| {
+        let list = std::fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read the baseline {path}: {e}");
+            std::process::exit(1);
+        });
+
+        list.lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(str::to_string)
+            .collect::<HashSet<_>>()
+    });
+
+    let outcomes = run_all(&tests, &args);
+
+    let regressed = |t: &Test, o: &Outcome| o.failed() && baseline.as_ref().is_none_or(|b| b.contains(&t.name));
+
+    let width = tests.iter().map(|t| t.name.len()).max().unwrap_or(0);
+    let mut counts = [0; 4];
+    let mut regressions = 0;
+
+    for (t, o) in tests.iter().zip(&outcomes) {
+        let (i, result, note) = match o {
+            Outcome::Pass => (0, "pass", ""),
+            Outcome::Fail(why) => (1, "fail", why.as_str()),
+            Outcome::Timeout => (2, "timeout", ""),
+            Outcome::Skip(why) => (3, "skip", why.as_str()),
+        };
+
+        counts[i] += 1;
+        let mark = if regressed(t, o) { regressions += 1; "!" } else { " " };
+        println!("{mark} {:<8} {:<width$} {result:<7} {note}", t.suite.to_string(), t.name);
+    }
+
+    println!(
+        "\n{} passed, {} failed, {} timed out, {} skipped, {regressions} regressions",
+        counts[0], counts[1], counts[2], counts[3],
+    );
+
+    if let Some(path) = &args.write_baseline {
+        let passed = tests.iter().zip(&outcomes)
+            .filter(|(_, o)| **o == Outcome::Pass)
+            .map(|(t, _)| format!("{}\n", t.name))
+            .collect::<String>();
+
+        std::fs::write(path, passed).unwrap_or_else(|e| {
+            eprintln!("Failed to write the baseline {path}: {e}");
+            std::process::exit(1);
+        });
+    }
+
+    if regressions != 0 { std::process::exit(1); }
+}
+
+fn find_tests(dir: &Path) -> Vec<Test> {
+    let mut tests = Vec::new();
+
+    let mut add = |suite, path: &Path| {
+        let Some(ext) = path.extension() else { return };
+        if ext != "gb" && ext != "gbc" { return; }
+
+        let name = path.strip_prefix(dir).unwrap_or(path).components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        // utils only dump hardware state, manual-only roms have to be looked at
+        if suite == Suite::Mooneye && (name.contains("/utils/") || name.contains("/manual-only/")) { return; }
+
+        tests.push(Test { suite, path: path.to_path_buf(), name });
+    };
+
+    for (suite, sub) in [
+        (Suite::Blargg, "blargg-tests"),
+        (Suite::Mooneye, "mooneye-tests"),
+        (Suite::Mealybug, "mealybug-tearoom-tests"),
+    ] {
+        for path in walk(&dir.join(sub)) { add(suite, &path); }
+    }
+
+    add(Suite::Acid2, &dir.join("dmg-acid2.gb"));
+
+    tests.retain(|t| t.path.is_file());
+    tests.sort_by(|a, b| a.name.cmp(&b.name));
+    tests
+}
+
+fn walk(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+
+    entries.flatten().flat_map(|e| {
+        let path = e.path();
+        if path.is_dir() { walk(&path) } else { vec![path] }
+    }).collect()
+}
+
+fn run_all(tests: &[Test], args: &args::Args) -> Vec<Outcome> {
+    let jobs = args.jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    let next = AtomicUsize::new(0);
+    let outcomes = Mutex::new(vec![None; tests.len()]);
+
+    thread::scope(|s| {
+        for _ in 0..jobs.max(1) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(test) = tests.get(i) else { break };
+
+                let outcome = run(test, args);
+                eprintln!("{}: {outcome:?}", test.name);
+                outcomes.lock().unwrap()[i] = Some(outcome);
+            });
+        }
+    });
+
+    outcomes.into_inner().unwrap().into_iter().map(Option::unwrap).collect()
+}
+
+fn run(test: &Test, args: &args::Args) -> Outcome {
+    let bin = match std::fs::read(&test.path) {
+        Ok(bin) => bin,
+        Err(e) => return Outcome::Skip(e.to_string()),
+    };
+
+    let header = match gb::cartridge::Header::parse(&bin) {
+        Ok(header) => header,
+        Err(e) => return Outcome::Skip(e.to_string()),
+    };
+
+    // test roms do not all bother with the header checksum
+    let mapper = match gb::mapper::Mapper::from_bin_permissive(&bin) {
+        Ok(mapper) => mapper,
+        Err(e) => return Outcome::Skip(e.to_string()),
+    };
+
+    let reference = if test.suite.screenshot() {
+        let name = Path::new(test.path.file_name().unwrap()).with_extension("png");
+        let path = match &args.references {
+            Some(dir) => Path::new(dir).join(name),
+            None => test.path.with_extension("png"),
+        };
+
+        match screen::load_reference(&path) {
+            Ok(r) => Some(r),
+            Err(e) => return Outcome::Skip(format!("no reference screenshot, {e}")),
+        }
+    } else {
+        None
+    };
+
+    let model = test.model().unwrap_or_else(|| gb::Model::from_header(&header));
+    let fb = Arc::new(Mutex::new([0; 160 * 144]));
+    let serial = Rc::new(RefCell::new(Vec::new()));
+
+    let mut gb = gb::Gameboy::new(model, mapper, None, fb.clone(), Box::new(|_| {}), Arc::new(AtomicU8::new(0)));
+    gb.set_link_cable(Some(Box::new(Capture(serial.clone()))));
+
+    for cycle in 1..=args.cycles {
+        gb.step();
+
+        let s = gb.cpu_state();
+        if s.ir == 0x40 {
+            // ld b, b is the software breakpoint these suites use
+            if let Some(reference) = &reference {
+                // let the frame being drawn reach the framebuffer
+                for _ in 0..FRAME_CYCLES * 2 { gb.step(); }
+
+                return match screen::compare(&*fb.lock().unwrap(), model, reference) {
+                    0 => Outcome::Pass,
+                    n => Outcome::Fail(format!("{n} pixels differ")),
+                };
+            }
+
+            if test.suite == Suite::Mooneye {
+                match [s.b, s.c, s.d, s.e, s.h, s.l] {
+                    FIBONACCI => return Outcome::Pass,
+                    [0x42, 0x42, 0x42, 0x42, 0x42, 0x42] => return Outcome::Fail("assertion failed".into()),
+                    _ => {},
+                }
+            }
+        }
+
+        if test.suite == Suite::Blargg && cycle % FRAME_CYCLES == 0 {
+            if let Some(outcome) = blargg(&gb, &serial.borrow()) { return outcome; }
+        }
+    }
+
+    Outcome::Timeout
+}
+
+/// Blargg roms print to the link cable, and also to cartridge ram when they have any, where the
+/// status byte after the signature is 0x80 while running and 0 when passed.
+fn blargg(gb: &gb::Gameboy, serial: &[u8]) -> Option<Outcome> {
+    if let Some(ram) = gb.get_sram().filter(|r| r.len() > 4 && r[1..4] == BLARGG_SIGNATURE) {
+        let text = ram[4..].split(|&c| c == 0).next().unwrap();
+
+        match ram[0] {
+            0x80 => {},
+            0 => return Some(Outcome::Pass),
+            _ => return Some(Outcome::Fail(last_line(text))),
+        }
+    }
+
+    let text = String::from_utf8_lossy(serial);
+    if text.contains("Passed") {
+        Some(Outcome::Pass)
+    } else if text.contains("Failed") {
+        Some(Outcome::Fail(last_line(serial)))
+    } else {
+        None
+    }
+}
+
+fn last_line(text: &[u8]) -> String {
+    let text = String::from_utf8_lossy(text);
+    text.lines().map(str::trim).rfind(|l| !l.is_empty()).unwrap_or_default().to_string()
+}
@@ -0,0 +1,40 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Loads a 160x144 reference screenshot as rgb.
+pub fn load_reference(path: &Path) -> Result<Vec<[u8; 3]>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| format!("{}: {e}", path.display()))?;
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    if (info.width, info.height) != (160, 144) {
+        return Err(format!("{} is {}x{}, not 160x144", path.display(), info.width, info.height));
+    }
+
+    let channels = info.color_type.samples();
+    Ok(buf[..info.buffer_size()].chunks_exact(channels).map(|p| match p.len() {
+        // gray, with or without alpha
+        1 | 2 => [p[0]; 3],
+        _ => [p[0], p[1], p[2]],
+    }).collect())
+}
+
+/// Counts the pixels that differ from the reference. DMG shades are matched against the nearest
+/// of four grays so the reference palette does not matter, CGB colors are compared at the 5 bits
+/// the ppu has.
+pub fn compare(fb: &[u16], model: gb::Model, reference: &[[u8; 3]]) -> usize {
+    fb.iter().zip(reference).filter(|(&p, r)| match model {
+        gb::Model::Dmg | gb::Model::Sgb => {
+            let gray = r.iter().map(|&c| c as usize).sum::<usize>() / 3;
+            (255 - gray + 42) / 85 != p as usize & 3
+        },
+        gb::Model::Cgb => {
+            let c = [p & 0x1f, (p >> 5) & 0x1f, (p >> 10) & 0x1f];
+            c.iter().zip(*r).any(|(&c, &r)| c != r as u16 >> 3)
+        },
+    }).count()
+}
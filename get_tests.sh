@@ -23,3 +23,17 @@ if [ ! -e mealybug-tearoom-tests ]; then
         rm mealybug-tearoom-tests.zip
     }
 fi
+
+# reference screenshots go next to their roms, where gb_test_runner looks for them
+if [ ! -e dmg-acid2.png ]; then
+    wget "https://raw.githubusercontent.com/mattcurrie/dmg-acid2/master/img/reference-dmg.png" -O dmg-acid2.png
+fi
+
+if [ -e mealybug-tearoom-tests ] && [ ! -e mealybug-tearoom-src ]; then
+    git clone https://github.com/mattcurrie/mealybug-tearoom-tests.git mealybug-tearoom-src --depth 1 && {
+        for rom in $(find mealybug-tearoom-tests -name '*.gb'); do
+            png="mealybug-tearoom-src/expected/DMG-blob/$(basename "$rom" .gb).png"
+            [ -e "$png" ] && cp "$png" "${rom%.gb}.png"
+        done
+    }
+fi
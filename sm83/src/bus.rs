@@ -1,7 +1,26 @@
 pub trait Bus {
     fn load(&mut self, a: u16) -> u8;
     fn store(&mut self, a: u16, d: u8);
+    /// Reads without any of the side effects of a cpu access, for debugging. The default just
+    /// loads, which is only right for a bus whose reads change nothing.
+    fn peek(&mut self, a: u16) -> u8 { self.load(a) }
+    /// Writes without any of the side effects of a cpu access, for debugging.
+    fn poke(&mut self, a: u16, d: u8) { self.store(a, d) }
+    /// Called when the cpu increments or decrements a register pair, which puts `a` on the
+    /// address bus without a read or write.
     fn glitch_store(&mut self, _a: u16) {}
 
+    /// Called on `stop`, returns whether it switched the cpu speed instead of stopping.
+    fn speed_switch(&mut self) -> bool { false }
+
+    /// Polled while the cpu is stopped, returns whether something woke it up.
+    fn stop_wake(&mut self) -> bool { true }
+
+    /// The bank mapped at `a`, for bank qualified breakpoints. `None` where nothing is banked.
+    fn bank(&self, _a: u16) -> Option<usize> { None }
+
+    /// Takes the m-cycles the cpu has to sit out before its next instruction, like for a dma.
+    fn stall(&mut self) -> usize { 0 }
+
     fn external_step(&mut self, div: usize, int_mgr: &mut crate::cpu::InterruptManager);
 }
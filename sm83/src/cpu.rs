@@ -19,11 +19,26 @@ pub struct Sm83<Bus: bus::Bus> {
     pub div: usize,
 
     mode: Mode,
+
+    pub debugger: Option<debug::Debugger>,
+    pub tracer: Option<trace::Tracer>,
 }
 
-enum Mode {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
     Normal,
     Halting,
+    /// Stopped by `stop`, with the clock off until a button is pressed.
+    Stopped,
+}
+
+/// Execution state not covered by [`State`], needed to snapshot the core mid-instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Internals {
+    pub ime: bool,
+    pub cycles: usize,
+    pub after_ei: bool,
+    pub mode: Mode,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -140,6 +155,9 @@ impl<B: bus::Bus> Sm83<B> {
             div: 0,
 
             mode: Mode::Normal,
+
+            debugger: None,
+            tracer: None,
         }
     }
 
@@ -175,6 +193,79 @@ impl<B: bus::Bus> Sm83<B> {
         }
     }
 
+    pub fn get_internals(&self) -> Internals {
+        Internals {
+            ime: self.ime,
+            cycles: self.cycles,
+            after_ei: self.after_ei,
+            mode: self.mode,
+        }
+    }
+
+    pub fn set_internals(&mut self, i: &Internals) {
+        self.ime = i.ime;
+        self.cycles = i.cycles;
+        self.after_ei = i.after_ei;
+        self.mode = i.mode;
+    }
+
+    /// Tells the debugger how far to run from here, call it before running again after a stop.
+    pub fn resume(&mut self, step: debug::Step) {
+        let at_boundary = self.cycles == 1;
+        let pc = self.pc.wrapping_sub(1);
+
+        let target = match self.ir {
+            0xc4 | 0xcc | 0xcd | 0xd4 | 0xdc => pc.wrapping_add(3),
+            ir if ir & 0xc7 == 0xc7 => pc.wrapping_add(1),
+            _ => pc,
+        };
+
+        // stepping over anything else is just a step
+        let step = if step == debug::Step::Over && target == pc { debug::Step::Into } else { step };
+
+        if let Some(d) = &mut self.debugger {
+            d.fresh = at_boundary;
+            d.hit = None;
+            d.step = Some((step, target, self.sp));
+        }
+    }
+
+    /// Asks the debugger whether to stop before the next `step`, which only happens between
+    /// instructions.
+    pub fn check_break(&mut self) -> Option<debug::Stop> {
+        use debug::{Step, Stop};
+
+        if self.cycles != 1 { return None; }
+
+        let s = self.get_state();
+        let pc = s.pc.wrapping_sub(1);
+        let bank = self.bus.bank(pc);
+        let running = matches!(self.mode, Mode::Normal);
+        let d = self.debugger.as_mut()?;
+
+        let stop = d.hit.take().or_else(|| {
+            if d.fresh || !running { return None; }
+
+            let cond = d.check_conditions(&s);
+            let bp = d.breakpoints.iter().find(|b| {
+                b.pc == pc && b.bank.is_none_or(|k| bank == Some(k)) && b.cond.is_none_or(|c| c.holds(&s))
+            });
+
+            let stepped = match d.step {
+                Some((Step::Into, ..)) => true,
+                Some((Step::Over, target, sp)) => pc == target && s.sp >= sp,
+                Some((Step::Out, _, sp)) => s.sp > sp,
+                Some((Step::Continue, ..)) | None => false,
+            };
+
+            bp.map(|b| Stop::Breakpoint(b.pc)).or(cond).or(stepped.then_some(Stop::Step))
+        })?;
+
+        d.fresh = true;
+        d.step = None;
+        Some(stop)
+    }
+
     pub fn step(&mut self) {
         macro_rules! setf {
             ($($t: tt)*) => {
@@ -182,11 +273,28 @@ impl<B: bus::Bus> Sm83<B> {
             };
         }
 
-        self.cycles -= 1;
+        // an illegal opcode leaves this at 0, wrapping it locks the cpu up
+        self.cycles = self.cycles.wrapping_sub(1);
         if self.cycles != 0 {
             return;
         }
 
+        let stall = self.bus.stall();
+        if stall != 0 {
+            self.incr_cycles(stall);
+            return;
+        }
+
+        if matches!(self.mode, Mode::Stopped) {
+            // nothing else gets clocked until the cpu wakes up
+            if !self.bus.stop_wake() {
+                self.cycles = 4;
+                return;
+            }
+
+            self.mode = Mode::Normal;
+        }
+
         if core::mem::replace(&mut self.after_ei, false) {
             self.ime = true;
         }
@@ -196,13 +304,17 @@ impl<B: bus::Bus> Sm83<B> {
             self.ir = self.fetch_u8();
 
             if matches!(self.mode, Mode::Halting) {
-                self.pc -= 1;
+                self.pc = self.pc.wrapping_sub(1);
             }
 
             return;
         }
 
+        if let Some(d) = &mut self.debugger { d.fresh = false; }
+        if self.tracer.is_some() { self.trace(); }
+
         let inst = self.ir;
+        let mut halt_bug = false;
         let x = inst >> 6;
         let y = (inst >> 3) & 7;
         let z = inst & 7;
@@ -216,18 +328,25 @@ impl<B: bus::Bus> Sm83<B> {
                 self.store_bus_u16(a, self.sp);
             },
             (0, 2, 0, _, _) => { // stop
-                // TODO: do the accurate thing
-                // https://gist.github.com/SonoSooS/c0055300670d678b5ae8433e20bea595#nop-and-stop
                 self.fetch_u8();
+                self.div = 0;
+
+                // a cgb switching speeds carries on, interrupts can't wake a stopped cpu
+                if !self.bus.speed_switch() {
+                    self.mode = Mode::Stopped;
+                    self.ir = self.fetch_u8();
+                    return;
+                }
             },
             (0, 3, 0, _, _) => { // jr s8
-                self.pc += self.fetch_u8() as i8 as u16;
+                let e = self.fetch_u8() as i8;
+                self.pc = self.pc.wrapping_add_signed(e as i16);
                 self.incr_cycles(1);
             },
             (0, 4..=7, 0, _, _) => { // jr cc, s8
-                let d = self.fetch_u8() as i8 as u16;
+                let e = self.fetch_u8() as i8;
                 if self.cond_check(y - 4) {
-                    self.pc += d;
+                    self.pc = self.pc.wrapping_add_signed(e as i16);
                     self.incr_cycles(1);
                 }
             },
@@ -241,7 +360,7 @@ impl<B: bus::Bus> Sm83<B> {
                 let b = self.load_reg_r16(p);
                 let (v, c) = a.overflowing_add(b);
                 self.store_reg_r16(HL, v);
-                setf!(c c, h hc_u16(a, b, u16::add), n 0);
+                setf!(c c, h hc_u16(a, b, u16::wrapping_add), n 0);
             },
             (0, _, 2, _, 0) => { // ld [m16], a
                 let a = self.load_reg_r16mem(p);
@@ -267,13 +386,13 @@ impl<B: bus::Bus> Sm83<B> {
             },
             (0, _, 4, _, _) => { // inc r8
                 let d = self.load_reg_r8(y);
-                self.store_reg_r8(y, d + 1);
-                setf!(h hc_u8(d, 1, u8::add), n 0, z d == 0xff);
+                self.store_reg_r8(y, d.wrapping_add(1));
+                setf!(h hc_u8(d, 1, u8::wrapping_add), n 0, z d == 0xff);
             },
             (0, _, 5, _, _) => { // dec r8
                 let d = self.load_reg_r8(y);
-                self.store_reg_r8(y, d - 1);
-                setf!(h hc_u8(d, 1, u8::sub), n 1, z d == 0x01);
+                self.store_reg_r8(y, d.wrapping_sub(1));
+                setf!(h hc_u8(d, 1, u8::wrapping_sub), n 1, z d == 0x01);
             },
             (0, _, 6, _, _) => { // ld r8 i8
                 let v = self.fetch_u8();
@@ -287,8 +406,9 @@ impl<B: bus::Bus> Sm83<B> {
             (0, 4, 7, _, _) => { // daa
                 let mut a = self.load_reg_r8(A);
                 let n = self.get_flag(FN);
-                if self.get_flag(FC) || (!n && a > 0x99) { a -= 0x60 * (n as u8 * 2 - 1); setf!(c 1); }
-                if self.get_flag(FH) || (!n && (a & 0x0f) > 0x09) { a -= 0x06 * (n as u8 * 2 - 1); }
+                let adjust = |a: u8, by: u8| if n { a.wrapping_sub(by) } else { a.wrapping_add(by) };
+                if self.get_flag(FC) || (!n && a > 0x99) { a = adjust(a, 0x60); setf!(c 1); }
+                if self.get_flag(FH) || (!n && (a & 0x0f) > 0x09) { a = adjust(a, 0x06); }
                 self.store_reg_r8(A, a);
 
                 setf!(z a == 0, h 0);
@@ -305,7 +425,13 @@ impl<B: bus::Bus> Sm83<B> {
                 setf!(n 0, h 0, c !self.get_flag(FC));
             },
             (1, 6, 6, _, _) => { // halt
-                self.mode = Mode::Halting;
+                // with an interrupt already waiting and ime off the cpu never halts, and the
+                // byte after gets read twice
+                if !self.ime && self.ints.pending & self.ints.enabled & 0x1f != 0 {
+                    halt_bug = true;
+                } else {
+                    self.mode = Mode::Halting;
+                }
             },
             (1, _, _, _, _) => { // ld r8, r8
                 let d = self.load_reg_r8(z);
@@ -332,10 +458,10 @@ impl<B: bus::Bus> Sm83<B> {
                 let e = self.fetch_u8();
                 self.incr_cycles(2);
                 let sp = self.sp;
-                self.sp += e as i8 as u16;
+                self.sp = sp.wrapping_add_signed(e as i8 as i16);
 
                 let (_, c) = (sp as u8).overflowing_add(e);
-                setf!(z 0, n 0, c c, h hc_u8(sp as _, e, u8::add));
+                setf!(z 0, n 0, c c, h hc_u8(sp as _, e, u8::wrapping_add));
             },
             (3, 6, 0, _, _) => { // ldh a, i8
                 let a = 0xff00 | self.fetch_u8() as u16;
@@ -344,12 +470,12 @@ impl<B: bus::Bus> Sm83<B> {
             },
             (3, 7, 0, _, _) => { // ld hl, sp + s8
                 let e = self.fetch_u8();
-                self.store_reg_r16(HL, self.sp + e as i8 as u16);
+                self.store_reg_r16(HL, self.sp.wrapping_add_signed(e as i8 as i16));
                 self.incr_cycles(1);
 
                 let (_, c) = (self.sp as u8).overflowing_add(e);
                 let sp = self.sp;
-                setf!(z 0, n 0, c c, h hc_u8(sp as _, e, u8::add));
+                setf!(z 0, n 0, c c, h hc_u8(sp as _, e, u8::wrapping_add));
             },
             (3, _, 1, _, 0) => { // pop r16
                 let v = self.pop();
@@ -438,17 +564,61 @@ impl<B: bus::Bus> Sm83<B> {
             (3, _, 7, _, _) => { // rst
                 self.call(y as u16 * 8);
             },
-            _ => return, // inv opc never fetch
+            _ => { // inv opc never fetch
+                if let Some(t) = &mut self.tracer { t.dump(); }
+                return;
+            },
         }
 
         self.check_interrupts();
         self.ir = self.fetch_u8();
+        if halt_bug { self.pc = self.pc.wrapping_sub(1); }
 
         if matches!(self.mode, Mode::Halting) {
-            self.pc -= 1;
+            self.pc = self.pc.wrapping_sub(1);
         }
     }
 
+    fn trace(&mut self) {
+        let pc = self.pc.wrapping_sub(1);
+        let bank = self.bus.bank(pc);
+        if !self.tracer.as_ref().is_some_and(|t| t.wants(pc, bank)) { return; }
+
+        let mem = [self.ir, self.peek(self.pc), self.peek(self.pc.wrapping_add(1)), self.peek(self.pc.wrapping_add(2))];
+        let line = trace::Line::new(&self.get_state(), mem);
+        if let Some(t) = &mut self.tracer { t.log(line); }
+    }
+
+    /// Reads memory without taking any time or disturbing anything.
+    pub fn peek(&mut self, a: u16) -> u8 {
+        #[cfg(not(feature = "test"))]
+        let v = match a {
+            0xff04 => (self.div >> 8) as u8,
+            0xff0f => self.ints.pending,
+            0xffff => self.ints.enabled,
+            _ => self.bus.peek(a),
+        };
+
+        #[cfg(feature = "test")]
+        let v = self.bus.peek(a);
+
+        v
+    }
+
+    /// Writes memory without taking any time, like `peek`.
+    pub fn poke(&mut self, a: u16, d: u8) {
+        #[cfg(not(feature = "test"))]
+        match a {
+            0xff04 => self.div = 0,
+            0xff0f => self.ints.pending = d,
+            0xffff => self.ints.enabled = d,
+            _ => self.bus.poke(a, d),
+        }
+
+        #[cfg(feature = "test")]
+        self.bus.poke(a, d);
+    }
+
     fn execute_cb(&mut self) {
         let inst = self.fetch_u8();
         let x = inst >> 6;
@@ -472,11 +642,13 @@ impl<B: bus::Bus> Sm83<B> {
     }
 
     fn idu_inc(&mut self, n: u16) -> u16 {
-        n + 1
+        self.bus.glitch_store(n);
+        n.wrapping_add(1)
     }
 
     fn idu_dec(&mut self, n: u16) -> u16 {
-        n - 1
+        self.bus.glitch_store(n);
+        n.wrapping_sub(1)
     }
 
     fn shift(&mut self, m: u8, v: u8, a: bool) -> u8 {
@@ -531,7 +703,7 @@ impl<B: bus::Bus> Sm83<B> {
     }
 
     fn check_interrupts(&mut self) {
-        let i = self.ints.pending & self.ints.enabled;
+        let i = self.ints.pending & self.ints.enabled & 0x1f;
 
         if i != 0 {
             self.mode = Mode::Normal;
@@ -541,15 +713,29 @@ impl<B: bus::Bus> Sm83<B> {
 
         if !self.ime { return; }
 
-        for b in 0..4 {
-            if (i >> b) & 1 != 0 {
+        if let Some(d) = &mut self.debugger { d.fresh = false; }
+        self.ime = false;
+        self.incr_cycles(2);
+
+        let pc = self.pc;
+        self.sp = self.idu_dec(self.sp);
+        self.incr_cycles(1);
+        self.store_bus_u8(self.sp, (pc >> 8) as u8);
+
+        // the interrupt is only picked after the high byte is pushed, which can land in ie and
+        // cancel it, leaving pc at 0
+        let i = self.ints.pending & self.ints.enabled & 0x1f;
+
+        self.sp = self.idu_dec(self.sp);
+        self.store_bus_u8(self.sp, pc as u8);
+
+        self.pc = match (0..5).find(|b| (i >> b) & 1 != 0) {
+            Some(b) => {
                 self.ints.pending ^= 1 << b;
-                self.ime = false;
-                self.incr_cycles(2);
-                self.call(0x40 + b * 8);
-                return;
-            }
-        }
+                0x40 + b as u16 * 8
+            },
+            None => 0,
+        };
     }
 
     fn incr_cycles(&mut self, t: usize) {
@@ -562,18 +748,20 @@ impl<B: bus::Bus> Sm83<B> {
     }
 
     fn push(&mut self, v: u16) {
+        self.sp = self.idu_dec(self.sp);
         self.incr_cycles(1);
-        self.sp -= 1;
         self.store_bus_u8(self.sp, (v >> 8) as u8);
-        self.sp -= 1;
+        self.sp = self.idu_dec(self.sp);
         self.store_bus_u8(self.sp, v as u8);
     }
 
     fn pop(&mut self) -> u16 {
-        let l = self.load_bus_u8(self.sp);
-        self.sp += 1;
-        let h = self.load_bus_u8(self.sp);
-        self.sp += 1;
+        let sp = self.sp;
+        self.sp = self.idu_inc(sp);
+        let l = self.load_bus_u8(sp);
+        let sp = self.sp;
+        self.sp = self.idu_inc(sp);
+        let h = self.load_bus_u8(sp);
         ((h as u16) << 8) | (l as u16)
     }
 
@@ -599,24 +787,24 @@ impl<B: bus::Bus> Sm83<B> {
             0 => { // add
                 let (v, c) = a.overflowing_add(b);
                 self.store_reg_r8(A, v);
-                setf!(h hc_u8(a, b, u8::add), n 0, z v == 0, c c);
+                setf!(h hc_u8(a, b, u8::wrapping_add), n 0, z v == 0, c c);
             },
             1 => { // adc
                 let c = self.get_flag(FC);
                 let (v, d) = a.carrying_add(b, c);
                 self.store_reg_r8(A, v);
-                setf!(h hc3_u8(a, b, c as u8, u8::add), n 0, z v == 0, c d);
+                setf!(h hc3_u8(a, b, c as u8, u8::wrapping_add), n 0, z v == 0, c d);
             },
             2 => { // sub
                 let (v, c) = a.overflowing_sub(b);
                 self.store_reg_r8(A, v);
-                setf!(h hc_u8(a, b, u8::sub), n 1, z v == 0, c c);
+                setf!(h hc_u8(a, b, u8::wrapping_sub), n 1, z v == 0, c c);
             },
             3 => { // sbc
                 let c = self.get_flag(FC);
                 let (v, d) = a.borrowing_sub(b, c);
                 self.store_reg_r8(A, v);
-                setf!(h hc3_u8(a, b, c as u8, u8::sub), n 1, z v == 0, c d);
+                setf!(h hc3_u8(a, b, c as u8, u8::wrapping_sub), n 1, z v == 0, c d);
             },
             4 => { // and
                 let v = a & b;
@@ -635,7 +823,7 @@ impl<B: bus::Bus> Sm83<B> {
             },
             7 => { // cp
                 let (v, c) = a.overflowing_sub(b);
-                setf!(h hc_u8(a, b, u8::sub), n 1, z v == 0, c c);
+                setf!(h hc_u8(a, b, u8::wrapping_sub), n 1, z v == 0, c c);
             },
             _ => panic!(),
         }
@@ -647,13 +835,13 @@ impl<B: bus::Bus> Sm83<B> {
 
     fn fetch_u8(&mut self) -> u8 {
         let v = self.load_bus_u8(self.pc);
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         v
     }
 
     fn fetch_u16(&mut self) -> u16 {
         let v = self.load_bus_u16(self.pc);
-        self.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         v
     }
 
@@ -661,20 +849,23 @@ impl<B: bus::Bus> Sm83<B> {
         self.incr_cycles(1);
 
         #[cfg(not(feature = "test"))]
-        match a {
+        let v = match a {
             0xff04 => (self.div >> 8) as u8,
             0xff0f => self.ints.pending,
             0xffff => self.ints.enabled,
             _ => self.bus.load(a),
-        }
+        };
 
         #[cfg(feature = "test")]
-        self.bus.load(a)
+        let v = self.bus.load(a);
+
+        if let Some(d) = &mut self.debugger { d.access(a, v, false); }
+        v
     }
 
     fn load_bus_u16(&mut self, a: u16) -> u16 {
         let l = self.load_bus_u8(a);
-        let h = self.load_bus_u8(a + 1);
+        let h = self.load_bus_u8(a.wrapping_add(1));
         ((h as u16) << 8) | l as u16
     }
 
@@ -691,11 +882,13 @@ impl<B: bus::Bus> Sm83<B> {
 
         #[cfg(feature = "test")]
         self.bus.store(a, d);
+
+        if let Some(dbg) = &mut self.debugger { dbg.access(a, d, true); }
     }
 
     fn store_bus_u16(&mut self, a: u16, d: u16) {
         self.store_bus_u8(a, d as u8);
-        self.store_bus_u8(a + 1, (d >> 8) as u8);
+        self.store_bus_u8(a.wrapping_add(1), (d >> 8) as u8);
     }
 
     fn load_reg_r8(&mut self, r: u8) -> u8 {
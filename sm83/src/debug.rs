@@ -0,0 +1,191 @@
+//! Breakpoints, watchpoints and stepping, checked by [`Sm83::check_break`] between instructions.
+//!
+//! [`Sm83::check_break`]: crate::Sm83::check_break
+
+use core::{fmt, str::FromStr};
+
+use crate::cpu::State;
+
+/// Stops before running the instruction at `pc`, only while `bank` is mapped there if given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub pc: u16,
+    pub bank: Option<usize>,
+    /// Only stops when this holds too.
+    pub cond: Option<Condition>,
+}
+
+/// Stops after an instruction that read or wrote `addr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: u16,
+    pub read: bool,
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    A, F, B, C, D, E, H, L,
+    Af, Bc, De, Hl, Sp, Pc,
+}
+
+impl Reg {
+    pub fn get(self, s: &State) -> u16 {
+        let pair = |h: u8, l: u8| ((h as u16) << 8) | l as u16;
+
+        match self {
+            Self::A => s.a as u16,
+            Self::F => s.f as u16,
+            Self::B => s.b as u16,
+            Self::C => s.c as u16,
+            Self::D => s.d as u16,
+            Self::E => s.e as u16,
+            Self::H => s.h as u16,
+            Self::L => s.l as u16,
+            Self::Af => pair(s.a, s.f),
+            Self::Bc => pair(s.b, s.c),
+            Self::De => pair(s.d, s.e),
+            Self::Hl => pair(s.h, s.l),
+            Self::Sp => s.sp,
+            // the opcode is already fetched, so pc is one past it
+            Self::Pc => s.pc.wrapping_sub(1),
+        }
+    }
+}
+
+impl FromStr for Reg {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "a" => Self::A,
+            "f" => Self::F,
+            "b" => Self::B,
+            "c" => Self::C,
+            "d" => Self::D,
+            "e" => Self::E,
+            "h" => Self::H,
+            "l" => Self::L,
+            "af" => Self::Af,
+            "bc" => Self::Bc,
+            "de" => Self::De,
+            "hl" => Self::Hl,
+            "sp" => Self::Sp,
+            "pc" => Self::Pc,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::A => "a",
+            Self::F => "f",
+            Self::B => "b",
+            Self::C => "c",
+            Self::D => "d",
+            Self::E => "e",
+            Self::H => "h",
+            Self::L => "l",
+            Self::Af => "af",
+            Self::Bc => "bc",
+            Self::De => "de",
+            Self::Hl => "hl",
+            Self::Sp => "sp",
+            Self::Pc => "pc",
+        })
+    }
+}
+
+/// Holds when a register has the given value. On its own it stops when it starts to hold,
+/// rather than on every instruction while it does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub reg: Reg,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn holds(&self, s: &State) -> bool {
+        self.reg.get(s) == self.value
+    }
+}
+
+/// How far to run before stopping on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Until something else stops it.
+    Continue,
+    /// One instruction.
+    Into,
+    /// One instruction, or a whole call or rst.
+    Over,
+    /// Until the current function returns.
+    Out,
+}
+
+/// Why the cpu stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u16),
+    Watchpoint { addr: u16, value: u8, write: bool },
+    Condition(Condition),
+    Step,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Breakpoint(pc) => write!(f, "breakpoint at {pc:04x}"),
+            Self::Watchpoint { addr, value, write: true } => write!(f, "wrote {value:02x} to {addr:04x}"),
+            Self::Watchpoint { addr, value, write: false } => write!(f, "read {value:02x} from {addr:04x}"),
+            Self::Condition(c) => write!(f, "{} is {:x}", c.reg, c.value),
+            Self::Step => write!(f, "stepped"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub conditions: Vec<Condition>,
+
+    /// What the conditions were at the last check, to stop only when one starts to hold.
+    held: Vec<bool>,
+    /// The first watchpoint the running instruction hit.
+    pub(crate) hit: Option<Stop>,
+    /// Set until an instruction runs, so resuming on a breakpoint does not stop right away.
+    pub(crate) fresh: bool,
+    /// The step being run, with the pc to step over to and the stack pointer it started at.
+    pub(crate) step: Option<(Step, u16, u16)>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn access(&mut self, addr: u16, value: u8, write: bool) {
+        if self.hit.is_some() { return; }
+
+        if self.watchpoints.iter().any(|w| w.addr == addr && if write { w.write } else { w.read }) {
+            self.hit = Some(Stop::Watchpoint { addr, value, write });
+        }
+    }
+
+    /// Checks the conditions, returning the first that started to hold.
+    pub(crate) fn check_conditions(&mut self, s: &State) -> Option<Stop> {
+        self.held.resize(self.conditions.len(), false);
+
+        let mut stop = None;
+        for (c, held) in self.conditions.iter().zip(&mut self.held) {
+            let holds = c.holds(s);
+            if holds && !*held && stop.is_none() { stop = Some(Stop::Condition(*c)); }
+            *held = holds;
+        }
+
+        stop
+    }
+}
@@ -0,0 +1,235 @@
+//! Decodes instructions into RGBDS syntax, with labels from a symbol file if there is one.
+
+use core::fmt;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// A register, register pair or condition.
+    Reg(&'static str),
+    /// Memory at a register pair, `hl+`, `hl-` and `c` included.
+    Ind(&'static str),
+    U8(u8),
+    U16(u16),
+    /// Memory at an address.
+    Addr(u16),
+    /// Where a jump, call or rst goes.
+    Target(u16),
+    /// The offset of `add sp, e8`.
+    I8(i8),
+    /// The source of `ld hl, sp + e8`.
+    SpOffset(i8),
+    Bit(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    pub len: u8,
+    /// In t-cycles, when a conditional branch is not taken. 0 for illegal opcodes, which lock
+    /// up the cpu.
+    pub cycles: u8,
+    /// In t-cycles, when a conditional branch is taken.
+    pub cycles_taken: Option<u8>,
+}
+
+const R8: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+const R16: [&str; 4] = ["bc", "de", "hl", "sp"];
+const R16_STK: [&str; 4] = ["bc", "de", "hl", "af"];
+const R16_MEM: [&str; 4] = ["bc", "de", "hl+", "hl-"];
+const COND: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ROT: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+const MISC: [&str; 8] = ["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"];
+
+fn r8(r: u8) -> Operand {
+    if r == 6 { Operand::Ind("hl") } else { Operand::Reg(R8[r as usize]) }
+}
+
+/// Decodes the instruction at `pc`, with `read` giving the bytes at an address.
+pub fn decode(pc: u16, mut read: impl FnMut(u16) -> u8) -> Instruction {
+    use Operand::*;
+
+    let op = read(pc);
+    let u8_at = |read: &mut dyn FnMut(u16) -> u8| read(pc.wrapping_add(1));
+    let u16_at = |read: &mut dyn FnMut(u16) -> u8| u16::from_le_bytes([read(pc.wrapping_add(1)), read(pc.wrapping_add(2))]);
+
+    let x = op >> 6;
+    let y = (op >> 3) & 7;
+    let z = op & 7;
+    let p = (y >> 1) as usize;
+    let q = y & 1;
+
+    // register [hl] takes an extra read, or a read and a write
+    let hl = |r: u8, extra: u8| if r == 6 { extra } else { 0 };
+
+    let (mnemonic, operands, len, cycles, cycles_taken) = match (x, z, y) {
+        (0, 0, 0) => ("nop", vec![], 1, 4, None),
+        (0, 0, 1) => ("ld", vec![Addr(u16_at(&mut read)), Reg("sp")], 3, 20, None),
+        (0, 0, 2) => ("stop", vec![], 2, 4, None),
+        (0, 0, 3..=7) => {
+            let e = u8_at(&mut read) as i8;
+            let target = Target(pc.wrapping_add(2).wrapping_add_signed(e as i16));
+
+            if y == 3 {
+                ("jr", vec![target], 2, 12, None)
+            } else {
+                ("jr", vec![Reg(COND[y as usize - 4]), target], 2, 8, Some(12))
+            }
+        },
+        (0, 1, _) if q == 0 => ("ld", vec![Reg(R16[p]), U16(u16_at(&mut read))], 3, 12, None),
+        (0, 1, _) => ("add", vec![Reg("hl"), Reg(R16[p])], 1, 8, None),
+        (0, 2, _) if q == 0 => ("ld", vec![Ind(R16_MEM[p]), Reg("a")], 1, 8, None),
+        (0, 2, _) => ("ld", vec![Reg("a"), Ind(R16_MEM[p])], 1, 8, None),
+        (0, 3, _) => (if q == 0 { "inc" } else { "dec" }, vec![Reg(R16[p])], 1, 8, None),
+        (0, 4 | 5, _) => (if z == 4 { "inc" } else { "dec" }, vec![r8(y)], 1, 4 + hl(y, 8), None),
+        (0, 6, _) => ("ld", vec![r8(y), U8(u8_at(&mut read))], 2, 8 + hl(y, 4), None),
+        (0, 7, _) => (MISC[y as usize], vec![], 1, 4, None),
+
+        (1, 6, 6) => ("halt", vec![], 1, 4, None),
+        (1, ..) => ("ld", vec![r8(y), r8(z)], 1, 4 + hl(y, 4) + hl(z, 4), None),
+
+        (2, ..) => alu(y, r8(z), 1, 4 + hl(z, 4)),
+
+        (3, 0, 0..=3) => ("ret", vec![Reg(COND[y as usize])], 1, 8, Some(20)),
+        (3, 0, 4) => ("ldh", vec![Addr(0xff00 | u8_at(&mut read) as u16), Reg("a")], 2, 12, None),
+        (3, 0, 5) => ("add", vec![Reg("sp"), I8(u8_at(&mut read) as i8)], 2, 16, None),
+        (3, 0, 6) => ("ldh", vec![Reg("a"), Addr(0xff00 | u8_at(&mut read) as u16)], 2, 12, None),
+        (3, 0, 7) => ("ld", vec![Reg("hl"), SpOffset(u8_at(&mut read) as i8)], 2, 12, None),
+        (3, 1, _) if q == 0 => ("pop", vec![Reg(R16_STK[p])], 1, 12, None),
+        (3, 1, 1) => ("ret", vec![], 1, 16, None),
+        (3, 1, 3) => ("reti", vec![], 1, 16, None),
+        (3, 1, 5) => ("jp", vec![Reg("hl")], 1, 4, None),
+        (3, 1, _) => ("ld", vec![Reg("sp"), Reg("hl")], 1, 8, None),
+        (3, 2, 0..=3) => ("jp", vec![Reg(COND[y as usize]), Target(u16_at(&mut read))], 3, 12, Some(16)),
+        (3, 2, 4) => ("ldh", vec![Ind("c"), Reg("a")], 1, 8, None),
+        (3, 2, 5) => ("ld", vec![Addr(u16_at(&mut read)), Reg("a")], 3, 16, None),
+        (3, 2, 6) => ("ldh", vec![Reg("a"), Ind("c")], 1, 8, None),
+        (3, 2, _) => ("ld", vec![Reg("a"), Addr(u16_at(&mut read))], 3, 16, None),
+        (3, 3, 0) => ("jp", vec![Target(u16_at(&mut read))], 3, 16, None),
+        (3, 3, 1) => return decode_cb(u8_at(&mut read)),
+        (3, 3, 6) => ("di", vec![], 1, 4, None),
+        (3, 3, 7) => ("ei", vec![], 1, 4, None),
+        (3, 4, 0..=3) => ("call", vec![Reg(COND[y as usize]), Target(u16_at(&mut read))], 3, 12, Some(24)),
+        (3, 5, _) if q == 0 => ("push", vec![Reg(R16_STK[p])], 1, 16, None),
+        (3, 5, 1) => ("call", vec![Target(u16_at(&mut read))], 3, 24, None),
+        (3, 6, _) => alu(y, U8(u8_at(&mut read)), 2, 8),
+        (3, 7, _) => ("rst", vec![Target(y as u16 * 8)], 1, 16, None),
+
+        _ => ("db", vec![U8(op)], 1, 0, None),
+    };
+
+    Instruction { mnemonic, operands, len, cycles, cycles_taken }
+}
+
+type Parts = (&'static str, Vec<Operand>, u8, u8, Option<u8>);
+
+fn alu(y: u8, src: Operand, len: u8, cycles: u8) -> Parts {
+    let operands = match y {
+        // only these spell out the a
+        0 | 1 | 3 => vec![Operand::Reg("a"), src],
+        _ => vec![src],
+    };
+
+    (ALU[y as usize], operands, len, cycles, None)
+}
+
+fn decode_cb(op: u8) -> Instruction {
+    let x = op >> 6;
+    let y = (op >> 3) & 7;
+    let z = op & 7;
+
+    let (mnemonic, operands) = match x {
+        0 => (ROT[y as usize], vec![r8(z)]),
+        1 => ("bit", vec![Operand::Bit(y), r8(z)]),
+        2 => ("res", vec![Operand::Bit(y), r8(z)]),
+        _ => ("set", vec![Operand::Bit(y), r8(z)]),
+    };
+
+    // bit only reads [hl], the rest write it back too
+    let cycles = match (x, z) {
+        (_, 0..=5 | 7) => 8,
+        (1, _) => 12,
+        _ => 16,
+    };
+
+    Instruction { mnemonic, operands, len: 2, cycles, cycles_taken: None }
+}
+
+impl Instruction {
+    /// Formats with labels in place of addresses that have one. `bank` is the rom bank the
+    /// instruction is in, which is where addresses in 0x4000..=0x7fff are looked up.
+    pub fn format(&self, symbols: Option<&Symbols>, bank: usize) -> String {
+        let label = |a: u16| symbols.and_then(|s| s.resolve(a, bank));
+
+        let operands = self.operands.iter().map(|o| match *o {
+            Operand::Reg(r) => r.to_string(),
+            Operand::Ind(r) => format!("[{r}]"),
+            Operand::U8(v) => format!("${v:02x}"),
+            Operand::U16(v) => label(v).map_or_else(|| format!("${v:04x}"), str::to_string),
+            Operand::Addr(a) => label(a).map_or_else(|| format!("[${a:04x}]"), |l| format!("[{l}]")),
+            Operand::Target(a) => label(a).map_or_else(|| format!("${a:04x}"), str::to_string),
+            Operand::I8(e) if e < 0 => format!("-${:02x}", e.unsigned_abs()),
+            Operand::I8(e) => format!("${e:02x}"),
+            Operand::SpOffset(e) if e < 0 => format!("sp - ${:02x}", e.unsigned_abs()),
+            Operand::SpOffset(e) => format!("sp + ${e:02x}"),
+            Operand::Bit(b) => b.to_string(),
+        }).collect::<Vec<_>>();
+
+        if operands.is_empty() {
+            self.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.mnemonic, operands.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(None, 1))
+    }
+}
+
+/// Labels from a RGBDS or no$gmb symbol file, both of which have lines like `01:4000 Label`
+/// and comments after a `;`.
+#[derive(Debug, Default, Clone)]
+pub struct Symbols {
+    labels: BTreeMap<(u16, usize), String>,
+}
+
+impl Symbols {
+    /// Skips lines that are not a label, like no$gmb section headers.
+    pub fn parse(text: &str) -> Self {
+        let labels = text.lines().filter_map(|l| {
+            let l = l.split(';').next()?.trim();
+            let (at, name) = l.split_once(char::is_whitespace)?;
+            let (bank, addr) = at.split_once(':')?;
+
+            let bank = usize::from_str_radix(bank, 16).ok()?;
+            let addr = u16::from_str_radix(addr, 16).ok()?;
+            Some(((addr, bank), name.trim().to_string()))
+        }).collect();
+
+        Self { labels }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// The label at exactly this bank and address.
+    pub fn get(&self, bank: usize, addr: u16) -> Option<&str> {
+        self.labels.get(&(addr, bank)).map(String::as_str)
+    }
+
+    /// The label for an address as code in `bank` sees it. Outside of the switchable rom bank
+    /// this takes the label in any bank, since which ram bank is mapped isn't known.
+    pub fn resolve(&self, addr: u16, bank: usize) -> Option<&str> {
+        match addr {
+            0x0000..=0x3fff => self.get(0, addr),
+            0x4000..=0x7fff => self.get(bank, addr),
+            _ => self.labels.range((addr, 0)..=(addr, usize::MAX)).next().map(|(_, l)| l.as_str()),
+        }
+    }
+}
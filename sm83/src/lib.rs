@@ -2,5 +2,8 @@
 
 pub mod bus;
 pub mod cpu;
+pub mod debug;
+pub mod disasm;
+pub mod trace;
 
 pub use cpu::Sm83;
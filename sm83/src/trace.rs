@@ -0,0 +1,102 @@
+//! Logs every instruction the cpu runs in the format of
+//! [gameboy-doctor](https://github.com/robert/gameboy-doctor), so a log can be diffed against
+//! one from another emulator.
+
+use std::{collections::VecDeque, fmt, io::Write, ops::RangeInclusive};
+
+use crate::cpu::State;
+
+/// The state before an instruction runs, with the 4 bytes at its address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Line {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub mem: [u8; 4],
+}
+
+impl Line {
+    /// `s.pc` is one past the fetched opcode, like the cpu keeps it.
+    pub fn new(s: &State, mem: [u8; 4]) -> Self {
+        Self {
+            a: s.a, f: s.f, b: s.b, c: s.c,
+            d: s.d, e: s.e, h: s.h, l: s.l,
+            sp: s.sp,
+            pc: s.pc.wrapping_sub(1),
+            mem,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+            SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l,
+            self.sp, self.pc, self.mem[0], self.mem[1], self.mem[2], self.mem[3],
+        )
+    }
+}
+
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    /// Only traces instructions at these addresses.
+    pub pc: Option<RangeInclusive<u16>>,
+    /// Only traces instructions in this rom bank, which leaves out everything outside of rom.
+    pub bank: Option<usize>,
+    /// Holds the last lines instead of writing them, up to its capacity.
+    ring: Option<(VecDeque<Line>, usize)>,
+}
+
+impl Tracer {
+    /// Writes every line as it is traced. `out` is written a line at a time, so it should be
+    /// buffered.
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self { out: Box::new(out), pc: None, bank: None, ring: None }
+    }
+
+    /// Keeps only the last `len` lines, which are written by [`Self::dump`]. That happens on
+    /// its own when the cpu locks up on an illegal opcode or the tracer is dropped by a panic.
+    pub fn ring(out: impl Write + Send + 'static, len: usize) -> Self {
+        Self { out: Box::new(out), pc: None, bank: None, ring: Some((VecDeque::with_capacity(len), len)) }
+    }
+
+    pub(crate) fn wants(&self, pc: u16, bank: Option<usize>) -> bool {
+        self.pc.as_ref().is_none_or(|r| r.contains(&pc))
+            && self.bank.is_none_or(|b| pc < 0x8000 && bank == Some(b))
+    }
+
+    pub(crate) fn log(&mut self, line: Line) {
+        match &mut self.ring {
+            Some((lines, len)) => {
+                if lines.len() == *len { lines.pop_front(); }
+                if *len != 0 { lines.push_back(line); }
+            },
+            // a trace that can't be written isn't worth stopping the emulator over
+            None => { let _ = writeln!(self.out, "{line}"); },
+        }
+    }
+
+    /// Writes out what the ring holds and empties it, does nothing without one.
+    pub fn dump(&mut self) {
+        let Some((lines, _)) = &mut self.ring else { return };
+
+        for line in lines.drain(..) { let _ = writeln!(self.out, "{line}"); }
+        let _ = self.out.flush();
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if std::thread::panicking() { self.dump(); }
+        let _ = self.out.flush();
+    }
+}
@@ -0,0 +1,76 @@
+//! Decoding and formatting instructions, with and without a symbol file.
+
+use sm83::disasm::{decode, Instruction, Symbols};
+
+const SYMBOLS: &str = "\
+; made by rgblink
+00:0150 Main
+01:4000 Bank1Func
+02:4000 Bank2Func
+00:c000 wCount ; any ram bank
+";
+
+/// Decodes `bytes` as if they were at `pc`, everything after them reads as 0xff.
+fn at(pc: u16, bytes: &[u8]) -> Instruction {
+    decode(pc, |a| bytes.get(a.wrapping_sub(pc) as usize).copied().unwrap_or(0xff))
+}
+
+fn plain(bytes: &[u8]) -> String {
+    at(0x0100, bytes).to_string()
+}
+
+#[test]
+fn base() {
+    assert_eq!(plain(&[0x00]), "nop");
+    assert_eq!(plain(&[0x3e, 0x42]), "ld a, $42");
+    assert_eq!(plain(&[0x21, 0x34, 0x12]), "ld hl, $1234");
+    assert_eq!(plain(&[0x22]), "ld [hl+], a");
+    assert_eq!(plain(&[0xea, 0x00, 0xc0]), "ld [$c000], a");
+    assert_eq!(plain(&[0xe8, 0xfe]), "add sp, -$02");
+    assert_eq!(plain(&[0xf8, 0x05]), "ld hl, sp + $05");
+    assert_eq!(plain(&[0xc7 | 3 << 3]), "rst $0018");
+}
+
+#[test]
+fn branches() {
+    // jr is relative to the end of the instruction
+    let jr = at(0x0156, &[0x18, 0xfa]);
+    assert_eq!(jr.to_string(), "jr $0152");
+    assert_eq!((jr.len, jr.cycles, jr.cycles_taken), (2, 12, None));
+
+    let call = at(0x0100, &[0xc4, 0x50, 0x01]);
+    assert_eq!(call.to_string(), "call nz, $0150");
+    assert_eq!((call.len, call.cycles, call.cycles_taken), (3, 12, Some(24)));
+}
+
+#[test]
+fn cb() {
+    assert_eq!(plain(&[0xcb, 0x37]), "swap a");
+    assert_eq!(plain(&[0xcb, 0x7c]), "bit 7, h");
+    assert_eq!(plain(&[0xcb, 0x86]), "res 0, [hl]");
+    assert_eq!(plain(&[0xcb, 0xff]), "set 7, a");
+
+    assert_eq!(at(0x0100, &[0xcb, 0x46]).cycles, 12);
+    assert_eq!(at(0x0100, &[0xcb, 0x86]).cycles, 16);
+}
+
+#[test]
+fn illegal() {
+    let i = at(0x0100, &[0xd3]);
+    assert_eq!((i.len, i.cycles), (1, 0));
+}
+
+#[test]
+fn symbols() {
+    let s = Symbols::parse(SYMBOLS);
+    assert_eq!(s.get(0, 0x0150), Some("Main"));
+
+    let call = at(0x0100, &[0xcd, 0x00, 0x40]);
+    assert_eq!(call.format(Some(&s), 1), "call Bank1Func");
+    assert_eq!(call.format(Some(&s), 2), "call Bank2Func");
+    assert_eq!(call.format(Some(&s), 3), "call $4000");
+
+    assert_eq!(at(0x0100, &[0xc3, 0x50, 0x01]).format(Some(&s), 1), "jp Main");
+    assert_eq!(at(0x0100, &[0xfa, 0x00, 0xc0]).format(Some(&s), 1), "ld a, [wCount]");
+    assert_eq!(at(0x0100, &[0x11, 0x00, 0xc0]).format(Some(&s), 1), "ld de, wCount");
+}